@@ -0,0 +1,20 @@
+-- Add migration script here
+
+CREATE TABLE IF NOT EXISTS alert_assets
+(
+    id                          INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_name                   TEXT                NOT NULL UNIQUE,
+    original_name               TEXT                NOT NULL,
+    kind                        TEXT                NOT NULL,
+    content_type                TEXT                NOT NULL,
+    size_bytes                  INTEGER             NOT NULL,
+    uploaded_at                 DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS alert_asset_assignments
+(
+    event_type                  TEXT                NOT NULL,
+    kind                        TEXT                NOT NULL,
+    asset_id                    INTEGER             NOT NULL,
+    PRIMARY KEY (event_type, kind)
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE follow_events ADD COLUMN follow_count INTEGER NOT NULL DEFAULT 1;
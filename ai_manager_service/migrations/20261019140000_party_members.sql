@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS party_members
+(
+    user_id       INTEGER PRIMARY KEY NOT NULL,
+    user_name     TEXT                NOT NULL,
+    class         TEXT                NOT NULL,
+    race          TEXT                NOT NULL,
+    level         INTEGER             NOT NULL DEFAULT 1,
+    xp            INTEGER             NOT NULL DEFAULT 0,
+    -- json array of item names
+    inventory     TEXT                NOT NULL DEFAULT '[]',
+    joined_at     DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at    DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
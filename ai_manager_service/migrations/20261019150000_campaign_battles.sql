@@ -0,0 +1,25 @@
+-- Add migration script here
+
+CREATE TABLE IF NOT EXISTS campaign_battles
+(
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    boss_name     TEXT                NOT NULL,
+    level         INTEGER             NOT NULL,
+    max_health    INTEGER             NOT NULL,
+    damage_dealt  INTEGER             NOT NULL DEFAULT 0,
+    -- victory or retreat, null while the fight is on
+    outcome       TEXT,
+    story         TEXT,
+    started_at    DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at      DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS campaign_hits
+(
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    battle_id     INTEGER             NOT NULL,
+    user_name     TEXT                NOT NULL,
+    damage        INTEGER             NOT NULL,
+    narration     TEXT,
+    hit_at        DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
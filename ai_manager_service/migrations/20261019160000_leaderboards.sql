@@ -0,0 +1,26 @@
+-- Add migration script here
+
+-- older gifts have no time, they only count for the all time boards
+ALTER TABLE gift_subs_events ADD COLUMN gifted_at DATETIME;
+
+CREATE TABLE IF NOT EXISTS cheer_events
+(
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id       INTEGER             NOT NULL,
+    user_name     TEXT                NOT NULL,
+    bits          INTEGER             NOT NULL,
+    cheered_at    DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- sub_events keeps one row per viewer, this keeps every sub and resub
+CREATE TABLE IF NOT EXISTS subscription_events
+(
+    id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id            INTEGER             NOT NULL,
+    user_name          TEXT                NOT NULL,
+    tier               TEXT                NOT NULL,
+    is_gift            BOOLEAN             NOT NULL,
+    is_resub           BOOLEAN             NOT NULL,
+    cumulative_months  INTEGER             NOT NULL,
+    subscribed_at      DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
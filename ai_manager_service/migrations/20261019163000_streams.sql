@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- every time the stream went live, the leaderboards count "this stream" from the latest one
+CREATE TABLE IF NOT EXISTS streams
+(
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at    DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+
+-- progress of the goals in goals.json, so a restart picks up where it left off
+CREATE TABLE IF NOT EXISTS goals
+(
+    kind          TEXT PRIMARY KEY    NOT NULL,
+    target        INTEGER             NOT NULL,
+    current       INTEGER             NOT NULL,
+    -- set when the target was reached, so it is only celebrated once
+    reached_at    DATETIME,
+    updated_at    DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
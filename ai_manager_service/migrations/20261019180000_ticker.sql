@@ -0,0 +1,10 @@
+-- Add migration script here
+
+-- what the overlay ticker shows in each slot, so it survives restarts
+CREATE TABLE IF NOT EXISTS ticker_events
+(
+    slot          TEXT PRIMARY KEY    NOT NULL,
+    -- the alert as json
+    message       TEXT                NOT NULL,
+    updated_at    DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+
+-- the epilogue written for the credits at the end of each stream
+CREATE TABLE IF NOT EXISTS campaign_recaps
+(
+    id                INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_started_at DATETIME           NOT NULL,
+    story             TEXT                NOT NULL,
+    created_at        DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
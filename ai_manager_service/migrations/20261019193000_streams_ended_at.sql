@@ -0,0 +1,4 @@
+-- Add migration script here
+
+-- set when the credits roll, a stream without it is still live
+ALTER TABLE streams ADD COLUMN ended_at DATETIME;
@@ -0,0 +1,11 @@
+-- Add migration script here
+
+-- what Helix told us about channels that raided, kept for a while so repeat raids don't ask again
+CREATE TABLE IF NOT EXISTS raider_channels
+(
+    broadcaster_user_id   TEXT PRIMARY KEY    NOT NULL,
+    game_name             TEXT,
+    title                 TEXT,
+    profile_image_url     TEXT,
+    fetched_at            DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
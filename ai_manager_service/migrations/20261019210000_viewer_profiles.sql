@@ -0,0 +1,12 @@
+-- Add migration script here
+
+-- the avatar, display name and broadcaster type Helix has for viewers, so every alert
+-- doesn't ask again
+CREATE TABLE IF NOT EXISTS viewer_profiles
+(
+    user_id               INTEGER PRIMARY KEY NOT NULL,
+    display_name          TEXT                NOT NULL,
+    profile_image_url     TEXT,
+    broadcaster_type      TEXT                NOT NULL DEFAULT '',
+    fetched_at            DATETIME            NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
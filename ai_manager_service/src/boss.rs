@@ -0,0 +1,526 @@
+//! Boss fights during hype trains.
+//!
+//! A hype train starting, or an admin starting one by hand, puts a boss up on the overlay.
+//! Bits, subs and gifts deal damage by the weights in the config, and hits of at least
+//! `big_hit` get a line of combat narration from the model. When the train ends the party
+//! either won or the boss retreated, and the story of the fight is saved to the campaign.
+use chatgpt::prelude::Conversation;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use messages::{
+    Boss, BossBattle, DisplayMessage, HypeTrainEvent, NewTwitchEventMessage, NullSubTier,
+    TwitchEvent,
+};
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::{hash::stable_hash, safety::SafetyFilter, sqlite, story::Story, AIManager};
+
+const NARRATION_PROMPT: &str = "You are D&DGPT, a dungeons and dragons dungeon master narrating a boss fight. Answer with one short sentence of combat narration, 15 words or less, no other text.";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BossConfig {
+    /// Picked from at random for each fight.
+    pub bosses: Vec<String>,
+    pub base_health: i64,
+    /// Extra health for each level of the hype train when the fight starts.
+    pub health_per_level: i64,
+    pub damage_per_bit: f64,
+    /// Damage of a tier 1 or prime sub or resub, tier 2 does twice that and tier 3 five times.
+    pub damage_per_sub: i64,
+    /// Damage of each sub in a gift, on top of the tier multiplier.
+    pub damage_per_gifted_sub: i64,
+    /// Hits doing at least this much damage get narrated.
+    pub big_hit: i64,
+}
+
+impl Default for BossConfig {
+    fn default() -> Self {
+        BossConfig {
+            bosses: vec![
+                "the Null Dragon".to_string(),
+                "the Lich of Lag".to_string(),
+                "the Buffering Beholder".to_string(),
+            ],
+            base_health: 10000,
+            health_per_level: 5000,
+            damage_per_bit: 1.0,
+            damage_per_sub: 500,
+            damage_per_gifted_sub: 500,
+            big_hit: 2500,
+        }
+    }
+}
+
+impl BossConfig {
+    pub fn load(path: &str) -> anyhow::Result<BossConfig> {
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    /// The damage the event deals, `None` for events that don't fight.
+    pub fn damage_for(&self, event: &TwitchEvent) -> Option<i64> {
+        let damage = match event {
+            TwitchEvent::ChannelCheer(cheer) => (cheer.bits as f64 * self.damage_per_bit) as i64,
+            // The gift already dealt the damage of the subs it gave
+            TwitchEvent::ChannelSubscribe(sub) if sub.is_gift => return None,
+            TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+                self.damage_per_sub * tier_multiplier(&sub.tier)
+            }
+            TwitchEvent::ChannelSubGift(gift) => {
+                self.damage_per_gifted_sub * tier_multiplier(&gift.tier) * gift.total
+            }
+            _ => return None,
+        };
+        Some(damage).filter(|damage| *damage > 0)
+    }
+}
+
+fn no_battle_row() -> BattleRow {
+    futures::future::ready(None).boxed().shared()
+}
+
+fn tier_multiplier(tier: &NullSubTier) -> i64 {
+    match tier {
+        NullSubTier::Tier2(_) => 2,
+        NullSubTier::Tier3(_) => 5,
+        _ => 1,
+    }
+}
+
+/// What an event did to the fight. Worked out as soon as the event comes in, so the fight
+/// plays out in the order things happened while the stories are still made several at once.
+#[derive(Debug, Clone)]
+pub(crate) enum BossStep {
+    Started(Boss),
+    Hit {
+        boss: Boss,
+        damage: i64,
+        attacker: String,
+    },
+    Ended(Boss),
+}
+
+/// The fight's row in the campaign_battles table, `None` when it could not be saved. Hits and
+/// the end of the fight wait for it before they are saved along with it.
+pub(crate) type BattleRow = Shared<BoxFuture<'static, Option<i64>>>;
+
+/// A step along with the row it is saved to.
+pub(crate) struct BossTurn {
+    pub step: BossStep,
+    pub battle_row: BattleRow,
+}
+
+/// Applies the event to the boss fight: hype trains start and end it, bits, subs and gifts
+/// hit the boss while it is on. `None` for events that change nothing.
+pub(crate) fn step_fight(
+    config: &BossConfig,
+    battle: &BossBattle,
+    safety: &SafetyFilter,
+    msg: &NewTwitchEventMessage,
+) -> Option<BossStep> {
+    match &msg.event {
+        TwitchEvent::ChannelHypeTrainBegin(train) => {
+            let name = if config.bosses.is_empty() {
+                "the Nameless Horror".to_string()
+            } else {
+                let index = stable_hash(&msg.message_id) % config.bosses.len() as u64;
+                config.bosses[index as usize].clone()
+            };
+            let max_health = config.base_health + config.health_per_level * train.level.max(1);
+            let boss = Boss {
+                name,
+                level: train.level,
+                max_health,
+                health: max_health,
+                last_hit: None,
+            };
+            if !battle.start(boss.clone()) {
+                println!(
+                    "Hype train level {}, the boss fight is already on",
+                    train.level
+                );
+                return None;
+            }
+            println!(
+                "Boss fight against {} with {} health",
+                boss.name, max_health
+            );
+            Some(BossStep::Started(boss))
+        }
+        TwitchEvent::ChannelHypeTrainEnd(_) => battle.end().map(BossStep::Ended),
+        event => {
+            let damage = config.damage_for(event)?;
+            let boss = battle.hit(damage)?;
+            let attacker = safety
+                .scrub(event)
+                .user_name()
+                .unwrap_or("An anonymous hero")
+                .to_string();
+            println!(
+                "{} hit {} for {}, {} health left",
+                attacker, boss.name, damage, boss.health
+            );
+            Some(BossStep::Hit {
+                boss,
+                damage,
+                attacker,
+            })
+        }
+    }
+}
+
+impl AIManager {
+    /// Applies the event to the boss fight right away, see [`step_fight`]. A new fight starts
+    /// saving its row to the campaign in the background.
+    pub(crate) fn fight_boss(&self, msg: &NewTwitchEventMessage) -> Option<BossTurn> {
+        let step = step_fight(&self.boss_config, &self.boss_battle, &self.safety, msg)?;
+        let mut current_row = self.battle_row.lock().unwrap();
+        let battle_row = match &step {
+            BossStep::Started(boss) => {
+                let row = self.save_battle(boss.clone());
+                *current_row = Some(row.clone());
+                row
+            }
+            BossStep::Hit { .. } => current_row.clone().unwrap_or_else(no_battle_row),
+            BossStep::Ended(_) => current_row.take().unwrap_or_else(no_battle_row),
+        };
+        Some(BossTurn { step, battle_row })
+    }
+
+    fn save_battle(&self, boss: Boss) -> BattleRow {
+        let pool = self.sqlite_pool.clone();
+        let insert = tokio::spawn(async move {
+            let conn = pool.acquire().await?;
+            sqlite::write_new_campaign_battle(conn, &boss.name, boss.level, boss.max_health).await
+        });
+        async move {
+            match insert.await {
+                Ok(Ok(battle_id)) => Some(battle_id),
+                Ok(Err(e)) => {
+                    println!("Could not save the boss fight to the campaign: {}", e);
+                    None
+                }
+                Err(e) => {
+                    println!("Could not save the boss fight to the campaign: {}", e);
+                    None
+                }
+            }
+        }
+        .boxed()
+        .shared()
+    }
+
+    /// Narrates the hit if it was a big one and saves it to the campaign. The narration has
+    /// until the event's deadline, it shares the time with the story.
+    pub(crate) async fn record_hit(
+        &self,
+        boss: &Boss,
+        damage: i64,
+        attacker: &str,
+        battle_row: BattleRow,
+        deadline: Instant,
+    ) {
+        let narration = if damage >= self.boss_config.big_hit {
+            self.narrate_hit(attacker, damage, boss, deadline).await
+        } else {
+            None
+        };
+        if let Some(narration) = &narration {
+            self.boss_battle.set_last_hit(narration.clone());
+        }
+
+        let Some(battle_id) = battle_row.await else {
+            return;
+        };
+        let saved = match self.sqlite_pool.acquire().await {
+            Ok(conn) => {
+                sqlite::write_new_campaign_hit(
+                    conn,
+                    battle_id,
+                    attacker,
+                    damage,
+                    narration.as_deref(),
+                )
+                .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = saved {
+            println!("Could not save the hit to the campaign: {}", e);
+        }
+    }
+
+    /// One line about the hit, `None` when the model fails or the line gets flagged.
+    async fn narrate_hit(
+        &self,
+        attacker: &str,
+        damage: i64,
+        boss: &Boss,
+        deadline: Instant,
+    ) -> Option<String> {
+        let mut conversation: Conversation =
+            self.chat_gpt.new_conversation_directed(NARRATION_PROMPT);
+        let prompt = format!(
+            "{} hits {} for {} damage, leaving it with {} of {} health{}.",
+            attacker,
+            boss.name,
+            damage,
+            boss.health,
+            boss.max_health,
+            if boss.is_defeated() { ", defeated" } else { "" }
+        );
+        let reply = tokio::time::timeout_at(deadline, conversation.send_message(prompt))
+            .await
+            .ok()?
+            .map_err(|e| println!("Could not narrate the hit: {}", e))
+            .ok()?;
+        let story = self
+            .safety
+            .check_story(Story::from_text(&reply.message().content))
+            .map_err(|reason| println!("Hit narration flagged: {}", reason))
+            .ok()?;
+        Some(story.story)
+    }
+
+    /// The victory or retreat story of the fight that ended, saved to the campaign and shown
+    /// as an alert.
+    pub(crate) async fn end_boss_fight(
+        &self,
+        train: &HypeTrainEvent,
+        boss: Boss,
+        battle_row: BattleRow,
+    ) -> DisplayMessage {
+        let outcome = if boss.is_defeated() {
+            "victory"
+        } else {
+            "retreat"
+        };
+        let damage_dealt = boss.max_health - boss.health;
+        println!("Boss fight against {} ended in {}", boss.name, outcome);
+
+        let payload = TwitchEvent::ChannelHypeTrainEnd(train.clone());
+        let prompt = if boss.is_defeated() {
+            format!(
+                "tell me an epic story about how the Null party defeated {} after a level {} hype train.",
+                boss.name, train.level
+            )
+        } else {
+            format!(
+                "tell me an epic story about how {} retreated, wounded by {} of {} damage, after the Null party fought it through a level {} hype train.",
+                boss.name, damage_dealt, boss.max_health, train.level
+            )
+        };
+        let mut conversation = self
+            .chat_gpt
+            .new_conversation_directed(crate::story::STORY_PROMPT);
+        let story = match tokio::time::timeout(
+            self.event_timeout,
+            self.ask_story(&mut conversation, prompt, &payload),
+        )
+        .await
+        {
+            Ok(Ok(story)) => story,
+            _ => {
+                println!("No story for the end of the boss fight, showing the neutral one");
+                crate::safety::neutral_story(&payload)
+            }
+        };
+
+        if let Some(battle_id) = battle_row.await {
+            let saved = match self.sqlite_pool.acquire().await {
+                Ok(conn) => {
+                    sqlite::end_campaign_battle(
+                        conn,
+                        battle_id,
+                        damage_dealt,
+                        outcome,
+                        &story.story,
+                    )
+                    .await
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = saved {
+                println!("Could not save the end of the boss fight: {}", e);
+            }
+        }
+
+        let display_time = story.story.split(" ").count() * 500;
+        self.display_message(story, display_time, payload).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{ChannelGiftMessage, CheerEvent, FollowEvent, SubscribeEvent};
+    use serde_json::json;
+
+    use super::*;
+
+    fn cheer(bits: i64) -> TwitchEvent {
+        TwitchEvent::ChannelCheer(CheerEvent {
+            user_name: "sam".to_string(),
+            user_id: 7,
+            bits,
+            message: String::new(),
+            profile: None,
+        })
+    }
+
+    fn sub(tier: NullSubTier) -> TwitchEvent {
+        TwitchEvent::ChannelSubscribe(SubscribeEvent {
+            broadcaster_user_id: 2,
+            broadcaster_user_name: "Null".to_string(),
+            user_name: "sam".to_string(),
+            user_id: 7,
+            is_gift: false,
+            tier,
+            cumulative_months: 1,
+            duration_months: 1,
+            message: String::new(),
+            streak_months: None,
+            profile: None,
+        })
+    }
+
+    fn gift(total: i64, tier: NullSubTier) -> TwitchEvent {
+        TwitchEvent::ChannelSubGift(ChannelGiftMessage {
+            broadcaster_user_id: "2".to_string(),
+            broadcaster_user_login: "null".to_string(),
+            broadcaster_user_name: "Null".to_string(),
+            cumulative_total: None,
+            is_anonymous: true,
+            tier,
+            total,
+            user_id: None,
+            user_login: None,
+            user_name: None,
+            recipients: vec![],
+        })
+    }
+
+    #[test]
+    fn damage_for_weighs_bits_subs_and_gifts() {
+        let config = BossConfig::default();
+        assert_eq!(config.damage_for(&cheer(300)), Some(300));
+        assert_eq!(
+            config.damage_for(&sub(NullSubTier::Prime("Prime".to_string()))),
+            Some(500)
+        );
+        assert_eq!(
+            config.damage_for(&sub(NullSubTier::Tier3("3000".to_string()))),
+            Some(2500)
+        );
+        assert_eq!(
+            config.damage_for(&gift(5, NullSubTier::Tier2("2000".to_string()))),
+            Some(5000)
+        );
+    }
+
+    #[test]
+    fn damage_for_skips_events_that_do_no_damage() {
+        let follow = TwitchEvent::ChannelFollow(FollowEvent {
+            user_name: "sam".to_string(),
+            user_id: 7,
+            broadcaster_user_id: 2,
+            profile: None,
+        });
+        let config: BossConfig = serde_json::from_value(json!({ "damage_per_bit": 0.5 })).unwrap();
+        assert_eq!(config.damage_for(&follow), None);
+        assert_eq!(config.damage_for(&cheer(1)), None);
+        assert_eq!(config.damage_for(&cheer(3)), Some(1));
+        // Fields left out of the config keep their defaults
+        assert_eq!(config.damage_per_sub, 500);
+    }
+
+    fn message(message_id: &str, event: TwitchEvent) -> NewTwitchEventMessage {
+        NewTwitchEventMessage {
+            event,
+            message_id: message_id.to_string(),
+            message_at: "2024-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    fn train(level: i64) -> HypeTrainEvent {
+        HypeTrainEvent {
+            level,
+            total: 0,
+            goal: None,
+        }
+    }
+
+    #[test]
+    fn damage_for_leaves_gifted_subs_to_their_gift() {
+        let TwitchEvent::ChannelSubscribe(mut gifted) = sub(NullSubTier::Tier1("1000".to_string()))
+        else {
+            unreachable!()
+        };
+        gifted.is_gift = true;
+        assert_eq!(
+            BossConfig::default().damage_for(&TwitchEvent::ChannelSubscribe(gifted)),
+            None
+        );
+    }
+
+    #[test]
+    fn step_fight_plays_the_fight_out_in_arrival_order() {
+        let config = BossConfig::default();
+        let battle = BossBattle::default();
+        let safety = SafetyFilter::default();
+        let step = |id: &str, event: TwitchEvent| {
+            step_fight(&config, &battle, &safety, &message(id, event))
+        };
+
+        assert!(step("0", cheer(100)).is_none(), "no fight yet");
+        let Some(BossStep::Started(boss)) = step("1", TwitchEvent::ChannelHypeTrainBegin(train(2)))
+        else {
+            panic!("the train starts the fight");
+        };
+        assert_eq!(boss.max_health, 20000);
+        assert!(step("2", TwitchEvent::ChannelHypeTrainBegin(train(3))).is_none());
+
+        let Some(BossStep::Hit {
+            boss,
+            damage,
+            attacker,
+        }) = step("3", cheer(300))
+        else {
+            panic!("cheers hit the boss");
+        };
+        assert_eq!(
+            (damage, boss.health, attacker.as_str()),
+            (300, 19700, "sam")
+        );
+        let Some(BossStep::Hit { boss, .. }) =
+            step("4", gift(2, NullSubTier::Tier1("1000".to_string())))
+        else {
+            panic!("gifts hit the boss");
+        };
+        assert_eq!(boss.health, 18700);
+
+        let Some(BossStep::Ended(boss)) = step("5", TwitchEvent::ChannelHypeTrainEnd(train(3)))
+        else {
+            panic!("the train ends the fight");
+        };
+        assert_eq!(boss.health, 18700);
+        assert!(battle.get().is_none());
+        assert!(step("6", cheer(300)).is_none(), "no fight after the end");
+        assert!(step("7", TwitchEvent::ChannelHypeTrainEnd(train(3))).is_none());
+    }
+
+    #[test]
+    fn step_fight_picks_the_same_boss_for_the_same_train() {
+        let config = BossConfig::default();
+        let name_for = |id: &str| {
+            let battle = BossBattle::default();
+            let begin = message(id, TwitchEvent::ChannelHypeTrainBegin(train(1)));
+            match step_fight(&config, &battle, &SafetyFilter::default(), &begin) {
+                Some(BossStep::Started(boss)) => boss.name,
+                other => panic!("no fight started: {:?}", other),
+            }
+        };
+        assert_eq!(name_for("train"), name_for("train"));
+        assert!(config.bosses.contains(&name_for("train")));
+    }
+}
@@ -0,0 +1,307 @@
+//! The credits rolled at the end of the stream.
+//!
+//! When the stream goes offline, or the credits are started from the admin page, everyone
+//! who followed, subscribed, gifted, cheered or raided this session is read back from the
+//! event tables and listed under a title for what they did. The model writes a recap of the
+//! session's campaign for the epilogue, saved along with the other campaign records.
+//!
+//! A session starts when the stream goes live and ends when the credits roll. Without a live
+//! stream, like before the first stream.online, it starts when the app did or the credits
+//! last rolled.
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use messages::{CreditsRoll, CreditsSection, StreamOfflineEvent, TwitchEvent};
+use sqlx::SqlitePool;
+
+use crate::{safety, sqlite, AIManager};
+
+/// The roles in the order they roll, with the title each is listed under.
+pub const ROLES: [(&str, &str); 5] = [
+    ("raider", "Allied Warbands"),
+    ("gifter", "Generous Patrons"),
+    ("subscriber", "Knights of the Null Order"),
+    ("cheerer", "Bards of the Bits"),
+    ("follower", "Adventurers Who Joined the Party"),
+];
+
+/// The time in unix seconds.
+pub fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// The session the credits are rolled for.
+#[derive(Debug)]
+pub struct Session {
+    /// Unix seconds, the start when no stream is live.
+    fallback: AtomicI64,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            fallback: AtomicI64::new(now_secs()),
+        }
+    }
+}
+
+impl Session {
+    /// Unix seconds the session started, when the live stream went live if there is one.
+    pub async fn started_at(&self, pool: &SqlitePool) -> i64 {
+        let live = match pool.acquire().await {
+            Ok(conn) => sqlite::get_live_stream_started_at(conn).await,
+            Err(e) => Err(e.into()),
+        };
+        let live = live.unwrap_or_else(|e| {
+            println!("Could not load the live stream: {}", e);
+            None
+        });
+        live.unwrap_or_else(|| self.fallback.load(Ordering::SeqCst))
+    }
+
+    /// Ends the session along with the live stream, the next one starts now.
+    pub async fn end(&self, pool: &SqlitePool) {
+        self.fallback.store(now_secs(), Ordering::SeqCst);
+        let ended = match pool.acquire().await {
+            Ok(conn) => sqlite::end_live_streams(conn).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = ended {
+            println!("Could not end the live stream: {}", e);
+        }
+    }
+}
+
+/// Groups the supporters by role, each name listed once per role.
+pub fn sections_for(supporters: Vec<(String, String)>) -> Vec<CreditsSection> {
+    ROLES
+        .iter()
+        .filter_map(|(role, title)| {
+            let mut names: Vec<String> = vec![];
+            for (_, name) in supporters.iter().filter(|(r, _)| r == role) {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            (!names.is_empty()).then(|| CreditsSection {
+                title: title.to_string(),
+                names,
+            })
+        })
+        .collect()
+}
+
+impl AIManager {
+    /// Puts the credits together and starts rolling them, which ends the session.
+    pub(crate) async fn roll_credits(&self, event: &StreamOfflineEvent) {
+        let since = self.session.started_at(&self.sqlite_pool).await;
+        let supporters = match self.sqlite_pool.acquire().await {
+            Ok(conn) => sqlite::get_session_supporters(conn, since).await,
+            Err(e) => Err(e.into()),
+        };
+        let supporters = supporters.unwrap_or_else(|e| {
+            println!("Could not load the supporters for the credits: {}", e);
+            vec![]
+        });
+        let sections = sections_for(supporters);
+        println!(
+            "Rolling the credits for {}, {} sections",
+            event.broadcaster_user_name,
+            sections.len()
+        );
+
+        let epilogue = self.campaign_recap(event, &sections, since).await;
+        self.session.end(&self.sqlite_pool).await;
+        self.credits.start(CreditsRoll { sections, epilogue });
+    }
+
+    /// The recap of the session's campaign, saved to the campaign records. The neutral story
+    /// when the model fails.
+    async fn campaign_recap(
+        &self,
+        event: &StreamOfflineEvent,
+        sections: &[CreditsSection],
+        since: i64,
+    ) -> String {
+        let battles = match self.sqlite_pool.acquire().await {
+            Ok(conn) => sqlite::get_session_battles(conn, since).await,
+            Err(e) => Err(e.into()),
+        };
+        let battles = battles.unwrap_or_else(|e| {
+            println!("Could not load the boss fights for the recap: {}", e);
+            vec![]
+        });
+
+        let mut prompt =
+            "tell me the epilogue of tonight's adventure of the Null party, as they make camp."
+                .to_string();
+        for section in sections {
+            prompt.push_str(&format!(
+                " {} {} joined them.",
+                section.names.len(),
+                section.title
+            ));
+        }
+        for (boss, outcome) in &battles {
+            prompt.push_str(&format!(" They fought {}, a {}.", boss, outcome));
+        }
+
+        let payload = TwitchEvent::StreamOffline(event.clone());
+        let mut conversation = self
+            .chat_gpt
+            .new_conversation_directed(crate::story::STORY_PROMPT);
+        let story = match tokio::time::timeout(
+            self.event_timeout,
+            self.ask_story(&mut conversation, prompt, &payload),
+        )
+        .await
+        {
+            Ok(Ok(story)) => story,
+            _ => {
+                println!("No campaign recap, ending the credits on the neutral one");
+                safety::neutral_story(&payload)
+            }
+        };
+
+        let saved = match self.sqlite_pool.acquire().await {
+            Ok(conn) => sqlite::write_new_campaign_recap(conn, since, &story.story).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = saved {
+            println!("Could not save the campaign recap: {}", e);
+        }
+        story.story
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{ANONYMOUS_CHEERER, ANONYMOUS_VIEWER_ID};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    async fn cheer(pool: &SqlitePool, user_id: i64, user_name: &str, at: &str) {
+        sqlx::query(
+            "INSERT INTO cheer_events ( user_id, user_name, bits, cheered_at ) VALUES ( ?, ?, 100, ? )",
+        )
+        .bind(user_id)
+        .bind(user_name)
+        .bind(at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn supporters(pool: &SqlitePool, session: &Session) -> Vec<(String, String)> {
+        let since = session.started_at(pool).await;
+        sqlite::get_session_supporters(pool.acquire().await.unwrap(), since)
+            .await
+            .unwrap()
+    }
+
+    fn supporter(role: &str, name: &str) -> (String, String) {
+        (role.to_string(), name.to_string())
+    }
+
+    fn titles_and_names(sections: Vec<CreditsSection>) -> Vec<(String, Vec<String>)> {
+        sections
+            .into_iter()
+            .map(|section| (section.title, section.names))
+            .collect()
+    }
+
+    #[test]
+    fn sections_for_lists_the_roles_in_order_once_each() {
+        let sections = sections_for(vec![
+            supporter("follower", "ann"),
+            supporter("cheerer", "bob"),
+            supporter("follower", "cat"),
+            supporter("raider", "dan"),
+            supporter("cheerer", "bob"),
+            supporter("subscriber", "ann"),
+        ]);
+        assert_eq!(
+            titles_and_names(sections),
+            vec![
+                ("Allied Warbands".to_string(), vec!["dan".to_string()]),
+                (
+                    "Knights of the Null Order".to_string(),
+                    vec!["ann".to_string()]
+                ),
+                ("Bards of the Bits".to_string(), vec!["bob".to_string()]),
+                (
+                    "Adventurers Who Joined the Party".to_string(),
+                    vec!["ann".to_string(), "cat".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn sections_for_skips_unknown_roles_and_empty_sessions() {
+        assert!(sections_for(vec![]).is_empty());
+        assert!(sections_for(vec![supporter("lurker", "eve")]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_session_starts_at_the_live_stream_and_the_next_one_starts_empty() {
+        let pool = pool().await;
+        let session = Session::default();
+        sqlx::query("INSERT INTO streams ( started_at ) VALUES ( '2024-01-01 00:00:00' )")
+            .execute(&pool)
+            .await
+            .unwrap();
+        cheer(&pool, 1, "early", "2023-12-31 23:00:00").await;
+        cheer(&pool, 2, "bob", "2024-01-01 00:10:00").await;
+        cheer(
+            &pool,
+            ANONYMOUS_VIEWER_ID,
+            ANONYMOUS_CHEERER,
+            "2024-01-01 00:20:00",
+        )
+        .await;
+
+        assert_eq!(session.started_at(&pool).await, 1704067200);
+        assert_eq!(
+            supporters(&pool, &session).await,
+            vec![supporter("cheerer", "bob")]
+        );
+
+        session.end(&pool).await;
+        assert!(session.started_at(&pool).await >= now_secs() - 1);
+        assert!(supporters(&pool, &session).await.is_empty());
+
+        sqlx::query("INSERT INTO streams DEFAULT VALUES")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO cheer_events ( user_id, user_name, bits ) VALUES ( 3, 'cat', 100 )",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            supporters(&pool, &session).await,
+            vec![supporter("cheerer", "cat")]
+        );
+    }
+}
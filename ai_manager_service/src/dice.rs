@@ -0,0 +1,120 @@
+//! The d20 rolled for every event.
+//!
+//! Rolls are seeded from the event's message id, so the same event always rolls the same
+//! number, on a retry or when it is replayed. Subs get a bonus by tier, cheers by bits and
+//! raids and gifts by their size.
+use messages::{DiceRoll, NewTwitchEventMessage, NullSubTier, TwitchEvent};
+
+use crate::hash::stable_hash;
+
+/// Highest bonus an event can get.
+pub const MAX_MODIFIER: i64 = 5;
+
+pub fn roll_for(msg: &NewTwitchEventMessage) -> DiceRoll {
+    let natural = (stable_hash(&msg.message_id) % DiceRoll::SIDES as u64) as i64 + 1;
+    DiceRoll {
+        natural,
+        modifier: modifier_for(&msg.event).min(MAX_MODIFIER),
+    }
+}
+
+fn modifier_for(event: &TwitchEvent) -> i64 {
+    match event {
+        TwitchEvent::ChannelFollow(_) | TwitchEvent::FollowBurst(_) => 0,
+        TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+            tier_modifier(&sub.tier)
+        }
+        TwitchEvent::ChannelSubGift(gift) => tier_modifier(&gift.tier) + gift.total / 10,
+        TwitchEvent::ChannelCheer(cheer) => cheer.bits / 500,
+        TwitchEvent::ChannelRaid(raid) => raid.viewers / 25,
+        TwitchEvent::ChannelHypeTrainBegin(train) | TwitchEvent::ChannelHypeTrainEnd(train) => {
+            train.level
+        }
+        TwitchEvent::StreamOnline(_) | TwitchEvent::StreamOffline(_) => 0,
+    }
+}
+
+fn tier_modifier(tier: &NullSubTier) -> i64 {
+    match tier {
+        NullSubTier::Tier1(_) | NullSubTier::Prime(_) => 1,
+        NullSubTier::Tier2(_) => 2,
+        NullSubTier::Tier3(_) => 3,
+        NullSubTier::Other(_) => 0,
+    }
+}
+
+/// The roll as it is given to the model along with the story prompt.
+pub fn roll_prompt(roll: &DiceRoll) -> String {
+    format!(
+        "They rolled a {} on a d20 ({} {:+}) against a difficulty of {}, a {}. The story must fit that outcome.",
+        roll.total(),
+        roll.natural,
+        roll.modifier,
+        DiceRoll::DIFFICULTY,
+        roll.outcome()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{CheerEvent, HypeTrainEvent};
+
+    use super::*;
+
+    fn message(message_id: &str, event: TwitchEvent) -> NewTwitchEventMessage {
+        NewTwitchEventMessage {
+            event,
+            message_id: message_id.to_string(),
+            message_at: "2024-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    fn cheer(bits: i64) -> TwitchEvent {
+        TwitchEvent::ChannelCheer(CheerEvent {
+            user_name: "sam".to_string(),
+            user_id: 7,
+            bits,
+            message: String::new(),
+            profile: None,
+        })
+    }
+
+    #[test]
+    fn roll_for_rolls_the_same_for_the_same_event() {
+        for i in 0..200 {
+            let id = format!("message-{}", i);
+            let roll = roll_for(&message(&id, cheer(0)));
+            assert!((1..=DiceRoll::SIDES).contains(&roll.natural));
+            assert_eq!(roll_for(&message(&id, cheer(0))).natural, roll.natural);
+        }
+    }
+
+    #[test]
+    fn roll_for_caps_the_modifier() {
+        assert_eq!(roll_for(&message("a", cheer(499))).modifier, 0);
+        assert_eq!(roll_for(&message("a", cheer(1000))).modifier, 2);
+        assert_eq!(
+            roll_for(&message("a", cheer(100_000))).modifier,
+            MAX_MODIFIER
+        );
+        let train = TwitchEvent::ChannelHypeTrainEnd(HypeTrainEvent {
+            level: 9,
+            total: 5000,
+            goal: None,
+        });
+        assert_eq!(roll_for(&message("a", train)).modifier, MAX_MODIFIER);
+    }
+
+    #[test]
+    fn roll_prompt_spells_out_the_outcome() {
+        let roll = DiceRoll {
+            natural: 8,
+            modifier: 3,
+        };
+        assert_eq!(
+            roll_prompt(&roll),
+            "They rolled a 11 on a d20 (8 +3) against a difficulty of 10, a success. The story \
+             must fit that outcome."
+        );
+    }
+}
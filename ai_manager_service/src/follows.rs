@@ -0,0 +1,182 @@
+//! Spots follow-bot waves.
+//!
+//! Once more than `threshold` follows come in within `window`, the follows after them are
+//! held back and go out together as one muted alert a window after the first of them.
+//! A wave that keeps going gets one alert per window. Follows before that point get their
+//! own stories as usual.
+use std::{collections::VecDeque, time::Duration};
+
+use messages::{FollowEvent, NewTwitchEventMessage, TwitchEvent};
+use tokio::time::Instant;
+
+pub struct FollowBurstDetector {
+    window: Duration,
+    threshold: usize,
+    recent: VecDeque<Instant>,
+    burst: Vec<FollowEvent>,
+    deadline: Option<Instant>,
+}
+
+impl FollowBurstDetector {
+    pub fn new(window: Duration, threshold: usize) -> FollowBurstDetector {
+        FollowBurstDetector {
+            window,
+            threshold,
+            recent: VecDeque::new(),
+            burst: vec![],
+            deadline: None,
+        }
+    }
+
+    /// Follows held back in the current burst.
+    pub fn len(&self) -> usize {
+        self.burst.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.burst.is_empty()
+    }
+
+    /// Passes everything but follows that are part of a burst straight back.
+    pub fn push(&mut self, message: NewTwitchEventMessage) -> Option<NewTwitchEventMessage> {
+        let TwitchEvent::ChannelFollow(follow) = &message.event else {
+            return Some(message);
+        };
+
+        let now = Instant::now();
+        self.recent.push_back(now);
+        while self
+            .recent
+            .front()
+            .is_some_and(|followed| now.duration_since(*followed) > self.window)
+        {
+            self.recent.pop_front();
+        }
+
+        if self.recent.len() <= self.threshold && self.burst.is_empty() {
+            return Some(message);
+        }
+
+        if self.burst.is_empty() {
+            println!(
+                "Follow burst, {} follows in {:?}",
+                self.recent.len(),
+                self.window
+            );
+            // Set once, so a steady wave can't keep pushing its alert back
+            self.deadline = Some(now + self.window);
+        }
+        self.burst.push(follow.clone());
+        None
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// The follows of a burst that has been held back for a whole window.
+    pub fn expired(&mut self, now: Instant) -> Option<Vec<FollowEvent>> {
+        if self.deadline.is_some_and(|deadline| deadline <= now) {
+            self.deadline = None;
+            return Some(std::mem::take(&mut self.burst));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::CheerEvent;
+
+    use super::*;
+
+    fn message(event: TwitchEvent) -> NewTwitchEventMessage {
+        NewTwitchEventMessage {
+            event,
+            message_id: "id".to_string(),
+            message_at: "now".to_string(),
+        }
+    }
+
+    fn follow(user_name: &str) -> NewTwitchEventMessage {
+        message(TwitchEvent::ChannelFollow(FollowEvent {
+            user_name: user_name.to_string(),
+            user_id: 1,
+            broadcaster_user_id: 2,
+            profile: None,
+        }))
+    }
+
+    #[test]
+    fn push_passes_follows_under_the_threshold() {
+        let mut follows = FollowBurstDetector::new(Duration::from_secs(10), 3);
+        for name in ["a", "b", "c"] {
+            assert!(follows.push(follow(name)).is_some());
+        }
+        assert!(follows.is_empty());
+        assert!(follows.next_deadline().is_none());
+    }
+
+    #[test]
+    fn push_holds_follows_once_the_threshold_is_passed() {
+        let mut follows = FollowBurstDetector::new(Duration::from_secs(10), 2);
+        assert!(follows.push(follow("a")).is_some());
+        assert!(follows.push(follow("b")).is_some());
+        assert!(follows.push(follow("bot1")).is_none());
+        assert!(follows.push(follow("bot2")).is_none());
+        assert_eq!(follows.len(), 2);
+    }
+
+    #[test]
+    fn push_lets_other_events_through_during_a_burst() {
+        let mut follows = FollowBurstDetector::new(Duration::from_secs(10), 0);
+        assert!(follows.push(follow("bot")).is_none());
+        let cheer = message(TwitchEvent::ChannelCheer(CheerEvent {
+            user_name: "fan".to_string(),
+            user_id: 3,
+            bits: 100,
+            message: String::new(),
+            profile: None,
+        }));
+        assert!(follows.push(cheer).is_some());
+    }
+
+    #[test]
+    fn expired_releases_the_burst_once_it_goes_quiet() {
+        let mut follows = FollowBurstDetector::new(Duration::from_secs(10), 0);
+        follows.push(follow("bot1"));
+        follows.push(follow("bot2"));
+        let deadline = follows.next_deadline().unwrap();
+
+        assert!(follows.expired(Instant::now()).is_none());
+        let burst = follows.expired(deadline).unwrap();
+        let names: Vec<&str> = burst.iter().map(|f| f.user_name.as_str()).collect();
+        assert_eq!(names, vec!["bot1", "bot2"]);
+        assert!(follows.is_empty());
+        assert!(follows.next_deadline().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_steady_wave_goes_out_once_a_window() {
+        let window = Duration::from_secs(10);
+        let mut follows = FollowBurstDetector::new(window, 0);
+        let start = Instant::now();
+        let mut bursts = vec![];
+
+        for i in 0..25 {
+            follows.push(follow(&format!("bot{}", i)));
+            tokio::time::advance(Duration::from_secs(1)).await;
+            if let Some(burst) = follows.expired(Instant::now()) {
+                bursts.push((start.elapsed(), burst.len()));
+            }
+        }
+
+        assert_eq!(
+            bursts,
+            vec![(window, 10), (window * 2, 10)],
+            "the rest are still held back"
+        );
+        assert_eq!(follows.len(), 5);
+        assert_eq!(follows.next_deadline(), Some(start + window * 3));
+    }
+}
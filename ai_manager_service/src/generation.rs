@@ -0,0 +1,208 @@
+//! How stories get generated: several at once, in the order the events came in, retried with
+//! backoff until the event runs out of time.
+use std::future::Future;
+
+use futures::{stream::FuturesOrdered, StreamExt};
+use tokio::time::Instant;
+
+use crate::{MAX_GENERATION_ATTEMPTS, RETRY_BACKOFF};
+
+/// Jobs that run up to `limit` at once and are handed back in the order they were pushed,
+/// so the alerts still play in the order the events came in.
+pub struct OrderedJobs<F: Future> {
+    running: FuturesOrdered<F>,
+    limit: usize,
+}
+
+impl<F: Future> OrderedJobs<F> {
+    pub fn new(limit: usize) -> Self {
+        OrderedJobs {
+            running: FuturesOrdered::new(),
+            limit: limit.max(1),
+        }
+    }
+
+    /// Whether another job can start without going over the limit.
+    pub fn has_room(&self) -> bool {
+        self.running.len() < self.limit
+    }
+
+    pub fn push(&mut self, job: F) {
+        self.running.push_back(job);
+    }
+
+    /// The output of the oldest job, once it is done. `None` when nothing is running.
+    pub async fn next(&mut self) -> Option<F::Output> {
+        self.running.next().await
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+}
+
+/// How trying to generate something went.
+#[derive(Debug)]
+pub enum Attempted<T> {
+    Done(T),
+    /// Every attempt failed, this is the last error.
+    Failed(anyhow::Error),
+    /// The deadline passed first.
+    TimedOut,
+}
+
+/// Tries up to `MAX_GENERATION_ATTEMPTS` times, waiting `RETRY_BACKOFF` before the first retry
+/// and twice as long before each one after. Gives up at the deadline, retries included.
+pub async fn with_retries<T, F, Fut>(what: &str, deadline: Instant, mut attempt: F) -> Attempted<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let attempts = async {
+        let mut backoff = RETRY_BACKOFF;
+        let mut tries = 1;
+        loop {
+            match attempt().await {
+                Ok(done) => return Attempted::Done(done),
+                Err(e) if tries < MAX_GENERATION_ATTEMPTS => {
+                    println!(
+                        "Attempt {} for {} failed, retrying in {:?}: {}",
+                        tries, what, backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    tries += 1;
+                }
+                Err(e) => return Attempted::Failed(e),
+            }
+        }
+    };
+    tokio::time::timeout_at(deadline, attempts)
+        .await
+        .unwrap_or(Attempted::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    async fn job(
+        id: usize,
+        delay: Duration,
+        running: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    ) -> usize {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        most.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(delay).await;
+        running.fetch_sub(1, Ordering::SeqCst);
+        id
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ordered_jobs_run_up_to_the_limit_and_finish_in_push_order() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let mut jobs = OrderedJobs::new(3);
+        let mut waiting = 0..10;
+        let mut done = vec![];
+
+        loop {
+            while jobs.has_room() {
+                let Some(id) = waiting.next() else { break };
+                // Later jobs finish first, they still come back after the earlier ones
+                let delay = Duration::from_millis(100 * (10 - id as u64));
+                jobs.push(job(id, delay, running.clone(), most.clone()));
+            }
+            match jobs.next().await {
+                Some(id) => done.push(id),
+                None => break,
+            }
+        }
+
+        assert_eq!(done, (0..10).collect::<Vec<_>>());
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+        assert!(jobs.is_empty());
+    }
+
+    #[test]
+    fn ordered_jobs_limit_is_at_least_one() {
+        let mut jobs = OrderedJobs::new(0);
+        assert!(jobs.has_room());
+        jobs.push(std::future::ready(()));
+        assert!(!jobs.has_room());
+        assert_eq!(jobs.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn with_retries_backs_off_until_an_attempt_works() {
+        let start = Instant::now();
+        let tries = Cell::new(0);
+        let result = with_retries("test", start + Duration::from_secs(30), || {
+            tries.set(tries.get() + 1);
+            let tries = tries.get();
+            async move {
+                match tries {
+                    3 => Ok(tries),
+                    _ => Err(anyhow!("model failed")),
+                }
+            }
+        })
+        .await;
+
+        assert!(matches!(result, Attempted::Done(3)));
+        assert_eq!(start.elapsed(), RETRY_BACKOFF + RETRY_BACKOFF * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn with_retries_gives_up_after_the_last_attempt() {
+        let tries = Cell::new(0);
+        let result: Attempted<()> =
+            with_retries("test", Instant::now() + Duration::from_secs(30), || {
+                tries.set(tries.get() + 1);
+                async { Err(anyhow!("model failed")) }
+            })
+            .await;
+
+        assert!(matches!(result, Attempted::Failed(e) if e.to_string() == "model failed"));
+        assert_eq!(tries.get(), MAX_GENERATION_ATTEMPTS);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn with_retries_times_out_at_the_deadline() {
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(5);
+        let result = with_retries("test", deadline, || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            anyhow::Ok(())
+        })
+        .await;
+
+        assert!(matches!(result, Attempted::TimedOut));
+        assert_eq!(Instant::now(), deadline);
+
+        // The deadline counts the retry waits too
+        let start = Instant::now();
+        let result: Attempted<()> = with_retries("test", start + RETRY_BACKOFF / 2, || async {
+            Err(anyhow!("model failed"))
+        })
+        .await;
+        assert!(matches!(result, Attempted::TimedOut));
+        assert_eq!(start.elapsed(), RETRY_BACKOFF / 2);
+    }
+}
@@ -0,0 +1,208 @@
+//! Folds the subscribe events of a gift bomb into the gift alert.
+//!
+//! Gifting 50 subs gets us one `channel.subscription.gift` and 50 `channel.subscribe` events
+//! with `is_gift` set, which don't say who gifted them. Gifted subs that show up within the
+//! window of a gift with the same tier are taken as its recipients, so the gift gets one alert
+//! listing them instead of 51 stories. Gifted subs with no gift waiting for them go out right
+//! away rather than holding every gifted sub up for the window.
+use std::{mem::discriminant, time::Duration};
+
+use messages::{ChannelGiftMessage, NewTwitchEventMessage, SubscribeEvent, TwitchEvent};
+use tokio::time::Instant;
+
+struct PendingGift {
+    message: NewTwitchEventMessage,
+    deadline: Instant,
+}
+
+pub struct GiftCoalescer {
+    window: Duration,
+    gifts: Vec<PendingGift>,
+}
+
+impl GiftCoalescer {
+    pub fn new(window: Duration) -> GiftCoalescer {
+        GiftCoalescer {
+            window,
+            gifts: vec![],
+        }
+    }
+
+    /// Gifts held back waiting for their recipients.
+    pub fn len(&self) -> usize {
+        self.gifts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes the next event, returning what can be turned into an alert right away.
+    /// That is the event itself when it has nothing to do with a pending gift, or a gift that
+    /// now has all its recipients.
+    pub fn push(&mut self, message: NewTwitchEventMessage) -> Option<NewTwitchEventMessage> {
+        match &message.event {
+            TwitchEvent::ChannelSubGift(gift) if !is_complete(gift) => {
+                self.gifts.push(PendingGift {
+                    message,
+                    deadline: Instant::now() + self.window,
+                });
+                None
+            }
+            TwitchEvent::ChannelSubscribe(sub) if sub.is_gift => {
+                let Some(index) = self
+                    .gifts
+                    .iter()
+                    .position(|pending| same_tier(gift(&pending.message), sub))
+                else {
+                    return Some(message);
+                };
+
+                add_recipient(&mut self.gifts[index].message, sub);
+                if is_complete(gift(&self.gifts[index].message)) {
+                    return Some(self.gifts.remove(index).message);
+                }
+                None
+            }
+            _ => Some(message),
+        }
+    }
+
+    /// When the next held gift runs out of time.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.gifts.iter().map(|pending| pending.deadline).min()
+    }
+
+    /// Lets go of the gifts whose window has passed, with the recipients they got.
+    pub fn expired(&mut self, now: Instant) -> Vec<NewTwitchEventMessage> {
+        let (done, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.gifts)
+            .into_iter()
+            .partition(|pending| pending.deadline <= now);
+        self.gifts = waiting;
+        done.into_iter().map(|pending| pending.message).collect()
+    }
+}
+
+fn gift(message: &NewTwitchEventMessage) -> &ChannelGiftMessage {
+    match &message.event {
+        TwitchEvent::ChannelSubGift(gift) => gift,
+        _ => unreachable!("only gift events are held as gifts"),
+    }
+}
+
+fn add_recipient(message: &mut NewTwitchEventMessage, sub: &SubscribeEvent) {
+    if let TwitchEvent::ChannelSubGift(gift) = &mut message.event {
+        gift.recipients.push(sub.user_name.clone());
+    }
+}
+
+fn is_complete(gift: &ChannelGiftMessage) -> bool {
+    gift.recipients.len() as i64 >= gift.total
+}
+
+fn same_tier(gift: &ChannelGiftMessage, sub: &SubscribeEvent) -> bool {
+    discriminant(&gift.tier) == discriminant(&sub.tier)
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::NullSubTier;
+
+    use super::*;
+
+    fn message(event: TwitchEvent) -> NewTwitchEventMessage {
+        NewTwitchEventMessage {
+            event,
+            message_id: "id".to_string(),
+            message_at: "now".to_string(),
+        }
+    }
+
+    fn gift_event(total: i64, tier: NullSubTier) -> NewTwitchEventMessage {
+        message(TwitchEvent::ChannelSubGift(ChannelGiftMessage {
+            broadcaster_user_id: "2".to_string(),
+            broadcaster_user_login: "null".to_string(),
+            broadcaster_user_name: "Null".to_string(),
+            cumulative_total: None,
+            is_anonymous: false,
+            tier,
+            total,
+            user_id: Some("1".to_string()),
+            user_login: Some("santa".to_string()),
+            user_name: Some("Santa".to_string()),
+            recipients: vec![],
+        }))
+    }
+
+    fn sub(user_name: &str, is_gift: bool, tier: NullSubTier) -> NewTwitchEventMessage {
+        message(TwitchEvent::ChannelSubscribe(SubscribeEvent {
+            broadcaster_user_id: 2,
+            broadcaster_user_name: "Null".to_string(),
+            user_name: user_name.to_string(),
+            user_id: 3,
+            is_gift,
+            tier,
+            cumulative_months: 1,
+            duration_months: 1,
+            message: String::new(),
+            streak_months: None,
+            profile: None,
+        }))
+    }
+
+    fn tier1() -> NullSubTier {
+        NullSubTier::Tier1("1000".to_string())
+    }
+
+    fn recipients(message: &NewTwitchEventMessage) -> Vec<String> {
+        super::gift(message).recipients.clone()
+    }
+
+    #[test]
+    fn push_folds_gifted_subs_into_their_gift() {
+        let mut gifts = GiftCoalescer::new(Duration::from_secs(5));
+        assert!(gifts.push(gift_event(2, tier1())).is_none());
+        assert!(gifts.push(sub("a", true, tier1())).is_none());
+        let done = gifts.push(sub("b", true, tier1())).unwrap();
+        assert_eq!(recipients(&done), vec!["a", "b"]);
+        assert!(gifts.is_empty());
+    }
+
+    #[test]
+    fn push_releases_gifted_subs_with_no_gift_waiting() {
+        let mut gifts = GiftCoalescer::new(Duration::from_secs(5));
+        assert!(gifts.push(sub("a", true, tier1())).is_some());
+        assert!(gifts.is_empty());
+    }
+
+    #[test]
+    fn push_only_folds_subs_of_the_same_tier() {
+        let mut gifts = GiftCoalescer::new(Duration::from_secs(5));
+        gifts.push(gift_event(1, tier1()));
+        let other_tier = sub("a", true, NullSubTier::Tier3("3000".to_string()));
+        assert!(gifts.push(other_tier).is_some());
+        assert_eq!(gifts.len(), 1);
+    }
+
+    #[test]
+    fn push_lets_other_events_through() {
+        let mut gifts = GiftCoalescer::new(Duration::from_secs(5));
+        gifts.push(gift_event(1, tier1()));
+        assert!(gifts.push(sub("a", false, tier1())).is_some());
+        assert_eq!(gifts.len(), 1);
+    }
+
+    #[test]
+    fn expired_releases_gifts_with_the_recipients_they_got() {
+        let mut gifts = GiftCoalescer::new(Duration::from_secs(5));
+        gifts.push(gift_event(3, tier1()));
+        gifts.push(sub("a", true, tier1()));
+        let deadline = gifts.next_deadline().unwrap();
+
+        assert!(gifts.expired(Instant::now()).is_empty());
+        let released = gifts.expired(deadline);
+        assert_eq!(released.len(), 1);
+        assert_eq!(recipients(&released[0]), vec!["a"]);
+        assert!(gifts.next_deadline().is_none());
+    }
+}
@@ -0,0 +1,359 @@
+//! Follower, sub point and bits goals, and the milestones on the way to them.
+//!
+//! The goals in the config start from the counts Twitch has at startup, or from where they
+//! were left in sqlite, and are counted up from the events. Reaching a goal, or a round number
+//! like the 1000th follower, gets a celebration story through the alert queue.
+use messages::{DisplayMessage, Goal, GoalKind, NullSubTier, TwitchEvent};
+use serde::Deserialize;
+
+use crate::{sqlite, story::Story, AIManager};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GoalsConfig {
+    pub goals: Vec<GoalConfig>,
+    pub milestones: Milestones,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GoalConfig {
+    pub kind: GoalKind,
+    /// Shown over the progress bar.
+    pub label: String,
+    pub target: i64,
+}
+
+/// Every this many of each is a milestone, 0 for none.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Milestones {
+    pub followers: i64,
+    pub sub_points: i64,
+    pub stream_bits: i64,
+}
+
+impl Milestones {
+    pub fn every(&self, kind: GoalKind) -> i64 {
+        match kind {
+            GoalKind::Followers => self.followers,
+            GoalKind::SubPoints => self.sub_points,
+            GoalKind::StreamBits => self.stream_bits,
+        }
+    }
+}
+
+impl Default for GoalsConfig {
+    fn default() -> Self {
+        GoalsConfig {
+            goals: vec![
+                GoalConfig {
+                    kind: GoalKind::Followers,
+                    label: "Follower Goal".to_string(),
+                    target: 1000,
+                },
+                GoalConfig {
+                    kind: GoalKind::SubPoints,
+                    label: "Sub Goal".to_string(),
+                    target: 100,
+                },
+                GoalConfig {
+                    kind: GoalKind::StreamBits,
+                    label: "Bits This Stream".to_string(),
+                    target: 5000,
+                },
+            ],
+            milestones: Milestones {
+                followers: 100,
+                sub_points: 50,
+                stream_bits: 1000,
+            },
+        }
+    }
+}
+
+impl GoalsConfig {
+    pub fn load(path: &str) -> anyhow::Result<GoalsConfig> {
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+}
+
+/// The goal the event counts towards and by how much, `None` for events that don't count.
+/// Gifted subs count through their gift, every sub in it, so the subscribe events of the
+/// recipients don't count again.
+pub fn progress_for(event: &TwitchEvent) -> Option<(GoalKind, i64)> {
+    match event {
+        TwitchEvent::ChannelFollow(_) => Some((GoalKind::Followers, 1)),
+        TwitchEvent::ChannelSubscribe(sub) if sub.is_gift => None,
+        TwitchEvent::ChannelSubscribe(sub) => Some((GoalKind::SubPoints, sub_points(&sub.tier))),
+        TwitchEvent::ChannelSubGift(gift) => {
+            Some((GoalKind::SubPoints, gift.total * sub_points(&gift.tier)))
+        }
+        TwitchEvent::ChannelCheer(cheer) => Some((GoalKind::StreamBits, cheer.bits)),
+        _ => None,
+    }
+}
+
+fn sub_points(tier: &NullSubTier) -> i64 {
+    match tier {
+        NullSubTier::Tier2(_) => 2,
+        NullSubTier::Tier3(_) => 6,
+        _ => 1,
+    }
+}
+
+/// The highest multiple of `every` passed going from `before` to `after`.
+fn milestone_between(before: i64, after: i64, every: i64) -> Option<i64> {
+    if every <= 0 {
+        return None;
+    }
+    let milestone = after / every * every;
+    (milestone > before && milestone > 0).then_some(milestone)
+}
+
+fn unit(kind: GoalKind) -> &'static str {
+    match kind {
+        GoalKind::Followers => "followers",
+        GoalKind::SubPoints => "sub points",
+        GoalKind::StreamBits => "bits this stream",
+    }
+}
+
+impl AIManager {
+    /// Starts the goals in the config from the counts Twitch gave us, or from where they were
+    /// left in sqlite. Goals already reached are not celebrated again.
+    pub async fn seed_goals(&self, followers: Option<i64>, sub_points: Option<i64>) {
+        let mut goals = vec![];
+        for config in &self.goals_config.goals {
+            let saved = match config.kind {
+                GoalKind::StreamBits => None,
+                kind => match self.sqlite_pool.acquire().await {
+                    Ok(conn) => sqlite::get_goal_current(conn, kind.as_str())
+                        .await
+                        .unwrap_or_else(|e| {
+                            println!("Could not load the {} goal: {}", kind.as_str(), e);
+                            None
+                        }),
+                    Err(e) => {
+                        println!("Could not load the {} goal: {}", kind.as_str(), e);
+                        None
+                    }
+                },
+            };
+            let twitch = match config.kind {
+                GoalKind::Followers => followers,
+                GoalKind::SubPoints => sub_points,
+                GoalKind::StreamBits => None,
+            };
+            let goal = Goal {
+                kind: config.kind,
+                label: config.label.clone(),
+                current: twitch.or(saved).unwrap_or_default(),
+                target: config.target,
+            };
+            println!(
+                "{}: {} of {} {}",
+                goal.label,
+                goal.current,
+                goal.target,
+                unit(goal.kind)
+            );
+            self.save_goal(&goal, goal.is_reached()).await;
+            goals.push(goal);
+        }
+        self.goals.set(goals);
+    }
+
+    /// Saves the goal's progress, marking it reached if it is. Returns false when it already
+    /// was marked, or saving failed.
+    async fn save_goal(&self, goal: &Goal, reached: bool) -> bool {
+        let saved = async {
+            let conn = self.sqlite_pool.acquire().await?;
+            sqlite::save_goal(conn, goal.kind.as_str(), goal.target, goal.current).await?;
+            if !reached {
+                return anyhow::Ok(false);
+            }
+            let conn = self.sqlite_pool.acquire().await?;
+            sqlite::mark_goal_reached(conn, goal.kind.as_str()).await
+        };
+        saved.await.unwrap_or_else(|e| {
+            println!("Could not save the {} goal: {}", goal.kind.as_str(), e);
+            false
+        })
+    }
+
+    /// Counts the event towards its goal, returning an alert for reaching the goal, or
+    /// for the milestone it passed if it didn't.
+    pub(crate) async fn advance_goal(
+        &self,
+        kind: GoalKind,
+        amount: i64,
+        payload: &TwitchEvent,
+    ) -> Option<DisplayMessage> {
+        let (before, goal) = self.goals.add(kind, amount)?;
+        let reached = goal.is_reached() && before < goal.target;
+        let newly_reached = self.save_goal(&goal, reached).await;
+        let who = payload.user_name().unwrap_or("A mysterious stranger");
+
+        let count = if newly_reached {
+            goal.target
+        } else {
+            milestone_between(
+                before,
+                goal.current,
+                self.goals_config.milestones.every(kind),
+            )?
+        };
+        let prompt = if newly_reached {
+            println!("{} reached: {} {}", goal.label, goal.current, unit(kind));
+            format!(
+                "tell me an epic story about how {} helped the Null party reach their goal of {} {}.",
+                who,
+                goal.target,
+                unit(kind)
+            )
+        } else {
+            println!("Milestone: {} {}", count, unit(kind));
+            format!(
+                "tell me an epic story about the Null party celebrating {} {}, with {} bringing them there.",
+                count,
+                unit(kind),
+                who
+            )
+        };
+        let plain = format!("The Null party celebrates {} {}!", count, unit(kind));
+        let mut celebration = self.celebrate(prompt, &plain, payload).await;
+        // Flagged so the dashboard points it out and catch-up mode doesn't fold it away
+        celebration.flag = Some(if newly_reached {
+            format!("{} reached", goal.label)
+        } else {
+            format!("Milestone: {} {}", count, unit(kind))
+        });
+        Some(celebration)
+    }
+
+    /// The celebration story, asked for without the rules so a rule for the event doesn't
+    /// swap the prompt out. `plain` is shown when the model fails.
+    async fn celebrate(
+        &self,
+        prompt: String,
+        plain: &str,
+        payload: &TwitchEvent,
+    ) -> DisplayMessage {
+        // Still celebrated while catching up, just without the wait for the model
+        if self.backlog.catching_up() {
+            let story = Story::from_text(plain);
+            let display_time = story.story.split(" ").count() * 500;
+            return self
+                .display_message(story, display_time, payload.clone())
+                .await;
+        }
+        let mut conversation = self
+            .chat_gpt
+            .new_conversation_directed(crate::story::STORY_PROMPT);
+        let story = match tokio::time::timeout(
+            self.event_timeout,
+            self.ask_prompt(&mut conversation, prompt, payload),
+        )
+        .await
+        {
+            Ok(Ok(story)) => story,
+            _ => {
+                println!("No celebration story, showing a plain one");
+                Story::from_text(plain)
+            }
+        };
+        let display_time = story.story.split(" ").count() * 500;
+        self.display_message(story, display_time, payload.clone())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{ChannelGiftMessage, CheerEvent, FollowEvent, SubscribeEvent};
+
+    use super::*;
+
+    fn sub(tier: NullSubTier, is_gift: bool) -> TwitchEvent {
+        TwitchEvent::ChannelSubscribe(SubscribeEvent {
+            broadcaster_user_id: 2,
+            broadcaster_user_name: "Null".to_string(),
+            user_name: "sam".to_string(),
+            user_id: 7,
+            is_gift,
+            tier,
+            cumulative_months: 1,
+            duration_months: 1,
+            message: String::new(),
+            streak_months: None,
+            profile: None,
+        })
+    }
+
+    #[test]
+    fn milestone_between_finds_the_highest_one_passed() {
+        assert_eq!(milestone_between(99, 100, 100), Some(100));
+        assert_eq!(milestone_between(95, 320, 100), Some(300));
+        assert_eq!(milestone_between(100, 150, 100), None);
+        assert_eq!(milestone_between(101, 199, 100), None);
+        assert_eq!(milestone_between(0, 50, 100), None);
+        assert_eq!(milestone_between(0, 500, 0), None);
+    }
+
+    #[test]
+    fn progress_for_counts_follows_sub_points_and_bits() {
+        let follow = TwitchEvent::ChannelFollow(FollowEvent {
+            user_name: "sam".to_string(),
+            user_id: 7,
+            broadcaster_user_id: 2,
+            profile: None,
+        });
+        let cheer = TwitchEvent::ChannelCheer(CheerEvent {
+            user_name: "sam".to_string(),
+            user_id: 7,
+            bits: 250,
+            message: String::new(),
+            profile: None,
+        });
+        assert!(matches!(
+            progress_for(&follow),
+            Some((GoalKind::Followers, 1))
+        ));
+        assert!(matches!(
+            progress_for(&cheer),
+            Some((GoalKind::StreamBits, 250))
+        ));
+        assert!(matches!(
+            progress_for(&sub(NullSubTier::Tier3("3000".to_string()), false)),
+            Some((GoalKind::SubPoints, 6))
+        ));
+        assert!(matches!(
+            progress_for(&sub(NullSubTier::Prime("Prime".to_string()), false)),
+            Some((GoalKind::SubPoints, 1))
+        ));
+    }
+
+    #[test]
+    fn progress_for_counts_a_gift_bomb_once_by_its_total() {
+        let gift = TwitchEvent::ChannelSubGift(ChannelGiftMessage {
+            broadcaster_user_id: "2".to_string(),
+            broadcaster_user_login: "null".to_string(),
+            broadcaster_user_name: "Null".to_string(),
+            cumulative_total: Some(20),
+            is_anonymous: false,
+            tier: NullSubTier::Tier2("2000".to_string()),
+            total: 10,
+            user_id: Some("7".to_string()),
+            user_login: Some("sam".to_string()),
+            user_name: Some("sam".to_string()),
+            recipients: vec![],
+        });
+        assert!(matches!(
+            progress_for(&gift),
+            Some((GoalKind::SubPoints, 20))
+        ));
+        // The recipients' subs came with the gift
+        assert!(progress_for(&sub(NullSubTier::Tier2("2000".to_string()), true)).is_none());
+    }
+}
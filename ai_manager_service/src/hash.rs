@@ -0,0 +1,37 @@
+//! A hash that stays the same across builds.
+//!
+//! Used wherever a hash ends up on disk or decides something a viewer sees again later, like
+//! image cache file names. The std hasher is randomly seeded and may change between Rust
+//! versions, so it is spelled out here instead.
+
+/// A random looking number that is always the same for the same seed.
+pub fn stable_hash(seed: &str) -> u64 {
+    splitmix64(fnv1a(seed.as_bytes()))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_hash_is_the_same_for_the_same_seed() {
+        assert_eq!(stable_hash("abc"), stable_hash("abc"));
+        assert_ne!(stable_hash("abc"), stable_hash("abd"));
+        // Pinned so a change to the hashing, which would orphan every cached file, is noticed
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(stable_hash(""), splitmix64(0xcbf29ce484222325));
+    }
+}
@@ -0,0 +1,257 @@
+//! Scene illustrations for the alerts, generated from the story segment.
+//!
+//! Images are cached on disk by prompt in a directory served under `/assets`, so the same
+//! story never gets generated twice. Generation runs with a timeout, an alert goes out
+//! without its picture rather than waiting on a slow backend.
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use base64::Engine;
+use serde_json::json;
+
+use crate::hash::stable_hash;
+
+/// Generates a png from a text prompt.
+#[async_trait]
+pub trait ImageGenerator: Send + Sync {
+    async fn generate(&self, prompt: &str) -> anyhow::Result<Vec<u8>>;
+}
+
+/// OpenAI's images endpoint.
+pub struct OpenAiImages {
+    client: reqwest::Client,
+    api_key: String,
+    pub model: String,
+    pub size: String,
+}
+
+impl OpenAiImages {
+    pub fn new(api_key: String) -> OpenAiImages {
+        OpenAiImages {
+            client: reqwest::Client::new(),
+            api_key,
+            model: "dall-e-3".to_string(),
+            size: "1024x1024".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ImageGenerator for OpenAiImages {
+    async fn generate(&self, prompt: &str) -> anyhow::Result<Vec<u8>> {
+        let response: serde_json::Value = self
+            .client
+            .post("https://api.openai.com/v1/images/generations")
+            .bearer_auth(&self.api_key)
+            .json(&json!({
+                "model": self.model,
+                "prompt": prompt,
+                "n": 1,
+                "size": self.size,
+                "response_format": "b64_json",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let image = response["data"][0]["b64_json"]
+            .as_str()
+            .ok_or(anyhow!("no image in the openai response"))?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(image)?)
+    }
+}
+
+/// A local Stable Diffusion server speaking the AUTOMATIC1111 `txt2img` api.
+pub struct StableDiffusion {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl StableDiffusion {
+    pub fn new(url: String) -> StableDiffusion {
+        StableDiffusion {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ImageGenerator for StableDiffusion {
+    async fn generate(&self, prompt: &str) -> anyhow::Result<Vec<u8>> {
+        let response: serde_json::Value = self
+            .client
+            .post(format!("{}/sdapi/v1/txt2img", self.url))
+            .json(&json!({
+                "prompt": prompt,
+                "steps": 20,
+                "width": 512,
+                "height": 512,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let image = response["images"][0]
+            .as_str()
+            .ok_or(anyhow!("no image in the stable diffusion response"))?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(image)?)
+    }
+}
+
+/// Always hands back the same transparent pixel, for running without a backend.
+pub struct StubImages;
+
+const TRANSPARENT_PIXEL_PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+
+#[async_trait]
+impl ImageGenerator for StubImages {
+    async fn generate(&self, _prompt: &str) -> anyhow::Result<Vec<u8>> {
+        Ok(base64::engine::general_purpose::STANDARD.decode(TRANSPARENT_PIXEL_PNG)?)
+    }
+}
+
+/// Picks the backend by name: `openai`, `stable-diffusion` (which needs its url) or `stub`.
+pub fn generator_by_name(
+    name: &str,
+    api_key: &str,
+    url: Option<String>,
+) -> anyhow::Result<Arc<dyn ImageGenerator>> {
+    match name {
+        "openai" => Ok(Arc::new(OpenAiImages::new(api_key.to_string()))),
+        "stable-diffusion" => {
+            let url = url.ok_or(anyhow!("stable diffusion needs the server url"))?;
+            Ok(Arc::new(StableDiffusion::new(url)))
+        }
+        "stub" => Ok(Arc::new(StubImages)),
+        other => bail!("unknown image backend {}", other),
+    }
+}
+
+/// The optional image step of the [`crate::AIManager`].
+pub struct ImageStep {
+    generator: Arc<dyn ImageGenerator>,
+    cache_dir: PathBuf,
+    url_prefix: String,
+    timeout: Duration,
+}
+
+impl ImageStep {
+    /// `cache_dir` has to be served by the frontend under `url_prefix`.
+    pub fn new(
+        generator: Arc<dyn ImageGenerator>,
+        cache_dir: PathBuf,
+        url_prefix: String,
+        timeout: Duration,
+    ) -> ImageStep {
+        ImageStep {
+            generator,
+            cache_dir,
+            url_prefix,
+            timeout,
+        }
+    }
+
+    /// Url of the illustration for the story, `None` when it failed or took too long.
+    pub async fn image_for(&self, story: &str) -> Option<String> {
+        let prompt = format!(
+            "A dungeons and dragons fantasy illustration of this scene, no text: {}",
+            story
+        );
+        let file_name = format!("{:016x}.png", stable_hash(&prompt));
+        let url = format!("{}/{}", self.url_prefix, file_name);
+        let path = self.cache_dir.join(&file_name);
+
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Some(url);
+        }
+
+        let image = match tokio::time::timeout(self.timeout, self.generator.generate(&prompt)).await
+        {
+            Ok(Ok(image)) => image,
+            Ok(Err(e)) => {
+                println!("image generation failed: {}", e);
+                return None;
+            }
+            Err(_) => {
+                println!("image generation took over {:?}, skipping", self.timeout);
+                return None;
+            }
+        };
+
+        let saved = async {
+            tokio::fs::create_dir_all(&self.cache_dir).await?;
+            tokio::fs::write(&path, image).await
+        };
+        if let Err(e) = saved.await {
+            println!("could not cache generated image: {}", e);
+            return None;
+        }
+        Some(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Counts the images it makes, taking `delay` for each.
+    struct CountingImages {
+        made: Arc<AtomicUsize>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl ImageGenerator for CountingImages {
+        async fn generate(&self, prompt: &str) -> anyhow::Result<Vec<u8>> {
+            tokio::time::sleep(self.delay).await;
+            self.made.fetch_add(1, Ordering::SeqCst);
+            Ok(prompt.as_bytes().to_vec())
+        }
+    }
+
+    fn image_step(name: &str, delay: Duration) -> (ImageStep, Arc<AtomicUsize>) {
+        let made = Arc::new(AtomicUsize::new(0));
+        let cache_dir =
+            std::env::temp_dir().join(format!("image-step-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let step = ImageStep::new(
+            Arc::new(CountingImages {
+                made: made.clone(),
+                delay,
+            }),
+            cache_dir,
+            "/assets/generated".to_string(),
+            Duration::from_millis(200),
+        );
+        (step, made)
+    }
+
+    #[tokio::test]
+    async fn image_for_caches_by_story() {
+        let (step, made) = image_step("cache", Duration::ZERO);
+        let first = step.image_for("The party meets a dragon").await;
+        let again = step.image_for("The party meets a dragon").await;
+        let other = step.image_for("The party meets a goblin").await;
+
+        assert!(first.as_deref().unwrap().starts_with("/assets/generated/"));
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(made.load(Ordering::SeqCst), 2);
+        let _ = std::fs::remove_dir_all(&step.cache_dir);
+    }
+
+    #[tokio::test]
+    async fn image_for_gives_up_on_slow_backends() {
+        let (step, made) = image_step("slow", Duration::from_secs(5));
+        assert_eq!(step.image_for("The party waits").await, None);
+        assert_eq!(made.load(Ordering::SeqCst), 0);
+    }
+}
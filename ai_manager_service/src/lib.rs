@@ -1,19 +1,100 @@
+pub mod boss;
+pub mod credits;
+pub mod dice;
+pub mod follows;
+pub mod generation;
+pub mod gifts;
+pub mod goals;
+pub mod hash;
+pub mod images;
+pub mod party;
+pub mod profiles;
+pub mod raiders;
+pub mod rules;
+pub mod safety;
 pub mod sqlite;
+pub mod story;
 
+use std::collections::HashMap;
 use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
+use boss::{BattleRow, BossConfig, BossStep, BossTurn};
 use chatgpt::prelude::{ChatGPT, Conversation};
 use eyre::eyre;
+use follows::FollowBurstDetector;
+use generation::{Attempted, OrderedJobs};
+use gifts::GiftCoalescer;
+use goals::GoalsConfig;
+use images::ImageStep;
 use messages::{
-    ChannelGiftMessage, DisplayMessage, FollowEvent, NewTwitchEventMessage, NullSubTier, RaidEvent,
-    SubscribeEvent, TwitchEvent,
+    AlertAssets, Backlog, BossBattle, ChannelGiftMessage, CharacterSheet, CheerEvent, Credits,
+    DiceRoll, DisplayMessage, FollowBurstEvent, FollowEvent, GoalKind, Goals, Mood,
+    NewTwitchEventMessage, NullSubTier, RaidEvent, SubscribeEvent, TwitchEvent,
 };
+use rules::Rules;
+use safety::SafetyFilter;
+use story::Story;
 use tokio::{runtime::Handle, sync::mpsc};
 
+/// How many times a story is tried before the neutral one is shown.
+pub const MAX_GENERATION_ATTEMPTS: usize = 3;
+
+/// Wait before the first retry, doubled for each one after.
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How many names a follow burst alert lists before it only counts the rest.
+const MAX_BURST_NAMES: usize = 5;
+
+/// What the run loop turns into an alert.
+enum Job {
+    /// The event, with what it already did to the boss fight when it came in.
+    Event(Box<NewTwitchEventMessage>, Option<BossTurn>),
+    FollowBurst(Vec<FollowEvent>),
+}
+
 pub struct AIManager {
     pub sqlite_pool: sqlx::SqlitePool,
     pub chat_gpt: ChatGPT,
     pub frontend_sender: mpsc::UnboundedSender<DisplayMessage>,
+    pub alert_assets: Arc<RwLock<AlertAssets>>,
+    /// Illustrates alerts that have no image configured. Off unless set.
+    pub image_step: Option<ImageStep>,
+    /// Looks up what raiding channels were streaming for the raid story. Off unless set.
+    pub raider_lookup: Option<raiders::RaiderLookup>,
+    /// Checks viewer text before it goes in a prompt and stories before they are shown.
+    pub safety: SafetyFilter,
+    /// Changes the prompt, sound, theme, priority or display time of alerts by what happened.
+    pub rules: Rules,
+    /// How many stories are generated at once.
+    pub concurrency: usize,
+    /// Longest an event waits for its story, retries and the narration of a boss hit included.
+    pub event_timeout: Duration,
+    /// Switches to short messages when the alerts fall behind.
+    pub backlog: Backlog,
+    /// How long a gift waits for the subscribe events of its recipients.
+    pub gift_window: Duration,
+    /// Follows from someone who followed within this long ago get no alert.
+    pub refollow_cooldown: Duration,
+    /// More follows than `follow_burst_threshold` within `follow_burst_window` are taken as a
+    /// follow-bot wave and get one muted alert.
+    pub follow_burst_window: Duration,
+    pub follow_burst_threshold: usize,
+    /// Health and damage weights of hype train boss fights.
+    pub boss_config: BossConfig,
+    /// The boss fight going on, shared with the frontend which shows it.
+    pub boss_battle: BossBattle,
+    /// Where the fight going on is saved in the campaign.
+    battle_row: Mutex<Option<BattleRow>>,
+    /// Targets of the follower, sub point and bits goals, and how often there is a milestone.
+    pub goals_config: GoalsConfig,
+    /// How far along the goals are, shared with the frontend which shows them.
+    pub goals: Goals,
+    /// The credits list everyone who supported the stream this session.
+    pub session: credits::Session,
+    /// The credits rolling at the end of the stream, shared with the frontend which shows them.
+    pub credits: Credits,
 }
 
 impl AIManager {
@@ -21,72 +102,585 @@ impl AIManager {
         sqlite: sqlx::SqlitePool,
         chat_key: String,
         fs: mpsc::UnboundedSender<DisplayMessage>,
+        alert_assets: Arc<RwLock<AlertAssets>>,
     ) -> anyhow::Result<Self> {
         let chat = ChatGPT::new(chat_key)?;
         Ok(AIManager {
             sqlite_pool: sqlite,
             chat_gpt: chat,
             frontend_sender: fs,
+            alert_assets,
+            image_step: None,
+            raider_lookup: None,
+            safety: SafetyFilter::default(),
+            rules: Rules::default(),
+            concurrency: 4,
+            event_timeout: Duration::from_secs(30),
+            backlog: Backlog::default(),
+            gift_window: Duration::from_secs(5),
+            refollow_cooldown: Duration::from_secs(30 * 24 * 60 * 60),
+            follow_burst_window: Duration::from_secs(10),
+            follow_burst_threshold: 5,
+            boss_config: BossConfig::default(),
+            boss_battle: BossBattle::default(),
+            battle_row: Mutex::new(None),
+            goals_config: GoalsConfig::default(),
+            goals: Goals::default(),
+            session: credits::Session::default(),
+            credits: Credits::default(),
         })
     }
 
+    /// Builds the message for the overlay, with the image, animation and sound configured for the event.
+    /// Events without an image get one generated from the story when the image step is on,
+    /// except follow bursts, which are made without the model to keep a follow-bot wave cheap.
+    /// Matching rules get the last say on sound, theme, priority and display time.
+    async fn display_message(
+        &self,
+        story: Story,
+        display_time: usize,
+        payload: TwitchEvent,
+    ) -> DisplayMessage {
+        let assets = self
+            .alert_assets
+            .read()
+            .unwrap()
+            .for_event(&payload, story.mood);
+        let message = story.story;
+        let image_url = match (assets.image_url, &self.image_step) {
+            (Some(url), _) => url,
+            (None, Some(step)) if !matches!(payload, TwitchEvent::FollowBurst(_)) => step
+                .image_for(&message)
+                .await
+                .unwrap_or_else(|| "none".to_string()),
+            (None, _) => "none".to_string(),
+        };
+        let mut display_message = DisplayMessage {
+            message,
+            image_url,
+            sound_url: assets.sound_url.unwrap_or_else(|| "none".to_string()),
+            animation: assets.animation.unwrap_or_else(|| "none".to_string()),
+            volume: assets.volume.unwrap_or(1.0),
+            display_time,
+            payload,
+            title: story.title,
+            mood: story.mood,
+            emphasis_words: story.emphasis_words,
+            flag: None,
+            theme: None,
+            priority: 0,
+            character: None,
+            roll: None,
+        };
+        self.rules
+            .actions_for(&display_message.payload)
+            .apply(&mut display_message);
+        display_message
+    }
+
+    /// Asks for a story with [`AIManager::ask_prompt`], using a rule's prompt for the event
+    /// instead of `prompt` when there is one.
+    async fn ask_story(
+        &self,
+        conversation: &mut Conversation,
+        prompt: String,
+        payload: &TwitchEvent,
+    ) -> anyhow::Result<Story> {
+        let prompt = self
+            .rules
+            .actions_for(payload)
+            .prompt_for(payload)
+            .unwrap_or(prompt);
+        self.ask_prompt(conversation, prompt, payload).await
+    }
+
+    /// Asks for a story, re-asking in the same conversation while the reply isn't valid.
+    /// After [`story::MAX_ATTEMPTS`] the last reply is shown as plain text.
+    /// Stories the safety filter flags are swapped for the neutral one.
+    async fn ask_prompt(
+        &self,
+        conversation: &mut Conversation,
+        prompt: String,
+        payload: &TwitchEvent,
+    ) -> anyhow::Result<Story> {
+        let mut reply = conversation
+            .send_message(prompt)
+            .await?
+            .message()
+            .content
+            .to_string();
+        for attempt in 1..story::MAX_ATTEMPTS {
+            match story::parse_story(&reply) {
+                Ok(story) => return Ok(self.checked_story(story, payload)),
+                Err(e) => {
+                    println!("Invalid story on attempt {}: {}", attempt, e);
+                    reply = conversation
+                        .send_message(format!(
+                            "That reply was not valid, {}. Answer again with only the json object.",
+                            e
+                        ))
+                        .await?
+                        .message()
+                        .content
+                        .to_string();
+                }
+            }
+        }
+
+        let story = story::parse_story(&reply).unwrap_or_else(|e| {
+            println!("Giving up on a valid story, showing the reply as is: {}", e);
+            Story::from_text(&reply)
+        });
+        Ok(self.checked_story(story, payload))
+    }
+
+    fn checked_story(&self, story: Story, payload: &TwitchEvent) -> Story {
+        self.safety.check_story(story).unwrap_or_else(|reason| {
+            println!("Story flagged, showing the neutral one: {}", reason);
+            safety::neutral_story(payload)
+        })
+    }
+
+    /// The alert with the neutral story for the event, made without asking the model.
+    async fn neutral_message(&self, event: &TwitchEvent) -> DisplayMessage {
+        let payload = self.safety.scrub(event);
+        let story = safety::neutral_story(&payload);
+        let display_time = story.story.split(" ").count() * 500;
+        self.display_message(story, display_time, payload).await
+    }
+
     pub async fn run(
         &self,
         mut receiver: mpsc::UnboundedReceiver<NewTwitchEventMessage>,
     ) -> Result<(), eyre::Error> {
-        loop {
-            let msg = (&mut receiver).recv().await;
-
-            match msg {
-                Some(message) => {
-                    let res = self.new_event(message).await;
-                    match res {
-                        Ok(()) => {
-                            println!("ok");
-                        }
-                        Err(e) => {
-                            println!("{}", e);
+        // Up to `concurrency` stories are generated at once, handed back in the order the
+        // events came in so the alerts still play in that order
+        let mut generating = OrderedJobs::new(self.concurrency);
+        let mut receiver_open = true;
+        // Gift bombs are held back here until the gift has its recipients
+        let mut gifts = GiftCoalescer::new(self.gift_window);
+        // and follow-bot waves until they die down
+        let mut follows =
+            FollowBurstDetector::new(self.follow_burst_window, self.follow_burst_threshold);
+        // What events did to the boss fight as they came in, until their job is queued
+        let mut boss_turns = HashMap::new();
+        let event_job = |message: NewTwitchEventMessage, turns: &mut HashMap<_, _>| {
+            let turn = turns.remove(&message.message_id);
+            self.run_job(Job::Event(Box::new(message), turn))
+        };
+
+        while receiver_open || !generating.is_empty() || !gifts.is_empty() || !follows.is_empty() {
+            let deadline = match (gifts.next_deadline(), follows.next_deadline()) {
+                (Some(gift), Some(follow)) => Some(gift.min(follow)),
+                (gift, follow) => gift.or(follow),
+            };
+            tokio::select! {
+                msg = receiver.recv(), if receiver_open && generating.has_room() => {
+                    match msg {
+                        Some(message) => {
+                            if let Some(turn) = self.fight_boss(&message) {
+                                boss_turns.insert(message.message_id.clone(), turn);
+                            }
+                            if let Some(message) = gifts.push(message).and_then(|message| follows.push(message)) {
+                                generating.push(event_job(message, &mut boss_turns));
+                            }
                         }
+                        None => receiver_open = false,
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                    let now = tokio::time::Instant::now();
+                    for message in gifts.expired(now) {
+                        generating.push(event_job(message, &mut boss_turns));
+                    }
+                    if let Some(burst) = follows.expired(now) {
+                        generating.push(self.run_job(Job::FollowBurst(burst)));
+                    }
+                }
+                Some(alerts) = generating.next(), if !generating.is_empty() => {
+                    if alerts.is_empty() {
+                        println!("no alert for the event");
+                    }
+                    for display_message in alerts {
+                        self.frontend_sender.send(display_message)?;
+                        println!("ok");
                     }
                 }
-                None => return Err(eyre!("error: receiver closed")),
             }
+            self.backlog.set_waiting_for_ai(
+                receiver.len() + generating.len() + gifts.len() + follows.len(),
+            );
         }
+        Err(eyre!("error: receiver closed"))
     }
 
-    async fn new_event(&self, msg: NewTwitchEventMessage) -> anyhow::Result<()> {
-        let conversation: Conversation = self.chat_gpt.new_conversation_directed(
-            "You are D&DGPT, when answering any questions, you always answer with a short epic story as a dungeons and dragons dungeon master in 27 words or less."
+    /// The alerts for the job, the event's own followed by any goal celebration it earned.
+    async fn run_job(&self, job: Job) -> Vec<DisplayMessage> {
+        match job {
+            Job::Event(message, boss_turn) => self.generate(*message, boss_turn).await,
+            Job::FollowBurst(follows) => self.follow_burst_message(follows).await,
+        }
+    }
+
+    /// Whether the follow should get an alert, recording it either way. Someone who followed
+    /// within `refollow_cooldown` is unfollowing and refollowing for attention and gets none.
+    async fn record_follow(&self, follow: &FollowEvent) -> bool {
+        let previous = match self.sqlite_pool.acquire().await {
+            Ok(conn) => sqlite::record_follow(conn, follow).await,
+            Err(e) => Err(e.into()),
+        };
+        match previous {
+            Ok(Some(seconds_ago)) if seconds_ago < self.refollow_cooldown.as_secs_f64() => {
+                println!(
+                    "{} refollowed after {:.0}s, no alert",
+                    follow.user_name, seconds_ago
+                );
+                false
+            }
+            Ok(_) => true,
+            Err(e) => {
+                println!("Could not record follow of {}: {}", follow.user_name, e);
+                true
+            }
+        }
+    }
+
+    /// One muted alert for a follow-bot wave, made without asking the model. Refollows in
+    /// the wave are left out and it is flagged for the dashboard. None when they all were.
+    /// The new follows still count towards the follower goal.
+    async fn follow_burst_message(&self, follows: Vec<FollowEvent>) -> Vec<DisplayMessage> {
+        let mut names = vec![];
+        for follow in &follows {
+            if self.record_follow(follow).await {
+                let payload = self
+                    .safety
+                    .scrub(&TwitchEvent::ChannelFollow(follow.clone()));
+                names.extend(payload.user_name().map(|name| name.to_string()));
+            }
+        }
+        println!(
+            "Follow burst over, {} follows, {} new",
+            follows.len(),
+            names.len()
         );
+        if names.is_empty() {
+            return vec![];
+        }
+
+        let name_list = if names.len() > MAX_BURST_NAMES {
+            format!(
+                "{} and {} others",
+                names[..MAX_BURST_NAMES].join(", "),
+                names.len() - MAX_BURST_NAMES
+            )
+        } else {
+            names.join(", ")
+        };
+        let story = Story {
+            title: String::new(),
+            story: format!(
+                "{} followers joined the party at once: {}",
+                names.len(),
+                name_list
+            ),
+            mood: Mood::default(),
+            emphasis_words: vec![],
+        };
+        let display_time = story.story.split(" ").count() * 500;
+        let new_follows = names.len() as i64;
+        let payload = TwitchEvent::FollowBurst(FollowBurstEvent {
+            user_names: names,
+            follows: follows.len() as i64,
+        });
+
+        let mut display_message = self
+            .display_message(story, display_time, payload.clone())
+            .await;
+        display_message.volume = 0.0;
+        display_message.flag = Some(format!("Follow burst: {} follows", follows.len()));
+        let celebration = self
+            .advance_goal(GoalKind::Followers, new_follows, &payload)
+            .await;
+        std::iter::once(display_message)
+            .chain(celebration)
+            .collect()
+    }
+
+    /// Makes the alert for the event, with the character sheet of the viewer behind it after
+    /// awarding their XP and the d20 rolled for it. Refollows get no alert. The end of a boss
+    /// fight gets its story and hits on the boss are narrated and saved, the fight itself
+    /// already moved on when the event came in. The end of the stream rolls the credits.
+    /// Events that reach a goal or a milestone get a celebration after their own alert.
+    async fn generate(
+        &self,
+        msg: NewTwitchEventMessage,
+        boss_turn: Option<BossTurn>,
+    ) -> Vec<DisplayMessage> {
+        // The story and the narration of a hit share the event's time
+        let deadline = tokio::time::Instant::now() + self.event_timeout;
+        match (&msg.event, boss_turn) {
+            (TwitchEvent::ChannelHypeTrainBegin(_), _) => return vec![],
+            (TwitchEvent::ChannelHypeTrainEnd(train), turn) => {
+                return match turn {
+                    Some(BossTurn {
+                        step: BossStep::Ended(boss),
+                        battle_row,
+                    }) => vec![self.end_boss_fight(train, boss, battle_row).await],
+                    _ => vec![],
+                };
+            }
+            (TwitchEvent::StreamOnline(_), _) => {
+                self.record_stream_start().await;
+                return vec![];
+            }
+            (TwitchEvent::StreamOffline(event), _) => {
+                self.roll_credits(event).await;
+                return vec![];
+            }
+            (
+                _,
+                Some(BossTurn {
+                    step:
+                        BossStep::Hit {
+                            boss,
+                            damage,
+                            attacker,
+                        },
+                    battle_row,
+                }),
+            ) => {
+                self.record_hit(&boss, damage, &attacker, battle_row, deadline)
+                    .await
+            }
+            _ => {}
+        }
+
+        if let TwitchEvent::ChannelFollow(follow) = &msg.event {
+            if !self.record_follow(follow).await {
+                return vec![];
+            }
+        }
+
+        self.record_event(&msg.event).await;
+        let character = self.award_xp(&msg.event).await;
+        // No time for the dice animation while catching up
+        let roll = (!self.backlog.catching_up()).then(|| dice::roll_for(&msg));
+        let display_message = self
+            .story_for(&msg, character.as_ref(), roll.as_ref(), deadline)
+            .await;
+        let display_message = display_message.map(|mut display_message| {
+            display_message.character = character;
+            display_message.roll = roll;
+            display_message
+        });
+
+        let celebration = match goals::progress_for(&msg.event) {
+            Some((kind, amount)) => {
+                self.advance_goal(kind, amount, &self.safety.scrub(&msg.event))
+                    .await
+            }
+            None => None,
+        };
+        display_message.into_iter().chain(celebration).collect()
+    }
+
+    /// Saves when the stream went live, the leaderboards count "this stream" from there.
+    async fn record_stream_start(&self) {
+        let saved = match self.sqlite_pool.acquire().await {
+            Ok(conn) => sqlite::write_stream_started(conn).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = saved {
+            println!("Could not save the start of the stream: {}", e);
+        }
+    }
 
-        match &msg.event {
+    /// Saves cheers and subs for the leaderboards. Raids and gifts are saved along with
+    /// their stories.
+    async fn record_event(&self, event: &TwitchEvent) {
+        let saved = async {
+            match event {
+                TwitchEvent::ChannelCheer(cheer) => {
+                    let conn = self.sqlite_pool.acquire().await?;
+                    sqlite::write_new_cheer_event(conn, cheer).await
+                }
+                TwitchEvent::ChannelSubscribe(sub) => {
+                    let conn = self.sqlite_pool.acquire().await?;
+                    sqlite::write_new_subscription_event(conn, sub, sub.tier.name(), false).await
+                }
+                TwitchEvent::ChannelResubscribe(sub) => {
+                    let conn = self.sqlite_pool.acquire().await?;
+                    sqlite::write_new_subscription_event(conn, sub, sub.tier.name(), true).await
+                }
+                _ => anyhow::Ok(()),
+            }
+        };
+        if let Err(e) = saved.await {
+            println!("Could not save the {} event: {}", event.event_type(), e);
+        }
+    }
+
+    /// Gives the viewer behind the event the XP it earned, along with a level and an item
+    /// for each level it got them to. `None` for anonymous events or when the database fails.
+    async fn award_xp(&self, event: &TwitchEvent) -> Option<CharacterSheet> {
+        let award = party::award_for(&self.safety.scrub(event))?;
+        let (class, race) = party::class_and_race(award.user_id);
+        let sheet = async {
+            let conn = self.sqlite_pool.acquire().await?;
+            let mut sheet =
+                sqlite::add_party_xp(conn, award.user_id, &award.user_name, class, race, award.xp)
+                    .await?;
+
+            let level = party::level_for(sheet.xp);
+            if level > sheet.level {
+                let mut inventory = sheet.inventory.clone();
+                inventory.extend(
+                    (sheet.level + 1..=level)
+                        .map(|level| party::item_for(award.user_id, level).to_string()),
+                );
+                let conn = self.sqlite_pool.acquire().await?;
+                if sqlite::level_up_party_member(
+                    conn,
+                    award.user_id,
+                    sheet.level,
+                    level,
+                    &inventory,
+                )
+                .await?
+                {
+                    println!("{} reached level {}", sheet.user_name, level);
+                    sheet.level = level;
+                    sheet.inventory = inventory;
+                    sheet.leveled_up = true;
+                }
+            }
+            anyhow::Ok(sheet)
+        };
+
+        match sheet.await {
+            Ok(sheet) => Some(sheet),
+            Err(e) => {
+                println!("Could not award XP to {}: {}", award.user_name, e);
+                None
+            }
+        }
+    }
+
+    /// Asks for the story, retrying with backoff when the model fails.
+    /// In catch-up mode the model is skipped for the short templated message.
+    /// Falls back to the neutral story when it keeps failing or is not done by the deadline.
+    async fn story_for(
+        &self,
+        msg: &NewTwitchEventMessage,
+        character: Option<&CharacterSheet>,
+        roll: Option<&DiceRoll>,
+        deadline: tokio::time::Instant,
+    ) -> Option<DisplayMessage> {
+        if self.backlog.catching_up() {
+            println!(
+                "Catching up, short message for the {} event",
+                msg.event.event_type()
+            );
+            return Some(self.neutral_message(&msg.event).await);
+        }
+
+        let what = format!("{} event", msg.event.event_type());
+        match generation::with_retries(&what, deadline, || self.new_event(msg, character, roll))
+            .await
+        {
+            Attempted::Done(display_message) => display_message,
+            Attempted::Failed(e) => {
+                println!(
+                    "Giving up on {} event, showing the neutral story: {}",
+                    msg.event.event_type(),
+                    e
+                );
+                Some(self.neutral_message(&msg.event).await)
+            }
+            Attempted::TimedOut => {
+                println!(
+                    "{} event took over {:?}, showing the neutral story",
+                    msg.event.event_type(),
+                    self.event_timeout
+                );
+                Some(self.neutral_message(&msg.event).await)
+            }
+        }
+    }
+
+    /// The alert for the event, `None` for events we don't show yet.
+    async fn new_event(
+        &self,
+        msg: &NewTwitchEventMessage,
+        character: Option<&CharacterSheet>,
+        roll: Option<&DiceRoll>,
+    ) -> anyhow::Result<Option<DisplayMessage>> {
+        if let Err(reason) = self.safety.check_event(&msg.event) {
+            println!(
+                "Flagged {} event, showing the neutral story: {}",
+                msg.event.event_type(),
+                reason
+            );
+            return Ok(Some(self.neutral_message(&msg.event).await));
+        }
+
+        for rule in self.rules.matching(&msg.event) {
+            println!(
+                "Rule {:?} matches the {} event",
+                rule.name,
+                msg.event.event_type()
+            );
+        }
+        if self.rules.actions_for(&msg.event).skip_ai() {
+            return Ok(Some(self.neutral_message(&msg.event).await));
+        }
+
+        let mut direction = story::STORY_PROMPT.to_string();
+        if let Some(sheet) = character {
+            direction.push_str(&format!("\n{}", party::sheet_prompt(sheet)));
+        }
+        if let Some(roll) = roll {
+            direction.push_str(&format!("\n{}", dice::roll_prompt(roll)));
+        }
+        let conversation: Conversation = self.chat_gpt.new_conversation_directed(direction);
+
+        let display_message = match &msg.event {
             TwitchEvent::ChannelFollow(follow_event) => {
                 println!("Channel Follow Event!");
-                self.handle_follow_event(follow_event, conversation).await?;
+                self.handle_follow_event(follow_event, conversation).await?
             }
             TwitchEvent::ChannelSubscribe(sub_event) => {
                 println!("Channel Subscribe Event!");
-                self.handle_subscribe_event(sub_event, conversation).await?;
+                self.handle_subscribe_event(sub_event, conversation).await?
             }
             TwitchEvent::ChannelRaid(raid_event) => {
                 println!("Channel Raid Event!");
-                self.handle_raid_event(raid_event, conversation).await?;
+                self.handle_raid_event(raid_event, conversation).await?
             }
             TwitchEvent::ChannelSubGift(sub_gift) => {
                 println!("Channel Sub Gift Event!");
-                self.handle_gift_sub_event(sub_gift, conversation).await?;
+                self.handle_gift_sub_event(sub_gift, conversation).await?
             }
             TwitchEvent::ChannelResubscribe(resub_event) => {
                 println!("Channel Resub Event!");
                 //TODO: handle resubscribe event
-                self.handle_resub_event(resub_event, conversation).await?;
+                self.handle_resub_event(resub_event, conversation).await?
             }
             TwitchEvent::ChannelCheer(cheer_event) => {
                 println!("Channel Cheer Event!");
-                //TODO: handle cheer event
+                self.handle_cheer_event(cheer_event, conversation).await?
             }
-        }
-        Ok(())
+            // Follow bursts are made by follow_burst_message, boss fights and the credits by
+            // generate
+            TwitchEvent::FollowBurst(_)
+            | TwitchEvent::ChannelHypeTrainBegin(_)
+            | TwitchEvent::ChannelHypeTrainEnd(_)
+            | TwitchEvent::StreamOnline(_)
+            | TwitchEvent::StreamOffline(_) => {
+                return Ok(None);
+            }
+        };
+        Ok(Some(display_message))
     }
 
     pub async fn get_story_segment(
@@ -103,7 +697,7 @@ impl AIManager {
         &self,
         gift_sub_event: &ChannelGiftMessage,
         mut conversation: Conversation,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<DisplayMessage> {
         let gifter_name = match gift_sub_event.user_name.clone() {
             Some(name) => name,
             None => "anonymous".to_string(),
@@ -117,177 +711,206 @@ impl AIManager {
             NullSubTier::Other(tier) => tier,
         };
 
-        let response = conversation
-            .send_message(format!(
-                "tell me an epic story about how {} gifted new {} powers to {} null party members.",
-                gifter_name, tier, gift_sub_event.total,
-            ))
+        // Recipient names never go in the prompt, but they are shown on the alert
+        let payload = self
+            .safety
+            .scrub(&TwitchEvent::ChannelSubGift(gift_sub_event.clone()));
+        let story = self
+            .ask_story(
+                &mut conversation,
+                format!(
+                    "tell me an epic story about how {} gifted new {} powers to {} null party members.",
+                    gifter_name, tier, gift_sub_event.total,
+                ),
+                &payload,
+            )
             .await?;
 
-        println!("Response: {}", response.message().content);
+        println!("Story: {:?}", story);
         let mut conn = self.sqlite_pool.acquire().await?;
-        let db_results = sqlite::write_new_gift_subs_event(
-            conn,
-            gift_sub_event,
-            tier,
-            response.message().content.to_string(),
-        )
-        .await?;
+        let db_results =
+            sqlite::write_new_gift_subs_event(conn, gift_sub_event, tier, story.story.clone())
+                .await?;
         println!("db_results: {:?}", db_results);
 
-        let display_time = response.message().content.split(" ").count() * 500;
+        let display_time = story.story.split(" ").count() * 500;
 
-        let display_message = DisplayMessage {
-            message: response.message().content.to_string(),
-            image_url: "none".to_string(),
-            sound_url: "none".to_string(),
-            display_time,
-            payload: TwitchEvent::ChannelSubGift(gift_sub_event.clone()),
-        };
-        self.frontend_sender.send(display_message)?;
-        Ok(())
+        let display_message = self.display_message(story, display_time, payload).await;
+        Ok(display_message)
     }
 
     pub async fn handle_raid_event(
         &self,
         raid_event: &RaidEvent,
         mut conversation: Conversation,
-    ) -> anyhow::Result<()> {
-        let response = conversation
-            .send_message(format!(
-                "tell me an epic story about how {} people from {}'s party joined forces with the Null party for a joint quest.",
-                raid_event.viewers,
-                raid_event.from_broadcaster_user_name,
-            ))
+    ) -> anyhow::Result<DisplayMessage> {
+        let payload = TwitchEvent::ChannelRaid(raid_event.clone());
+        let raider = self.raider_info(raid_event).await.unwrap_or_default();
+        let story = self
+            .ask_story(
+                &mut conversation,
+                format!(
+                    "tell me an epic story about how {} people from {}'s party joined forces with the Null party for a joint quest.{}",
+                    raid_event.viewers,
+                    raid_event.from_broadcaster_user_name,
+                    raiders::raider_prompt(&raider),
+                ),
+                &payload,
+            )
             .await?;
 
-        println!("Response: {}", response.message().content);
+        println!("Story: {:?}", story);
         let conn = self.sqlite_pool.acquire().await?;
         let db_results =
-            sqlite::write_new_raid_event(conn, raid_event, response.message().content.to_string())
-                .await?;
+            sqlite::write_new_raid_event(conn, raid_event, story.story.clone()).await?;
         println!("db_results: {:?}", db_results);
 
-        let display_time = response.message().content.split(" ").count() * 500;
+        let display_time = story.story.split(" ").count() * 500;
 
-        let display_message = DisplayMessage {
-            message: response.message().content.to_string(),
-            image_url: "none".to_string(),
-            sound_url: "none".to_string(),
-            display_time,
-            payload: TwitchEvent::ChannelRaid(raid_event.clone()),
-        };
-        self.frontend_sender.send(display_message)?;
-        Ok(())
+        let mut display_message = self.display_message(story, display_time, payload).await;
+        // The raider's avatar over any configured or generated image
+        if let Some(avatar) = raider.profile_image_url {
+            display_message.image_url = avatar;
+        }
+        Ok(display_message)
     }
 
     pub async fn handle_resub_event(
         &self,
         subscriber_event: &SubscribeEvent,
         mut conversation: Conversation,
-    ) -> anyhow::Result<()> {
-        let response = conversation
-            .send_message(format!(
-                "tell me an epic story about how {} supported the party",
-                subscriber_event.user_name
-            ))
+    ) -> anyhow::Result<DisplayMessage> {
+        let payload = TwitchEvent::ChannelResubscribe(subscriber_event.clone());
+        let story = self
+            .ask_story(
+                &mut conversation,
+                format!(
+                    "tell me an epic story about how {} supported the party",
+                    subscriber_event.user_name
+                ),
+                &payload,
+            )
             .await?;
 
-        println!("Response: {}", response.message().content);
+        println!("Story: {:?}", story);
         let conn = self.sqlite_pool.acquire().await?;
         let db_results = sqlite::write_new_story_segment(
             conn,
             subscriber_event.user_id,
             "subscribe".to_string(),
-            response.message().content.to_string(),
+            story.story.clone(),
         )
         .await?;
 
         println!("db_results: {:?}", db_results);
 
-        let display_time = response.message().content.split(" ").count() * 500;
+        let display_time = story.story.split(" ").count() * 500;
 
-        let display_message = DisplayMessage {
-            message: response.message().content.to_string(),
-            image_url: "none".to_string(),
-            sound_url: "none".to_string(),
-            display_time,
-            payload: TwitchEvent::ChannelResubscribe(subscriber_event.clone()),
-        };
-        self.frontend_sender.send(display_message)?;
-        Ok(())
+        let display_message = self.display_message(story, display_time, payload).await;
+        Ok(display_message)
+    }
+
+    pub async fn handle_cheer_event(
+        &self,
+        cheer_event: &CheerEvent,
+        mut conversation: Conversation,
+    ) -> anyhow::Result<DisplayMessage> {
+        let payload = TwitchEvent::ChannelCheer(cheer_event.clone());
+        let story = self
+            .ask_story(
+                &mut conversation,
+                format!(
+                    "tell me an epic story about how {} cheered {} bits to power up the party",
+                    cheer_event.user_name, cheer_event.bits
+                ),
+                &payload,
+            )
+            .await?;
+
+        println!("Story: {:?}", story);
+        let conn = self.sqlite_pool.acquire().await?;
+        let db_results = sqlite::write_new_story_segment(
+            conn,
+            cheer_event.user_id,
+            "cheer".to_string(),
+            story.story.clone(),
+        )
+        .await?;
+
+        println!("db_results: {:?}", db_results);
+
+        let display_time = story.story.split(" ").count() * 500;
+
+        let display_message = self.display_message(story, display_time, payload).await;
+        Ok(display_message)
     }
 
     pub async fn handle_subscribe_event(
         &self,
         subscriber_event: &SubscribeEvent,
         mut conversation: Conversation,
-    ) -> anyhow::Result<()> {
-        let response = conversation
-            .send_message(format!(
-                "tell me an epic story about how {} supported the party",
-                subscriber_event.user_name
-            ))
+    ) -> anyhow::Result<DisplayMessage> {
+        let payload = TwitchEvent::ChannelSubscribe(subscriber_event.clone());
+        let story = self
+            .ask_story(
+                &mut conversation,
+                format!(
+                    "tell me an epic story about how {} supported the party",
+                    subscriber_event.user_name
+                ),
+                &payload,
+            )
             .await?;
 
-        println!("Response: {}", response.message().content);
+        println!("Story: {:?}", story);
         let conn = self.sqlite_pool.acquire().await?;
         let db_results = sqlite::write_new_story_segment(
             conn,
             subscriber_event.user_id,
             "subscribe".to_string(),
-            response.message().content.to_string(),
+            story.story.clone(),
         )
         .await?;
         println!("db_results: {:?}", db_results);
 
-        let display_time = response.message().content.split(" ").count() * 500;
+        let display_time = story.story.split(" ").count() * 500;
 
-        let display_message = DisplayMessage {
-            message: response.message().content.to_string(),
-            image_url: "none".to_string(),
-            sound_url: "none".to_string(),
-            display_time,
-            payload: TwitchEvent::ChannelSubscribe(subscriber_event.clone()),
-        };
-        self.frontend_sender.send(display_message)?;
-        Ok(())
+        let display_message = self.display_message(story, display_time, payload).await;
+        Ok(display_message)
     }
 
     pub async fn handle_follow_event(
         &self,
         follow_event: &FollowEvent,
         mut conversation: Conversation,
-    ) -> anyhow::Result<()> {
-        let response = conversation
-            .send_message(format!(
-                "tell me an epic story about how {} joined forces with the null party.",
-                follow_event.user_name
-            ))
+    ) -> anyhow::Result<DisplayMessage> {
+        let payload = TwitchEvent::ChannelFollow(follow_event.clone());
+        let story = self
+            .ask_story(
+                &mut conversation,
+                format!(
+                    "tell me an epic story about how {} joined forces with the null party.",
+                    follow_event.user_name
+                ),
+                &payload,
+            )
             .await?;
 
-        println!("Response: {}", response.message().content);
+        println!("Story: {:?}", story);
         let conn = self.sqlite_pool.acquire().await?;
         let db_results = sqlite::write_new_story_segment(
             conn,
             follow_event.user_id,
             "follow".to_string(),
-            response.message().content.to_string(),
+            story.story.clone(),
         )
         .await?;
         println!("db_results: {:?}", db_results);
 
-        let display_time = response.message().content.split(" ").count() * 750;
+        let display_time = story.story.split(" ").count() * 750;
         //TODO: check if there is a "MAX_DISPLAY_TIME" env var
 
-        let display_message = DisplayMessage {
-            message: response.message().content.to_string(),
-            image_url: "none".to_string(),
-            sound_url: "none".to_string(),
-            display_time,
-            payload: TwitchEvent::ChannelFollow(follow_event.clone()),
-        };
-        self.frontend_sender.send(display_message)?;
-        Ok(())
+        let display_message = self.display_message(story, display_time, payload).await;
+        Ok(display_message)
     }
 }
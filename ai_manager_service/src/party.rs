@@ -0,0 +1,226 @@
+//! Character sheets for the members of the Null party.
+//!
+//! Every viewer who supports the stream gets a class and race picked from their user id the
+//! first time, so they keep the same ones for good. Follows, subs, resubs, gifts, cheers and
+//! raids earn XP, and each level brings an item for the inventory.
+use messages::{CharacterSheet, NullSubTier, TwitchEvent};
+
+use crate::hash::stable_hash;
+
+pub const CLASSES: [&str; 10] = [
+    "warrior", "wizard", "rogue", "cleric", "ranger", "bard", "paladin", "druid", "warlock", "monk",
+];
+
+pub const RACES: [&str; 8] = [
+    "human",
+    "elf",
+    "dwarf",
+    "halfling",
+    "orc",
+    "gnome",
+    "tiefling",
+    "dragonborn",
+];
+
+/// One of these is found at every new level.
+pub const ITEMS: [&str; 12] = [
+    "a rusty sword",
+    "a healing potion",
+    "a map of the null realm",
+    "a lucky coin",
+    "a bag of holding",
+    "an enchanted cloak",
+    "a cursed amulet",
+    "a dragon scale shield",
+    "a staff of many sparks",
+    "a pair of boots of speed",
+    "a talking skull",
+    "a crown of the null king",
+];
+
+/// XP for a level, the step to the next one grows by this much each level.
+pub const XP_PER_LEVEL: i64 = 100;
+
+/// Who gets XP for the event and how much.
+pub struct Award {
+    pub user_id: i64,
+    pub user_name: String,
+    pub xp: i64,
+}
+
+/// `None` for events without a viewer we can tell apart, like anonymous gifts and cheers.
+pub fn award_for(event: &TwitchEvent) -> Option<Award> {
+    let (user_id, xp) = match event {
+        TwitchEvent::ChannelFollow(follow) => (follow.user_id, 10),
+        TwitchEvent::ChannelSubscribe(sub) => (sub.user_id, 50 * tier_multiplier(&sub.tier)),
+        TwitchEvent::ChannelResubscribe(sub) => (
+            sub.user_id,
+            50 * tier_multiplier(&sub.tier) + 5 * sub.cumulative_months,
+        ),
+        TwitchEvent::ChannelSubGift(gift) => (
+            gift.user_id.as_ref()?.parse().ok()?,
+            50 * tier_multiplier(&gift.tier) * gift.total,
+        ),
+        TwitchEvent::ChannelCheer(cheer) if cheer.is_anonymous() => return None,
+        TwitchEvent::ChannelCheer(cheer) => (cheer.user_id, (cheer.bits / 10).max(1)),
+        TwitchEvent::ChannelRaid(raid) => (
+            raid.from_broadcaster_user_id.parse().ok()?,
+            (raid.viewers * 2).max(20),
+        ),
+        TwitchEvent::FollowBurst(_)
+        | TwitchEvent::ChannelHypeTrainBegin(_)
+        | TwitchEvent::ChannelHypeTrainEnd(_)
+        | TwitchEvent::StreamOnline(_)
+        | TwitchEvent::StreamOffline(_) => return None,
+    };
+    Some(Award {
+        user_id,
+        user_name: event.user_name()?.to_string(),
+        xp,
+    })
+}
+
+fn tier_multiplier(tier: &NullSubTier) -> i64 {
+    match tier {
+        NullSubTier::Tier2(_) => 2,
+        NullSubTier::Tier3(_) => 5,
+        _ => 1,
+    }
+}
+
+/// Level 2 takes 100 XP, level 3 another 200, level 4 another 300 and so on.
+pub fn level_for(xp: i64) -> i64 {
+    let mut level = 1;
+    while xp >= XP_PER_LEVEL * level * (level + 1) / 2 {
+        level += 1;
+    }
+    level
+}
+
+/// The class and race a new party member starts with.
+pub fn class_and_race(user_id: i64) -> (&'static str, &'static str) {
+    let hash = stable_hash(&user_id.to_string());
+    (
+        CLASSES[(hash % CLASSES.len() as u64) as usize],
+        RACES[(hash / CLASSES.len() as u64 % RACES.len() as u64) as usize],
+    )
+}
+
+/// The item found on reaching the level.
+pub fn item_for(user_id: i64, level: i64) -> &'static str {
+    let hash = stable_hash(&format!("{}:{}", user_id, level));
+    ITEMS[(hash % ITEMS.len() as u64) as usize]
+}
+
+/// The sheet as it is given to the model along with the story prompt.
+pub fn sheet_prompt(sheet: &CharacterSheet) -> String {
+    let mut prompt = format!(
+        "{} is a level {} {} {} in the Null party",
+        sheet.user_name, sheet.level, sheet.race, sheet.class
+    );
+    if !sheet.inventory.is_empty() {
+        prompt.push_str(&format!(", carrying {}", sheet.inventory.join(", ")));
+    }
+    prompt.push('.');
+    if sheet.leveled_up {
+        prompt.push_str(&format!(
+            " They just reached level {}, make it part of the story.",
+            sheet.level
+        ));
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{CheerEvent, RaidEvent};
+
+    use super::*;
+
+    fn cheer(bits: i64) -> TwitchEvent {
+        TwitchEvent::ChannelCheer(CheerEvent {
+            user_name: "sam".to_string(),
+            user_id: 7,
+            bits,
+            message: String::new(),
+            profile: None,
+        })
+    }
+
+    fn raid(viewers: i64) -> TwitchEvent {
+        TwitchEvent::ChannelRaid(RaidEvent {
+            from_broadcaster_user_id: "42".to_string(),
+            from_broadcaster_user_login: "friend".to_string(),
+            from_broadcaster_user_name: "Friend".to_string(),
+            to_broadcaster_user_id: "2".to_string(),
+            to_broadcaster_user_login: "null".to_string(),
+            to_broadcaster_user_name: "Null".to_string(),
+            viewers,
+        })
+    }
+
+    #[test]
+    fn level_for_grows_the_step_each_level() {
+        assert_eq!(level_for(0), 1);
+        assert_eq!(level_for(99), 1);
+        assert_eq!(level_for(100), 2);
+        assert_eq!(level_for(299), 2);
+        assert_eq!(level_for(300), 3);
+        assert_eq!(level_for(600), 4);
+        assert_eq!(level_for(-5), 1);
+    }
+
+    #[test]
+    fn award_for_gives_at_least_a_little_xp() {
+        let award = award_for(&cheer(5)).unwrap();
+        assert_eq!(
+            (award.user_id, award.user_name.as_str(), award.xp),
+            (7, "sam", 1)
+        );
+        assert_eq!(award_for(&cheer(1000)).unwrap().xp, 100);
+
+        let award = award_for(&raid(3)).unwrap();
+        assert_eq!((award.user_id, award.xp), (42, 20));
+        assert_eq!(award_for(&raid(50)).unwrap().xp, 100);
+    }
+
+    #[test]
+    fn award_for_skips_anonymous_cheers() {
+        let TwitchEvent::ChannelCheer(mut anonymous) = cheer(1000) else {
+            unreachable!()
+        };
+        anonymous.user_id = messages::ANONYMOUS_VIEWER_ID;
+        assert!(award_for(&TwitchEvent::ChannelCheer(anonymous)).is_none());
+    }
+
+    #[test]
+    fn class_race_and_items_stay_the_same() {
+        assert_eq!(class_and_race(7), class_and_race(7));
+        assert_eq!(item_for(7, 3), item_for(7, 3));
+    }
+
+    #[test]
+    fn sheet_prompt_mentions_the_inventory_and_new_level() {
+        let mut sheet = CharacterSheet {
+            user_name: "sam".to_string(),
+            class: "bard".to_string(),
+            race: "gnome".to_string(),
+            level: 2,
+            xp: 150,
+            inventory: vec![],
+            leveled_up: false,
+        };
+        assert_eq!(
+            sheet_prompt(&sheet),
+            "sam is a level 2 gnome bard in the Null party."
+        );
+
+        sheet.inventory = vec!["a lucky coin".to_string(), "a talking skull".to_string()];
+        sheet.leveled_up = true;
+        assert_eq!(
+            sheet_prompt(&sheet),
+            "sam is a level 2 gnome bard in the Null party, carrying a lucky coin, a talking \
+             skull. They just reached level 2, make it part of the story."
+        );
+    }
+}
@@ -0,0 +1,333 @@
+//! Profiles of the viewers behind events.
+//!
+//! Follows, subs and cheers only carry the viewer's name and id. On their way to the AIManager
+//! they pass through here, where the viewer's avatar, display name and broadcaster type are
+//! attached from sqlite, or looked up when the cached profile is older than the ttl. Events
+//! arriving together, like a wave of follows, are looked up in one request, and a lookup that
+//! takes too long lets the events go on without profiles.
+use std::time::Duration;
+
+use async_trait::async_trait;
+use messages::{NewTwitchEventMessage, ViewerProfile};
+use sqlx::SqlitePool;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::sqlite;
+
+/// How long the first event with a viewer waits for others to be looked up with it.
+pub const BATCH_WINDOW: Duration = Duration::from_millis(250);
+/// Most users Helix returns for one request.
+pub const BATCH_SIZE: usize = 100;
+/// Longest a lookup may hold up the events.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Looks up users by their ids, at most [`BATCH_SIZE`] at once.
+#[async_trait]
+pub trait UserLookup: Send + Sync {
+    /// The profiles of the users found, users that weren't are left out.
+    async fn viewer_profiles(&self, user_ids: &[i64]) -> anyhow::Result<Vec<(i64, ViewerProfile)>>;
+}
+
+pub struct ViewerProfiles {
+    lookup: Box<dyn UserLookup>,
+    pool: SqlitePool,
+    ttl: Duration,
+    pub timeout: Duration,
+}
+
+impl ViewerProfiles {
+    pub fn new(lookup: Box<dyn UserLookup>, pool: SqlitePool, ttl: Duration) -> ViewerProfiles {
+        ViewerProfiles {
+            lookup,
+            pool,
+            ttl,
+            timeout: LOOKUP_TIMEOUT,
+        }
+    }
+
+    /// Starts attaching profiles to the events sent to the returned sender, which go on to
+    /// `events` in the order they came.
+    pub fn spawn(
+        self,
+        events: UnboundedSender<NewTwitchEventMessage>,
+    ) -> UnboundedSender<NewTwitchEventMessage> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(self.run(receiver, events));
+        sender
+    }
+
+    async fn run(
+        self,
+        mut receiver: UnboundedReceiver<NewTwitchEventMessage>,
+        events: UnboundedSender<NewTwitchEventMessage>,
+    ) {
+        while let Some(first) = receiver.recv().await {
+            let mut batch = vec![first];
+            // Events without a viewer, like hype trains, aren't held back
+            if batch[0].event.viewer_id().is_some() {
+                let window = tokio::time::sleep(BATCH_WINDOW);
+                tokio::pin!(window);
+                while batch.len() < BATCH_SIZE {
+                    tokio::select! {
+                        message = receiver.recv() => match message {
+                            Some(message) => batch.push(message),
+                            None => break,
+                        },
+                        _ = &mut window => break,
+                    }
+                }
+            }
+
+            self.attach_profiles(&mut batch).await;
+            for message in batch {
+                if events.send(message).is_err() {
+                    println!("No one is listening for events, stopping the profile lookup");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Attaches the profiles of the viewers in the batch, cached or looked up. Events whose
+    /// viewer couldn't be looked up go on without one.
+    async fn attach_profiles(&self, batch: &mut [NewTwitchEventMessage]) {
+        let mut user_ids: Vec<i64> = vec![];
+        for user_id in batch.iter().filter_map(|message| message.event.viewer_id()) {
+            if !user_ids.contains(&user_id) {
+                user_ids.push(user_id);
+            }
+        }
+        if user_ids.is_empty() {
+            return;
+        }
+
+        let mut profiles = self.cached(&user_ids).await;
+        let missing: Vec<i64> = user_ids
+            .into_iter()
+            .filter(|user_id| !profiles.iter().any(|(id, _)| id == user_id))
+            .collect();
+        if !missing.is_empty() {
+            let looked_up =
+                tokio::time::timeout(self.timeout, self.lookup.viewer_profiles(&missing))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("lookup timed out"))
+                    .and_then(|profiles| profiles);
+            match looked_up {
+                Ok(looked_up) => {
+                    for (user_id, profile) in looked_up {
+                        self.save(user_id, &profile).await;
+                        profiles.push((user_id, profile));
+                    }
+                }
+                Err(e) => println!("Could not look up {} viewer profiles: {}", missing.len(), e),
+            }
+        }
+
+        for message in batch.iter_mut() {
+            let Some(user_id) = message.event.viewer_id() else {
+                continue;
+            };
+            if let Some((_, profile)) = profiles.iter().find(|(id, _)| *id == user_id) {
+                message.event.set_profile(profile.clone());
+            }
+        }
+    }
+
+    async fn cached(&self, user_ids: &[i64]) -> Vec<(i64, ViewerProfile)> {
+        let cached = match self.pool.acquire().await {
+            Ok(conn) => sqlite::get_viewer_profiles(conn, user_ids, self.ttl.as_secs_f64()).await,
+            Err(e) => Err(e.into()),
+        };
+        cached.unwrap_or_else(|e| {
+            println!("Could not read the cached viewer profiles: {}", e);
+            vec![]
+        })
+    }
+
+    async fn save(&self, user_id: i64, profile: &ViewerProfile) {
+        let saved = match self.pool.acquire().await {
+            Ok(conn) => sqlite::save_viewer_profile(conn, user_id, profile).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = saved {
+            println!("Could not cache the profile of {}: {}", user_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use messages::{FollowEvent, HypeTrainEvent, TwitchEvent};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    /// Knows every user but `unknown`, and remembers the ids it was asked for.
+    struct FakeLookup {
+        calls: Arc<Mutex<Vec<Vec<i64>>>>,
+        unknown: i64,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl UserLookup for FakeLookup {
+        async fn viewer_profiles(
+            &self,
+            user_ids: &[i64],
+        ) -> anyhow::Result<Vec<(i64, ViewerProfile)>> {
+            self.calls.lock().unwrap().push(user_ids.to_vec());
+            tokio::time::sleep(self.delay).await;
+            Ok(user_ids
+                .iter()
+                .filter(|user_id| **user_id != self.unknown)
+                .map(|user_id| (*user_id, profile(*user_id)))
+                .collect())
+        }
+    }
+
+    fn profile(user_id: i64) -> ViewerProfile {
+        ViewerProfile {
+            display_name: format!("Viewer{}", user_id),
+            profile_image_url: Some(format!("https://example.com/{}.png", user_id)),
+            broadcaster_type: String::new(),
+        }
+    }
+
+    fn follow(user_id: i64) -> NewTwitchEventMessage {
+        NewTwitchEventMessage {
+            event: TwitchEvent::ChannelFollow(FollowEvent {
+                user_name: format!("viewer{}", user_id),
+                user_id,
+                broadcaster_user_id: 1,
+                profile: None,
+            }),
+            message_id: format!("follow-{}", user_id),
+            message_at: "2024-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    fn hype_train() -> NewTwitchEventMessage {
+        NewTwitchEventMessage {
+            event: TwitchEvent::ChannelHypeTrainBegin(HypeTrainEvent {
+                level: 1,
+                total: 100,
+                goal: Some(500),
+            }),
+            message_id: "hype-train".to_string(),
+            message_at: "2024-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    /// Starts the lookup, returning where to send events, where they come out and the ids
+    /// the lookup was asked for.
+    async fn spawn(
+        unknown: i64,
+        delay: Duration,
+    ) -> (
+        UnboundedSender<NewTwitchEventMessage>,
+        UnboundedReceiver<NewTwitchEventMessage>,
+        Arc<Mutex<Vec<Vec<i64>>>>,
+    ) {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let lookup = FakeLookup {
+            calls: calls.clone(),
+            unknown,
+            delay,
+        };
+        let mut profiles =
+            ViewerProfiles::new(Box::new(lookup), pool().await, Duration::from_secs(60 * 60));
+        profiles.timeout = Duration::from_millis(100);
+        let (events, received) = mpsc::unbounded_channel();
+        (profiles.spawn(events), received, calls)
+    }
+
+    async fn receive(
+        received: &mut UnboundedReceiver<NewTwitchEventMessage>,
+        count: usize,
+    ) -> Vec<NewTwitchEventMessage> {
+        let mut messages = vec![];
+        for _ in 0..count {
+            let message = tokio::time::timeout(Duration::from_secs(5), received.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn viewers_arriving_together_are_looked_up_once_each() {
+        let (sender, mut received, calls) = spawn(-1, Duration::ZERO).await;
+        sender.send(follow(2)).unwrap();
+        sender.send(follow(3)).unwrap();
+        sender.send(hype_train()).unwrap();
+        sender.send(follow(2)).unwrap();
+
+        let messages = receive(&mut received, 4).await;
+        let ids: Vec<&str> = messages.iter().map(|m| m.message_id.as_str()).collect();
+        assert_eq!(ids, vec!["follow-2", "follow-3", "hype-train", "follow-2"]);
+        assert_eq!(messages[0].event.profile(), Some(&profile(2)));
+        assert_eq!(messages[1].event.profile(), Some(&profile(3)));
+        assert_eq!(messages[2].event.profile(), None);
+        assert_eq!(messages[3].event.profile(), Some(&profile(2)));
+        assert_eq!(*calls.lock().unwrap(), vec![vec![2, 3]]);
+
+        // The next time they come from the cache
+        sender.send(follow(3)).unwrap();
+        sender.send(follow(4)).unwrap();
+        let messages = receive(&mut received, 2).await;
+        assert_eq!(messages[0].event.profile(), Some(&profile(3)));
+        assert_eq!(messages[1].event.profile(), Some(&profile(4)));
+        assert_eq!(*calls.lock().unwrap(), vec![vec![2, 3], vec![4]]);
+    }
+
+    #[tokio::test]
+    async fn events_without_a_viewer_are_not_held_back() {
+        let (sender, mut received, calls) = spawn(-1, Duration::ZERO).await;
+        sender.send(hype_train()).unwrap();
+
+        let message = tokio::time::timeout(BATCH_WINDOW / 2, received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.message_id, "hype-train");
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn viewers_that_are_not_found_go_on_without_a_profile() {
+        let (sender, mut received, _) = spawn(3, Duration::ZERO).await;
+        sender.send(follow(2)).unwrap();
+        sender.send(follow(3)).unwrap();
+
+        let messages = receive(&mut received, 2).await;
+        assert_eq!(messages[0].event.profile(), Some(&profile(2)));
+        assert_eq!(messages[1].event.profile(), None);
+    }
+
+    #[tokio::test]
+    async fn a_slow_lookup_lets_the_events_go_on_without_profiles() {
+        let (sender, mut received, calls) = spawn(-1, Duration::from_secs(60)).await;
+        sender.send(follow(2)).unwrap();
+
+        let messages = receive(&mut received, 1).await;
+        assert_eq!(messages[0].message_id, "follow-2");
+        assert_eq!(messages[0].event.profile(), None);
+        assert_eq!(*calls.lock().unwrap(), vec![vec![2]]);
+    }
+}
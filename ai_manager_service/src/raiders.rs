@@ -0,0 +1,129 @@
+//! What the raiding channel was up to, for the raid story.
+//!
+//! The game, stream title and profile image of the raiding channel are looked up before the
+//! story is asked for, and kept in sqlite for `ttl` so a channel raiding again soon doesn't
+//! need another lookup. The game and title go in the prompt and the profile image becomes the
+//! alert's image.
+use std::time::Duration;
+
+use async_trait::async_trait;
+use messages::RaidEvent;
+
+use crate::{sqlite, AIManager};
+
+/// Longest a lookup may hold up the raid story.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default)]
+pub struct ChannelInfo {
+    pub game_name: Option<String>,
+    pub title: Option<String>,
+    pub profile_image_url: Option<String>,
+}
+
+/// Looks up a channel by its broadcaster id.
+#[async_trait]
+pub trait ChannelLookup: Send + Sync {
+    async fn channel_info(&self, broadcaster_id: &str) -> anyhow::Result<ChannelInfo>;
+}
+
+pub struct RaiderLookup {
+    lookup: Box<dyn ChannelLookup>,
+    ttl: Duration,
+}
+
+impl RaiderLookup {
+    pub fn new(lookup: Box<dyn ChannelLookup>, ttl: Duration) -> RaiderLookup {
+        RaiderLookup { lookup, ttl }
+    }
+}
+
+impl AIManager {
+    /// The raiding channel's game, title and profile image, cached or looked up. `None` when
+    /// there is no lookup or it failed. Flagged titles and game names are left out.
+    pub(crate) async fn raider_info(&self, raid: &RaidEvent) -> Option<ChannelInfo> {
+        let raider_lookup = self.raider_lookup.as_ref()?;
+        let id = &raid.from_broadcaster_user_id;
+
+        let cached = match self.sqlite_pool.acquire().await {
+            Ok(conn) => sqlite::get_raider_channel(conn, id).await,
+            Err(e) => Err(e.into()),
+        };
+        let info = match cached {
+            Ok(Some((info, seconds_ago))) if seconds_ago < raider_lookup.ttl.as_secs_f64() => info,
+            cached => {
+                if let Err(e) = &cached {
+                    println!("Could not read the cached channel of {}: {}", id, e);
+                }
+                let looked_up =
+                    tokio::time::timeout(LOOKUP_TIMEOUT, raider_lookup.lookup.channel_info(id))
+                        .await
+                        .map_err(|_| anyhow::anyhow!("lookup timed out"))
+                        .and_then(|info| info);
+                match looked_up {
+                    Ok(info) => {
+                        let saved = match self.sqlite_pool.acquire().await {
+                            Ok(conn) => sqlite::save_raider_channel(conn, id, &info).await,
+                            Err(e) => Err(e.into()),
+                        };
+                        if let Err(e) = saved {
+                            println!("Could not cache the channel of {}: {}", id, e);
+                        }
+                        info
+                    }
+                    // An old lookup is better than none
+                    Err(e) => {
+                        println!("Could not look up the channel of {}: {}", id, e);
+                        cached.ok().flatten()?.0
+                    }
+                }
+            }
+        };
+
+        Some(ChannelInfo {
+            game_name: info
+                .game_name
+                .filter(|game| self.safety.check_user_text(game).is_ok()),
+            title: info
+                .title
+                .filter(|title| self.safety.check_user_text(title).is_ok())
+                .map(|title| self.safety.sanitize(&title)),
+            profile_image_url: info.profile_image_url,
+        })
+    }
+}
+
+/// What goes in the raid prompt about where the raiders come from.
+pub fn raider_prompt(info: &ChannelInfo) -> String {
+    let mut prompt = String::new();
+    if let Some(game) = &info.game_name {
+        prompt.push_str(&format!(
+            " They are adventurers returning from the lands of {}.",
+            game
+        ));
+    }
+    if let Some(title) = &info.title {
+        prompt.push_str(&format!(" Their last quest was called \"{}\".", title));
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raider_prompt_mentions_what_is_known() {
+        assert_eq!(raider_prompt(&ChannelInfo::default()), "");
+        let info = ChannelInfo {
+            game_name: Some("Elden Ring".to_string()),
+            title: Some("no hit run".to_string()),
+            profile_image_url: None,
+        };
+        assert_eq!(
+            raider_prompt(&info),
+            " They are adventurers returning from the lands of Elden Ring. Their last quest was \
+             called \"no hit run\"."
+        );
+    }
+}
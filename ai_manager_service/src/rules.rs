@@ -0,0 +1,341 @@
+//! Rules that change how an alert is made based on what happened.
+//!
+//! Each rule has conditions on the event and actions to take when they all hold. Every
+//! matching rule applies, earlier rules win when two set the same action. For example
+//!
+//! ```json
+//! { "rules": [
+//!     { "name": "big raid", "when": { "event": "raid", "min_viewers": 50 },
+//!       "then": { "prompt": "tell me a legendary story about ...", "priority": 10 } }
+//! ] }
+//! ```
+use messages::{DisplayMessage, NullSubTier, TwitchEvent};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RulesConfig {
+    pub rules: Vec<Rule>,
+}
+
+impl RulesConfig {
+    pub fn load(path: &str) -> anyhow::Result<RulesConfig> {
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rule {
+    /// Shown in the logs when the rule matches.
+    pub name: String,
+    #[serde(default)]
+    pub when: Conditions,
+    pub then: Actions,
+}
+
+/// Everything left out matches any event. Conditions on a field the event doesn't have,
+/// like `min_bits` on a raid, never match.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Conditions {
+    /// See [`TwitchEvent::event_type`].
+    pub event: Option<String>,
+    pub min_bits: Option<i64>,
+    pub min_viewers: Option<i64>,
+    /// "1", "2", "3" or "prime".
+    pub tier: Option<String>,
+    pub min_cumulative_months: Option<i64>,
+    /// Matches every this many months, 12 for yearly resub anniversaries.
+    pub cumulative_months_every: Option<i64>,
+    /// User names, ignoring case.
+    pub users: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Actions {
+    /// Used instead of the event's usual prompt. `{user}`, `{viewers}`, `{bits}`, `{months}`,
+    /// `{tier}` and `{total}` are filled in from the event.
+    pub prompt: Option<String>,
+    pub sound_url: Option<String>,
+    /// Theme the alert is rendered with instead of the active one.
+    pub theme: Option<String>,
+    /// Alerts with a higher priority are shown before the ones already waiting.
+    pub priority: Option<i32>,
+    pub display_time_ms: Option<usize>,
+    /// Shows the neutral story without asking the model.
+    pub skip_ai: Option<bool>,
+}
+
+impl Actions {
+    /// Fills in everything these actions leave out from `other`.
+    fn or(self, other: &Actions) -> Actions {
+        Actions {
+            prompt: self.prompt.or_else(|| other.prompt.clone()),
+            sound_url: self.sound_url.or_else(|| other.sound_url.clone()),
+            theme: self.theme.or_else(|| other.theme.clone()),
+            priority: self.priority.or(other.priority),
+            display_time_ms: self.display_time_ms.or(other.display_time_ms),
+            skip_ai: self.skip_ai.or(other.skip_ai),
+        }
+    }
+
+    pub fn skip_ai(&self) -> bool {
+        self.skip_ai.unwrap_or(false)
+    }
+
+    /// The rule's prompt with the placeholders filled in from the event.
+    pub fn prompt_for(&self, event: &TwitchEvent) -> Option<String> {
+        let prompt = self.prompt.as_ref()?;
+        let fields = EventFields::of(event);
+        Some(
+            prompt
+                .replace("{user}", event.user_name().unwrap_or("anonymous"))
+                .replace("{viewers}", &fields.viewers.unwrap_or(0).to_string())
+                .replace("{bits}", &fields.bits.unwrap_or(0).to_string())
+                .replace("{months}", &fields.months.unwrap_or(0).to_string())
+                .replace("{tier}", fields.tier.unwrap_or("1"))
+                .replace("{total}", &fields.total.unwrap_or(0).to_string()),
+        )
+    }
+
+    /// Sets the sound, theme, priority and display time on the alert.
+    pub fn apply(&self, message: &mut DisplayMessage) {
+        if let Some(sound_url) = &self.sound_url {
+            message.sound_url = sound_url.clone();
+        }
+        if let Some(theme) = &self.theme {
+            message.theme = Some(theme.clone());
+        }
+        if let Some(priority) = self.priority {
+            message.priority = priority;
+        }
+        if let Some(display_time) = self.display_time_ms {
+            message.display_time = display_time;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new(config: RulesConfig) -> Rules {
+        Rules {
+            rules: config.rules,
+        }
+    }
+
+    pub fn matching<'a>(&'a self, event: &'a TwitchEvent) -> impl Iterator<Item = &'a Rule> {
+        self.rules.iter().filter(|rule| rule.when.matches(event))
+    }
+
+    /// The actions of every rule matching the event.
+    pub fn actions_for(&self, event: &TwitchEvent) -> Actions {
+        self.matching(event)
+            .fold(Actions::default(), |actions, rule| actions.or(&rule.then))
+    }
+}
+
+impl Conditions {
+    pub fn matches(&self, event: &TwitchEvent) -> bool {
+        let fields = EventFields::of(event);
+        let at_least = |min: Option<i64>, value: Option<i64>| match min {
+            Some(min) => value.is_some_and(|value| value >= min),
+            None => true,
+        };
+
+        self.event
+            .iter()
+            .all(|event_type| event_type == event.event_type())
+            && at_least(self.min_bits, fields.bits)
+            && at_least(self.min_viewers, fields.viewers)
+            && at_least(self.min_cumulative_months, fields.months)
+            && self
+                .tier
+                .iter()
+                .all(|tier| fields.tier.is_some_and(|event_tier| event_tier == tier))
+            && self.cumulative_months_every.iter().all(|every| {
+                *every > 0
+                    && fields
+                        .months
+                        .is_some_and(|months| months > 0 && months % every == 0)
+            })
+            && (self.users.is_empty()
+                || event.user_name().is_some_and(|name| {
+                    self.users
+                        .iter()
+                        .any(|user| user.eq_ignore_ascii_case(name))
+                }))
+    }
+}
+
+/// The numbers rules look at, `None` where the event doesn't have them.
+struct EventFields<'a> {
+    bits: Option<i64>,
+    viewers: Option<i64>,
+    months: Option<i64>,
+    tier: Option<&'a str>,
+    total: Option<i64>,
+}
+
+impl<'a> EventFields<'a> {
+    fn of(event: &'a TwitchEvent) -> EventFields<'a> {
+        let mut fields = EventFields {
+            bits: None,
+            viewers: None,
+            months: None,
+            tier: None,
+            total: None,
+        };
+        match event {
+            TwitchEvent::ChannelFollow(_) => {}
+            TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+                fields.months = Some(sub.cumulative_months);
+                fields.tier = Some(tier_name(&sub.tier));
+            }
+            TwitchEvent::ChannelRaid(raid) => fields.viewers = Some(raid.viewers),
+            TwitchEvent::ChannelSubGift(gift) => {
+                fields.tier = Some(tier_name(&gift.tier));
+                fields.total = Some(gift.total);
+            }
+            TwitchEvent::ChannelCheer(cheer) => fields.bits = Some(cheer.bits),
+            TwitchEvent::FollowBurst(burst) => fields.total = Some(burst.follows),
+            TwitchEvent::ChannelHypeTrainBegin(_)
+            | TwitchEvent::ChannelHypeTrainEnd(_)
+            | TwitchEvent::StreamOnline(_)
+            | TwitchEvent::StreamOffline(_) => {}
+        }
+        fields
+    }
+}
+
+fn tier_name(tier: &NullSubTier) -> &str {
+    match tier {
+        NullSubTier::Tier1(_) => "1",
+        NullSubTier::Tier2(_) => "2",
+        NullSubTier::Tier3(_) => "3",
+        NullSubTier::Prime(_) => "prime",
+        NullSubTier::Other(tier) => tier,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{CheerEvent, RaidEvent, SubscribeEvent};
+    use serde_json::json;
+
+    use super::*;
+
+    fn when(conditions: serde_json::Value) -> Conditions {
+        serde_json::from_value(conditions).unwrap()
+    }
+
+    fn cheer(user_name: &str, bits: i64) -> TwitchEvent {
+        TwitchEvent::ChannelCheer(CheerEvent {
+            user_name: user_name.to_string(),
+            user_id: 1,
+            bits,
+            message: String::new(),
+            profile: None,
+        })
+    }
+
+    fn raid(viewers: i64) -> TwitchEvent {
+        TwitchEvent::ChannelRaid(RaidEvent {
+            from_broadcaster_user_id: "3".to_string(),
+            from_broadcaster_user_login: "friend".to_string(),
+            from_broadcaster_user_name: "Friend".to_string(),
+            to_broadcaster_user_id: "2".to_string(),
+            to_broadcaster_user_login: "null".to_string(),
+            to_broadcaster_user_name: "Null".to_string(),
+            viewers,
+        })
+    }
+
+    fn resub(cumulative_months: i64, tier: NullSubTier) -> TwitchEvent {
+        TwitchEvent::ChannelResubscribe(SubscribeEvent {
+            broadcaster_user_id: 2,
+            broadcaster_user_name: "Null".to_string(),
+            user_name: "loyal".to_string(),
+            user_id: 1,
+            is_gift: false,
+            tier,
+            cumulative_months,
+            duration_months: 1,
+            message: String::new(),
+            streak_months: None,
+            profile: None,
+        })
+    }
+
+    #[test]
+    fn matches_everything_without_conditions() {
+        assert!(Conditions::default().matches(&cheer("a", 1)));
+        assert!(Conditions::default().matches(&raid(1)));
+    }
+
+    #[test]
+    fn matches_the_event_type_and_minimums() {
+        let big_raid = when(json!({ "event": "raid", "min_viewers": 50 }));
+        assert!(big_raid.matches(&raid(50)));
+        assert!(!big_raid.matches(&raid(49)));
+        assert!(!big_raid.matches(&cheer("a", 1000)));
+    }
+
+    #[test]
+    fn never_matches_on_fields_the_event_does_not_have() {
+        assert!(!when(json!({ "min_bits": 1 })).matches(&raid(100)));
+        assert!(!when(json!({ "tier": "1" })).matches(&cheer("a", 100)));
+    }
+
+    #[test]
+    fn matches_tiers_and_anniversaries() {
+        let yearly = when(json!({ "cumulative_months_every": 12, "tier": "3" }));
+        assert!(yearly.matches(&resub(24, NullSubTier::Tier3("3000".to_string()))));
+        assert!(!yearly.matches(&resub(13, NullSubTier::Tier3("3000".to_string()))));
+        assert!(!yearly.matches(&resub(12, NullSubTier::Tier1("1000".to_string()))));
+        assert!(!when(json!({ "cumulative_months_every": 0 }))
+            .matches(&resub(12, NullSubTier::Tier1("1000".to_string()))));
+    }
+
+    #[test]
+    fn matches_users_ignoring_case() {
+        let regulars = when(json!({ "users": ["NullVoxPopuli"] }));
+        assert!(regulars.matches(&cheer("nullvoxpopuli", 1)));
+        assert!(!regulars.matches(&cheer("someone", 1)));
+    }
+
+    #[test]
+    fn actions_for_lets_earlier_rules_win() {
+        let rules = Rules::new(
+            serde_json::from_value(json!({ "rules": [
+                { "name": "big cheer", "when": { "min_bits": 1000 }, "then": { "priority": 10 } },
+                { "name": "cheers", "when": { "event": "cheer" },
+                  "then": { "priority": 1, "sound_url": "/cheer.wav" } },
+            ] }))
+            .unwrap(),
+        );
+        let big = rules.actions_for(&cheer("a", 5000));
+        assert_eq!(big.priority, Some(10));
+        assert_eq!(big.sound_url.as_deref(), Some("/cheer.wav"));
+        assert_eq!(rules.actions_for(&cheer("a", 5)).priority, Some(1));
+        assert_eq!(rules.actions_for(&raid(5)).priority, None);
+    }
+
+    #[test]
+    fn prompt_for_fills_in_the_placeholders() {
+        let actions = Actions {
+            prompt: Some("{user} cheered {bits} bits and {viewers} viewers".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            actions.prompt_for(&cheer("sam", 500)).as_deref(),
+            Some("sam cheered 500 bits and 0 viewers")
+        );
+        assert_eq!(Actions::default().prompt_for(&cheer("sam", 500)), None);
+    }
+}
@@ -31,9 +31,9 @@ pub async fn write_new_gift_subs_event(
         r#"
 INSERT INTO gift_subs_events (
     broadcaster_user_id, cumulative_total, is_anonymous,
-    tier, total, user_id, user_login, user_name, story_segment 
+    tier, total, user_id, user_login, user_name, story_segment, gifted_at
 )
-VALUES (?, ?, ?, ?, ?, ?, ? ,? ,?)
+VALUES (?, ?, ?, ?, ?, ?, ? ,? ,?, CURRENT_TIMESTAMP)
     "#,
         event.broadcaster_user_id,
         event.cumulative_total,
@@ -108,3 +108,500 @@ WHERE user_id = ?
     .await?;
     Ok(db_results.story_segment)
 }
+
+/// Records the follow, returning how many seconds ago the user last followed,
+/// `None` when this is their first follow.
+pub async fn record_follow(
+    mut conn: PoolConnection<Sqlite>,
+    event: &messages::FollowEvent,
+) -> anyhow::Result<Option<f64>> {
+    let previous = sqlx::query!(
+        r#"
+SELECT (julianday('now') - julianday(followed_at)) * 86400.0 AS "seconds_ago!: f64"
+FROM follow_events
+WHERE user_id = ?
+        "#,
+        event.user_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO follow_events ( account_id, user_id, user_name )
+VALUES ( ?, ?, ? )
+ON CONFLICT(user_id) DO UPDATE SET
+    user_name = excluded.user_name,
+    followed_at = CURRENT_TIMESTAMP,
+    follow_count = follow_count + 1
+        "#,
+        event.broadcaster_user_id,
+        event.user_id,
+        event.user_name,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(previous.map(|row| row.seconds_ago))
+}
+
+/// Adds XP to the party member, making them with the given class and race if they are new.
+pub async fn add_party_xp(
+    mut conn: PoolConnection<Sqlite>,
+    user_id: i64,
+    user_name: &str,
+    class: &str,
+    race: &str,
+    xp: i64,
+) -> anyhow::Result<messages::CharacterSheet> {
+    let row = sqlx::query!(
+        r#"
+INSERT INTO party_members ( user_id, user_name, class, race, xp )
+VALUES ( ?, ?, ?, ?, ? )
+ON CONFLICT(user_id) DO UPDATE SET
+    user_name = excluded.user_name,
+    xp = xp + excluded.xp,
+    updated_at = CURRENT_TIMESTAMP
+RETURNING user_name AS "user_name!", class AS "class!", race AS "race!",
+    level AS "level!: i64", xp AS "xp!: i64", inventory AS "inventory!"
+        "#,
+        user_id,
+        user_name,
+        class,
+        race,
+        xp,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(messages::CharacterSheet {
+        user_name: row.user_name,
+        class: row.class,
+        race: row.race,
+        level: row.level,
+        xp: row.xp,
+        inventory: serde_json::from_str(&row.inventory).unwrap_or_default(),
+        leveled_up: false,
+    })
+}
+
+/// Moves the party member from `from_level` up to `level`. Returns false when another event
+/// got there first.
+pub async fn level_up_party_member(
+    mut conn: PoolConnection<Sqlite>,
+    user_id: i64,
+    from_level: i64,
+    level: i64,
+    inventory: &[String],
+) -> anyhow::Result<bool> {
+    let inventory = serde_json::to_string(inventory)?;
+    let result = sqlx::query!(
+        r#"
+UPDATE party_members
+SET level = ?, inventory = ?, updated_at = CURRENT_TIMESTAMP
+WHERE user_id = ? AND level = ?
+        "#,
+        level,
+        inventory,
+        user_id,
+        from_level,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Starts a boss fight in the campaign, returning its id.
+pub async fn write_new_campaign_battle(
+    mut conn: PoolConnection<Sqlite>,
+    boss_name: &str,
+    level: i64,
+    max_health: i64,
+) -> anyhow::Result<i64> {
+    let result = sqlx::query!(
+        r#"
+INSERT INTO campaign_battles ( boss_name, level, max_health )
+VALUES ( ?, ?, ? )
+        "#,
+        boss_name,
+        level,
+        max_health,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn write_new_campaign_hit(
+    mut conn: PoolConnection<Sqlite>,
+    battle_id: i64,
+    user_name: &str,
+    damage: i64,
+    narration: Option<&str>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO campaign_hits ( battle_id, user_name, damage, narration )
+VALUES ( ?, ?, ?, ? )
+        "#,
+        battle_id,
+        user_name,
+        damage,
+        narration,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn end_campaign_battle(
+    mut conn: PoolConnection<Sqlite>,
+    battle_id: i64,
+    damage_dealt: i64,
+    outcome: &str,
+    story: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+UPDATE campaign_battles
+SET damage_dealt = ?, outcome = ?, story = ?, ended_at = CURRENT_TIMESTAMP
+WHERE id = ?
+        "#,
+        damage_dealt,
+        outcome,
+        story,
+        battle_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn write_stream_started(mut conn: PoolConnection<Sqlite>) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO streams DEFAULT VALUES
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn write_new_cheer_event(
+    mut conn: PoolConnection<Sqlite>,
+    event: &messages::CheerEvent,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO cheer_events ( user_id, user_name, bits )
+VALUES ( ?, ?, ? )
+        "#,
+        event.user_id,
+        event.user_name,
+        event.bits,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn write_new_subscription_event(
+    mut conn: PoolConnection<Sqlite>,
+    event: &messages::SubscribeEvent,
+    tier: &str,
+    is_resub: bool,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO subscription_events ( user_id, user_name, tier, is_gift, is_resub, cumulative_months )
+VALUES ( ?, ?, ?, ?, ?, ? )
+        "#,
+        event.user_id,
+        event.user_name,
+        tier,
+        event.is_gift,
+        is_resub,
+        event.cumulative_months,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Where the goal was left when the app last ran, `None` when it never ran with it.
+pub async fn get_goal_current(
+    mut conn: PoolConnection<Sqlite>,
+    kind: &str,
+) -> anyhow::Result<Option<i64>> {
+    let row = sqlx::query!(
+        r#"
+SELECT current
+FROM goals
+WHERE kind = ?
+        "#,
+        kind,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(row.map(|row| row.current))
+}
+
+/// Saves the goal's progress. A new target clears `reached_at` so it can be celebrated again.
+pub async fn save_goal(
+    mut conn: PoolConnection<Sqlite>,
+    kind: &str,
+    target: i64,
+    current: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO goals ( kind, target, current )
+VALUES ( ?, ?, ? )
+ON CONFLICT(kind) DO UPDATE SET
+    reached_at = CASE WHEN target = excluded.target THEN reached_at END,
+    target = excluded.target,
+    current = excluded.current,
+    updated_at = CURRENT_TIMESTAMP
+        "#,
+        kind,
+        target,
+        current,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Marks the goal reached. Returns false when it already was.
+pub async fn mark_goal_reached(
+    mut conn: PoolConnection<Sqlite>,
+    kind: &str,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+UPDATE goals
+SET reached_at = CURRENT_TIMESTAMP
+WHERE kind = ? AND reached_at IS NULL
+        "#,
+        kind,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Unix seconds the stream that is live went live, `None` when none is.
+pub async fn get_live_stream_started_at(
+    mut conn: PoolConnection<Sqlite>,
+) -> anyhow::Result<Option<i64>> {
+    let row = sqlx::query!(
+        r#"
+SELECT CAST(strftime('%s', started_at) AS INTEGER) AS "started_at!: i64"
+FROM streams
+WHERE ended_at IS NULL
+ORDER BY id DESC
+LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(row.map(|row| row.started_at))
+}
+
+pub async fn end_live_streams(mut conn: PoolConnection<Sqlite>) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+UPDATE streams
+SET ended_at = CURRENT_TIMESTAMP
+WHERE ended_at IS NULL
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Everyone who followed, subscribed, gifted, cheered or raided since the unix time `since`,
+/// as (role, name) in the order they did it. Gifted subs and anonymous gifts and cheers are
+/// left out.
+pub async fn get_session_supporters(
+    mut conn: PoolConnection<Sqlite>,
+    since: i64,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let rows = sqlx::query!(
+        r#"
+SELECT role AS "role!: String", name AS "name!: String"
+FROM (
+    SELECT 'follower' AS role, user_name AS name, followed_at AS at
+    FROM follow_events WHERE followed_at >= datetime(?, 'unixepoch')
+    UNION ALL
+    SELECT 'subscriber', user_name, subscribed_at
+    FROM subscription_events WHERE is_gift = 0 AND subscribed_at >= datetime(?, 'unixepoch')
+    UNION ALL
+    SELECT 'gifter', user_name, gifted_at
+    FROM gift_subs_events WHERE is_anonymous = 0 AND gifted_at >= datetime(?, 'unixepoch')
+    UNION ALL
+    SELECT 'cheerer', user_name, cheered_at
+    FROM cheer_events WHERE user_id <> 0 AND cheered_at >= datetime(?, 'unixepoch')
+    UNION ALL
+    SELECT 'raider', from_broadcaster_user_name, raid_at
+    FROM raid_events WHERE raid_at >= datetime(?, 'unixepoch')
+)
+ORDER BY at
+        "#,
+        since,
+        since,
+        since,
+        since,
+        since,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows.into_iter().map(|row| (row.role, row.name)).collect())
+}
+
+/// How the boss fights that ended since the unix time `since` went, as (boss, outcome).
+pub async fn get_session_battles(
+    mut conn: PoolConnection<Sqlite>,
+    since: i64,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let rows = sqlx::query!(
+        r#"
+SELECT boss_name, outcome AS "outcome!"
+FROM campaign_battles
+WHERE outcome IS NOT NULL AND started_at >= datetime(?, 'unixepoch')
+ORDER BY started_at
+        "#,
+        since,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.boss_name, row.outcome))
+        .collect())
+}
+
+pub async fn write_new_campaign_recap(
+    mut conn: PoolConnection<Sqlite>,
+    session_started_at: i64,
+    story: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO campaign_recaps ( session_started_at, story )
+VALUES ( datetime(?, 'unixepoch'), ? )
+        "#,
+        session_started_at,
+        story,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// The cached channel of a raider and how many seconds ago it was fetched, `None` when it
+/// was never fetched.
+pub async fn get_raider_channel(
+    mut conn: PoolConnection<Sqlite>,
+    broadcaster_user_id: &str,
+) -> anyhow::Result<Option<(crate::raiders::ChannelInfo, f64)>> {
+    let row = sqlx::query!(
+        r#"
+SELECT game_name, title, profile_image_url,
+    (julianday('now') - julianday(fetched_at)) * 86400.0 AS "seconds_ago!: f64"
+FROM raider_channels
+WHERE broadcaster_user_id = ?
+        "#,
+        broadcaster_user_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(row.map(|row| {
+        (
+            crate::raiders::ChannelInfo {
+                game_name: row.game_name,
+                title: row.title,
+                profile_image_url: row.profile_image_url,
+            },
+            row.seconds_ago,
+        )
+    }))
+}
+
+pub async fn save_raider_channel(
+    mut conn: PoolConnection<Sqlite>,
+    broadcaster_user_id: &str,
+    info: &crate::raiders::ChannelInfo,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT OR REPLACE INTO raider_channels ( broadcaster_user_id, game_name, title, profile_image_url )
+VALUES ( ?, ?, ?, ? )
+        "#,
+        broadcaster_user_id,
+        info.game_name,
+        info.title,
+        info.profile_image_url,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// The cached profiles of the viewers, leaving out those never fetched or older than
+/// `max_age_secs`.
+pub async fn get_viewer_profiles(
+    mut conn: PoolConnection<Sqlite>,
+    user_ids: &[i64],
+    max_age_secs: f64,
+) -> anyhow::Result<Vec<(i64, messages::ViewerProfile)>> {
+    let user_ids = serde_json::to_string(user_ids)?;
+    let rows = sqlx::query!(
+        r#"
+SELECT user_id, display_name, profile_image_url, broadcaster_type
+FROM viewer_profiles
+WHERE user_id IN (SELECT value FROM json_each(?))
+AND (julianday('now') - julianday(fetched_at)) * 86400.0 < ?
+        "#,
+        user_ids,
+        max_age_secs,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.user_id,
+                messages::ViewerProfile {
+                    display_name: row.display_name,
+                    profile_image_url: row.profile_image_url,
+                    broadcaster_type: row.broadcaster_type,
+                },
+            )
+        })
+        .collect())
+}
+
+pub async fn save_viewer_profile(
+    mut conn: PoolConnection<Sqlite>,
+    user_id: i64,
+    profile: &messages::ViewerProfile,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT OR REPLACE INTO viewer_profiles ( user_id, display_name, profile_image_url, broadcaster_type )
+VALUES ( ?, ?, ?, ? )
+        "#,
+        user_id,
+        profile.display_name,
+        profile.profile_image_url,
+        profile.broadcaster_type,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
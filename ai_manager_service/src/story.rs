@@ -0,0 +1,143 @@
+//! Stories come back from the model as a json object, so the overlay can style them by mood
+//! and make the important words stand out.
+use messages::Mood;
+use serde::Deserialize;
+
+pub const STORY_PROMPT: &str = r#"You are D&DGPT, when answering any questions, you always answer with a short epic story as a dungeons and dragons dungeon master in 27 words or less.
+Reply with only a json object, no other text, like:
+{"title": "a short headline", "story": "the story", "mood": "epic", "emphasis_words": ["up to 3 words from the story"]}
+mood is one of epic, heroic, funny, mysterious, ominous or wholesome."#;
+
+/// How many times we ask before showing whatever the model said.
+pub const MAX_ATTEMPTS: usize = 3;
+
+/// The prompt asks for 27 words, anything much longer won't fit on the alert.
+pub const MAX_STORY_WORDS: usize = 60;
+
+pub const MAX_EMPHASIS_WORDS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct Story {
+    pub title: String,
+    pub story: String,
+    pub mood: Mood,
+    pub emphasis_words: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct StoryReply {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    story: String,
+    #[serde(default)]
+    mood: String,
+    #[serde(default)]
+    emphasis_words: Vec<String>,
+}
+
+impl Story {
+    /// Used when the model never gets the json right, the reply is shown as it is.
+    pub fn from_text(text: &str) -> Story {
+        Story {
+            title: String::new(),
+            story: text.trim().to_string(),
+            mood: Mood::default(),
+            emphasis_words: vec![],
+        }
+    }
+}
+
+/// Validates the model's reply, repairing what can be repaired.
+///
+/// The json can be wrapped in a code fence or chatter, an unknown mood falls back to the
+/// default and emphasis words that aren't in the story are dropped. A missing or overly long
+/// story can't be repaired and has to be asked for again.
+pub fn parse_story(reply: &str) -> Result<Story, String> {
+    let (Some(start), Some(end)) = (reply.find('{'), reply.rfind('}')) else {
+        return Err("there is no json object in the reply".to_string());
+    };
+    if end < start {
+        return Err("there is no json object in the reply".to_string());
+    }
+    let parsed: StoryReply =
+        serde_json::from_str(&reply[start..=end]).map_err(|e| format!("invalid json: {}", e))?;
+
+    let story = parsed.story.trim().to_string();
+    if story.is_empty() {
+        return Err("the story field is missing or empty".to_string());
+    }
+    let words = story.split_whitespace().count();
+    if words > MAX_STORY_WORDS {
+        return Err(format!(
+            "the story is {} words, it has to be {} or less",
+            words, MAX_STORY_WORDS
+        ));
+    }
+
+    let mood = Mood::parse(&parsed.mood).unwrap_or_else(|| {
+        println!("unknown mood {:?}, using the default", parsed.mood);
+        Mood::default()
+    });
+
+    let lower_story = story.to_lowercase();
+    let emphasis_words = parsed
+        .emphasis_words
+        .into_iter()
+        .map(|word| word.trim().to_string())
+        .filter(|word| !word.is_empty() && lower_story.contains(&word.to_lowercase()))
+        .take(MAX_EMPHASIS_WORDS)
+        .collect();
+
+    Ok(Story {
+        title: parsed.title.trim().to_string(),
+        story,
+        mood,
+        emphasis_words,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_story_reads_the_reply() {
+        let story = parse_story(
+            r#"{"title": "A New Ally", "story": "Brave Sam joins the party.", "mood": "heroic", "emphasis_words": ["Brave"]}"#,
+        )
+        .unwrap();
+        assert_eq!(story.title, "A New Ally");
+        assert_eq!(story.story, "Brave Sam joins the party.");
+        assert_eq!(story.mood, Mood::Heroic);
+        assert_eq!(story.emphasis_words, vec!["Brave"]);
+    }
+
+    #[test]
+    fn parse_story_finds_the_json_in_chatter_and_code_fences() {
+        let story =
+            parse_story("Sure! ```json\n{\"story\": \"The party rests.\"}\n``` Enjoy!").unwrap();
+        assert_eq!(story.story, "The party rests.");
+        assert_eq!(story.title, "");
+    }
+
+    #[test]
+    fn parse_story_repairs_the_mood_and_emphasis_words() {
+        let story = parse_story(
+            r#"{"story": "A goblin sneezes.", "mood": "sneezy", "emphasis_words": ["GOBLIN", "dragon", " "]}"#,
+        )
+        .unwrap();
+        assert_eq!(story.mood, Mood::default());
+        assert_eq!(story.emphasis_words, vec!["GOBLIN"]);
+    }
+
+    #[test]
+    fn parse_story_rejects_replies_it_cannot_repair() {
+        assert!(parse_story("once upon a time").is_err());
+        assert!(parse_story("} backwards {").is_err());
+        assert!(parse_story(r#"{"story": "  "}"#).is_err());
+        assert!(parse_story(r#"{"story": "unterminated}"#).is_err());
+        let long = format!(r#"{{"story": "{}"}}"#, "word ".repeat(MAX_STORY_WORDS + 1));
+        assert!(parse_story(&long).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! Versioned JSON event stream for custom overlays, Stream Deck plugins and other tools.
+//!
+//! The same events are served over a websocket and as Server-Sent Events, and both can be
+//! filtered by event type with `?types=follow,raid`.
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use futures::{Stream, StreamExt};
+use messages::DisplayMessage;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::UnitedStates;
+
+/// Bumped whenever the shape of [`ApiEvent`] changes in a way clients would notice.
+pub const API_VERSION: u32 = 1;
+
+/// How many events a slow client can fall behind before it starts missing them.
+const EVENT_BUFFER: usize = 100;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiEventKind {
+    /// The event was received and is waiting in the queue.
+    Queued,
+    /// The event is being shown on the overlay.
+    Alert,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ApiEvent {
+    pub version: u32,
+    pub seq: u64,
+    pub kind: ApiEventKind,
+    pub event_type: &'static str,
+    /// The message as it is shown on the overlay, `display.payload` is the raw twitch event.
+    pub display: DisplayMessage,
+}
+
+#[derive(Clone)]
+pub struct EventStream {
+    sender: broadcast::Sender<ApiEvent>,
+    seq: Arc<AtomicU64>,
+}
+
+impl EventStream {
+    pub fn new() -> EventStream {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        EventStream {
+            sender,
+            seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn publish(&self, kind: ApiEventKind, display: &DisplayMessage) {
+        let event = ApiEvent {
+            version: API_VERSION,
+            seq: self.seq.fetch_add(1, Ordering::SeqCst) + 1,
+            kind,
+            event_type: display.payload.event_type(),
+            display: display.clone(),
+        };
+        // No one listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Stream of every event published from now on that passes `filter`.
+    pub fn subscribe(&self, filter: EventFilter) -> impl Stream<Item = ApiEvent> {
+        futures::stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(missed)) => {
+                        println!("Event stream client fell behind, missed {} events", missed);
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .filter(move |event| futures::future::ready(filter.matches(event)))
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EventFilterQuery {
+    /// Comma separated event types, e.g. `follow,raid`. Everything if left out.
+    pub types: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    types: HashSet<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &ApiEvent) -> bool {
+        self.types.is_empty() || self.types.contains(event.event_type)
+    }
+}
+
+impl From<EventFilterQuery> for EventFilter {
+    fn from(query: EventFilterQuery) -> Self {
+        let types = query
+            .types
+            .unwrap_or_default()
+            .split(',')
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        EventFilter { types }
+    }
+}
+
+pub async fn events_ws(
+    ws: WebSocketUpgrade,
+    Query(query): Query<EventFilterQuery>,
+    State(state): State<UnitedStates>,
+) -> Response {
+    let events = state.event_stream.subscribe(query.into());
+    ws.on_upgrade(move |socket| send_events(socket, events))
+}
+
+async fn send_events(mut socket: WebSocket, events: impl Stream<Item = ApiEvent>) {
+    let mut events = Box::pin(events);
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else { break };
+                let Ok(json) = serde_json::to_string(&event) else { continue };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            msg = socket.recv() => {
+                // We don't expect anything from the client, just notice when it goes away
+                match msg {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+pub async fn events_sse(
+    Query(query): Query<EventFilterQuery>,
+    State(state): State<UnitedStates>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    sse_events(&state.event_stream, query.into())
+}
+
+fn sse_events(
+    event_stream: &EventStream,
+    filter: EventFilter,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = event_stream.subscribe(filter).map(|event| {
+        let sse_event = Event::default()
+            .id(event.seq.to_string())
+            .json_data(&event)
+            .unwrap_or_else(|_| Event::default().comment("could not serialize event"));
+        Ok(sse_event)
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+    use messages::{FollowEvent, Mood, TwitchEvent};
+
+    use super::*;
+
+    fn follow(user_name: &str) -> DisplayMessage {
+        DisplayMessage {
+            message: format!("a story about {}", user_name),
+            image_url: "none".to_string(),
+            sound_url: "none".to_string(),
+            animation: "none".to_string(),
+            volume: 1.0,
+            display_time: 1000,
+            payload: TwitchEvent::ChannelFollow(FollowEvent {
+                user_name: user_name.to_string(),
+                user_id: 1,
+                broadcaster_user_id: 2,
+                profile: None,
+            }),
+            title: "A Title".to_string(),
+            mood: Mood::Epic,
+            emphasis_words: vec![],
+            flag: None,
+            theme: None,
+            priority: 0,
+            character: None,
+            roll: None,
+        }
+    }
+
+    fn filter(types: &str) -> EventFilter {
+        EventFilterQuery {
+            types: Some(types.to_string()),
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    async fn subscribe_gets_versioned_events_in_order() {
+        let stream = EventStream::new();
+        let mut events = Box::pin(stream.subscribe(EventFilter::default()));
+        stream.publish(ApiEventKind::Queued, &follow("a"));
+        stream.publish(ApiEventKind::Alert, &follow("a"));
+
+        let queued = serde_json::to_value(events.next().await.unwrap()).unwrap();
+        assert_eq!(queued["version"], API_VERSION);
+        assert_eq!(queued["seq"], 1);
+        assert_eq!(queued["kind"], "queued");
+        assert_eq!(queued["event_type"], "follow");
+        assert_eq!(
+            queued["display"]["payload"]["ChannelFollow"]["user_name"],
+            "a"
+        );
+        let alert = events.next().await.unwrap();
+        assert_eq!((alert.seq, alert.event_type), (2, "follow"));
+        assert!(matches!(alert.kind, ApiEventKind::Alert));
+    }
+
+    #[test]
+    fn filter_matches_the_listed_event_types() {
+        let stream = EventStream::new();
+        let mut receiver = stream.sender.subscribe();
+        stream.publish(ApiEventKind::Queued, &follow("a"));
+        let event = receiver.try_recv().unwrap();
+
+        assert!(EventFilter::default().matches(&event));
+        assert!(filter("raid, Follow").matches(&event));
+        assert!(!filter("raid,cheer").matches(&event));
+        assert!(filter(",").matches(&event), "an empty list is everything");
+    }
+
+    #[tokio::test]
+    async fn sse_sends_each_event_as_json_with_its_seq_as_the_id() {
+        let stream = EventStream::new();
+        let response = sse_events(&stream, filter("follow")).into_response();
+        stream.publish(ApiEventKind::Queued, &follow("a"));
+
+        let mut body = response.into_body().into_data_stream();
+        let chunk = body.next().await.unwrap().unwrap();
+        let text = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(text.contains("id: 1\n"), "{}", text);
+        let data = text
+            .lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .unwrap();
+        let event: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(event["version"], API_VERSION);
+        assert_eq!(event["event_type"], "follow");
+    }
+}
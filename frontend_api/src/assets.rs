@@ -0,0 +1,455 @@
+//! Alert sounds and images uploaded from the admin page.
+//!
+//! Files are kept in the uploads dir and served under `/uploads`, with what we know about
+//! them in sqlite. Assigning an asset to an event type updates the [`AlertAssets`] the
+//! AIManager picks the assets for each alert from.
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Multipart, Path, State},
+    http::StatusCode,
+    Form, Json,
+};
+use maud::{html, Markup};
+use messages::{AlertAssets, EVENT_TYPES};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    sqlite::{self, AssetRow, AssignmentRow},
+    UnitedStates,
+};
+
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+pub const MAX_SOUND_BYTES: usize = 10 * 1024 * 1024;
+
+/// Url path the uploads dir is served under.
+pub const UPLOADS_PATH: &str = "/uploads";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AssetKind {
+    Image,
+    Sound,
+}
+
+impl AssetKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssetKind::Image => "image",
+            AssetKind::Sound => "sound",
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        match self {
+            AssetKind::Image => MAX_IMAGE_BYTES,
+            AssetKind::Sound => MAX_SOUND_BYTES,
+        }
+    }
+
+    /// Works out what was uploaded from the file extension, checking the content type and the
+    /// first few bytes of the file agree with it.
+    fn detect(file_name: &str, content_type: &str, bytes: &[u8]) -> Result<AssetKind, String> {
+        let extension = file_name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_lowercase())
+            .unwrap_or_default();
+
+        let (kind, magic_ok) = match extension.as_str() {
+            "png" => (AssetKind::Image, bytes.starts_with(b"\x89PNG")),
+            "jpg" | "jpeg" => (AssetKind::Image, bytes.starts_with(&[0xFF, 0xD8, 0xFF])),
+            "gif" => (AssetKind::Image, bytes.starts_with(b"GIF8")),
+            "webp" => (AssetKind::Image, is_riff(bytes, b"WEBP")),
+            "wav" => (AssetKind::Sound, is_riff(bytes, b"WAVE")),
+            "ogg" => (AssetKind::Sound, bytes.starts_with(b"OggS")),
+            "mp3" => (
+                AssetKind::Sound,
+                bytes.starts_with(b"ID3") || is_mpeg_frame(bytes),
+            ),
+            other => return Err(format!("files of type .{} are not allowed", other)),
+        };
+
+        let expected_type = match kind {
+            AssetKind::Image => "image/",
+            AssetKind::Sound => "audio/",
+        };
+        if !content_type.starts_with(expected_type) {
+            return Err(format!(
+                "{} does not look like a {}, got {}",
+                file_name,
+                kind.as_str(),
+                content_type
+            ));
+        }
+        if !magic_ok {
+            return Err(format!("{} is not a valid .{} file", file_name, extension));
+        }
+        Ok(kind)
+    }
+}
+
+/// An mp3 without an ID3 tag starts right at a frame header: the sync bits, then MPEG 1, 2
+/// or 2.5 layer III, with or without a CRC.
+fn is_mpeg_frame(bytes: &[u8]) -> bool {
+    bytes.len() >= 2
+        && bytes[0] == 0xFF
+        && matches!(bytes[1], 0xFA | 0xFB | 0xF2 | 0xF3 | 0xE2 | 0xE3)
+}
+
+fn is_riff(bytes: &[u8], format: &[u8; 4]) -> bool {
+    bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == format
+}
+
+#[derive(Clone)]
+pub struct AssetManager {
+    pool: SqlitePool,
+    data_dir: PathBuf,
+    alert_assets: Arc<RwLock<AlertAssets>>,
+}
+
+impl AssetManager {
+    pub fn new(
+        pool: SqlitePool,
+        data_dir: String,
+        alert_assets: Arc<RwLock<AlertAssets>>,
+    ) -> AssetManager {
+        AssetManager {
+            pool,
+            data_dir: PathBuf::from(data_dir),
+            alert_assets,
+        }
+    }
+
+    pub fn data_dir(&self) -> &std::path::Path {
+        &self.data_dir
+    }
+
+    pub fn url(file_name: &str) -> String {
+        format!("{}/{}", UPLOADS_PATH, file_name)
+    }
+
+    /// Applies the assignments made from the admin page on top of the configured alert assets.
+    pub async fn apply_assignments(&self) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.data_dir).await?;
+        let conn = self.pool.acquire().await?;
+        let assignments = sqlite::get_assignments(conn).await?;
+
+        let mut alert_assets = self.alert_assets.write().unwrap();
+        for assignment in assignments {
+            set_asset(
+                &mut alert_assets,
+                &assignment.event_type,
+                &assignment.kind,
+                Some(Self::url(&assignment.file_name)),
+            );
+        }
+        Ok(())
+    }
+
+    async fn save(
+        &self,
+        original_name: &str,
+        content_type: &str,
+        bytes: &[u8],
+    ) -> Result<(), (StatusCode, String)> {
+        let kind = AssetKind::detect(original_name, content_type, bytes)
+            .map_err(|e| (StatusCode::UNSUPPORTED_MEDIA_TYPE, e))?;
+        if bytes.len() > kind.max_bytes() {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "{} is too big, {}s can be at most {} MB",
+                    original_name,
+                    kind.as_str(),
+                    kind.max_bytes() / 1024 / 1024
+                ),
+            ));
+        }
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let file_name = format!("{}-{}", millis, sanitize_file_name(original_name));
+
+        tokio::fs::write(self.data_dir.join(&file_name), bytes)
+            .await
+            .map_err(internal_error)?;
+
+        let conn = self.pool.acquire().await.map_err(internal_error)?;
+        sqlite::write_new_asset(
+            conn,
+            &file_name,
+            original_name,
+            kind.as_str(),
+            content_type,
+            bytes.len() as i64,
+        )
+        .await
+        .map_err(internal_error)?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), (StatusCode, String)> {
+        let asset = self.get(id).await?;
+
+        let conn = self.pool.acquire().await.map_err(internal_error)?;
+        sqlite::delete_asset(conn, id)
+            .await
+            .map_err(internal_error)?;
+
+        // Stop using it for any alerts before the file goes away
+        let url = Self::url(&asset.file_name);
+        {
+            let mut alert_assets = self.alert_assets.write().unwrap();
+            let alert_assets = &mut *alert_assets;
+            let sets = std::iter::once(&mut alert_assets.default)
+                .chain(alert_assets.events.values_mut())
+                .chain(alert_assets.moods.values_mut())
+                .chain(alert_assets.viewers.values_mut());
+            for assets in sets {
+                if assets.image_url.as_deref() == Some(&url) {
+                    assets.image_url = None;
+                }
+                if assets.sound_url.as_deref() == Some(&url) {
+                    assets.sound_url = None;
+                }
+            }
+        }
+
+        if let Err(e) = tokio::fs::remove_file(self.data_dir.join(&asset.file_name)).await {
+            println!("could not remove asset file {}: {}", asset.file_name, e);
+        }
+        Ok(())
+    }
+
+    async fn assign(&self, id: i64, event_type: &str) -> Result<(), (StatusCode, String)> {
+        if !EVENT_TYPES.contains(&event_type) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("unknown event type {}", event_type),
+            ));
+        }
+        let asset = self.get(id).await?;
+
+        let conn = self.pool.acquire().await.map_err(internal_error)?;
+        sqlite::assign_asset(conn, event_type, &asset.kind, id)
+            .await
+            .map_err(internal_error)?;
+
+        let mut alert_assets = self.alert_assets.write().unwrap();
+        set_asset(
+            &mut alert_assets,
+            event_type,
+            &asset.kind,
+            Some(Self::url(&asset.file_name)),
+        );
+        Ok(())
+    }
+
+    async fn get(&self, id: i64) -> Result<AssetRow, (StatusCode, String)> {
+        let conn = self.pool.acquire().await.map_err(internal_error)?;
+        sqlite::get_asset(conn, id)
+            .await
+            .map_err(internal_error)?
+            .ok_or((StatusCode::NOT_FOUND, format!("no asset with id {}", id)))
+    }
+
+    async fn list(&self) -> Result<(Vec<AssetRow>, Vec<AssignmentRow>), (StatusCode, String)> {
+        let conn = self.pool.acquire().await.map_err(internal_error)?;
+        let assets = sqlite::get_assets(conn).await.map_err(internal_error)?;
+        let conn = self.pool.acquire().await.map_err(internal_error)?;
+        let assignments = sqlite::get_assignments(conn)
+            .await
+            .map_err(internal_error)?;
+        Ok((assets, assignments))
+    }
+}
+
+fn set_asset(alert_assets: &mut AlertAssets, event_type: &str, kind: &str, url: Option<String>) {
+    let assets = alert_assets
+        .events
+        .entry(event_type.to_string())
+        .or_default();
+    match kind {
+        "image" => assets.image_url = url,
+        "sound" => assets.sound_url = url,
+        other => println!("unknown asset kind {}", other),
+    }
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn manager(state: &UnitedStates) -> Result<&AssetManager, (StatusCode, String)> {
+    state.asset_manager.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "asset manager is not enabled".to_string(),
+    ))
+}
+
+pub async fn list_assets(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    let (assets, assignments) = manager(&state)?.list().await?;
+    Ok(asset_list(assets, assignments))
+}
+
+pub async fn assets_json(
+    State(state): State<UnitedStates>,
+) -> Result<Json<Vec<AssetRow>>, (StatusCode, String)> {
+    let (assets, _) = manager(&state)?.list().await?;
+    Ok(Json(assets))
+}
+
+pub async fn upload_asset(
+    State(state): State<UnitedStates>,
+    mut multipart: Multipart,
+) -> Result<Markup, (StatusCode, String)> {
+    let manager = manager(&state)?;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+    {
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            continue;
+        };
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let bytes = field
+            .bytes()
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        manager.save(&file_name, &content_type, &bytes).await?;
+    }
+
+    let (assets, assignments) = manager.list().await?;
+    Ok(asset_list(assets, assignments))
+}
+
+pub async fn delete_asset(
+    State(state): State<UnitedStates>,
+    Path(id): Path<i64>,
+) -> Result<Markup, (StatusCode, String)> {
+    let manager = manager(&state)?;
+    manager.delete(id).await?;
+    let (assets, assignments) = manager.list().await?;
+    Ok(asset_list(assets, assignments))
+}
+
+#[derive(Deserialize)]
+pub struct AssignForm {
+    pub event_type: String,
+}
+
+pub async fn assign_asset(
+    State(state): State<UnitedStates>,
+    Path(id): Path<i64>,
+    Form(form): Form<AssignForm>,
+) -> Result<Markup, (StatusCode, String)> {
+    let manager = manager(&state)?;
+    manager.assign(id, &form.event_type).await?;
+    let (assets, assignments) = manager.list().await?;
+    Ok(asset_list(assets, assignments))
+}
+
+fn asset_list(assets: Vec<AssetRow>, assignments: Vec<AssignmentRow>) -> Markup {
+    html! {
+        ul id="assets" {
+            @for asset in assets {
+                li {
+                    @if asset.kind == "image" {
+                        img src=(AssetManager::url(&asset.file_name)) height="48";
+                    } @else {
+                        audio controls src=(AssetManager::url(&asset.file_name)) {}
+                    }
+                    span { (format!("{} ({}, {} KB)", asset.original_name, asset.kind, asset.size_bytes / 1024)) }
+                    @for assignment in assignments.iter().filter(|a| a.asset_id == asset.id) {
+                        span class="assigned" { (assignment.event_type) }
+                    }
+                    form hx-post=(format!("/admin/assets/{}/assign", asset.id)) hx-target="#assets" hx-swap="outerHTML" {
+                        select name="event_type" {
+                            @for event_type in EVENT_TYPES {
+                                option value=(event_type) { (event_type) }
+                            }
+                        }
+                        button type="submit" { "Use for" }
+                    }
+                    button hx-delete=(format!("/admin/assets/{}", asset.id)) hx-target="#assets" hx-swap="outerHTML" hx-confirm="Delete this asset?" { "Delete" }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_accepts_files_matching_their_extension() {
+        assert_eq!(
+            AssetKind::detect("hero.PNG", "image/png", b"\x89PNG\r\n\x1a\n"),
+            Ok(AssetKind::Image)
+        );
+        assert_eq!(
+            AssetKind::detect("cheer.wav", "audio/wav", b"RIFF\0\0\0\0WAVEfmt "),
+            Ok(AssetKind::Sound)
+        );
+        assert_eq!(
+            AssetKind::detect("win.gif", "image/gif", b"GIF89a"),
+            Ok(AssetKind::Image)
+        );
+    }
+
+    #[test]
+    fn detect_accepts_tagged_and_untagged_mp3s() {
+        for start in [&b"ID3\x04"[..], &[0xFF, 0xFB], &[0xFF, 0xF3], &[0xFF, 0xF2]] {
+            assert_eq!(
+                AssetKind::detect("horn.mp3", "audio/mpeg", start),
+                Ok(AssetKind::Sound),
+                "{:x?}",
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn detect_rejects_unknown_extensions() {
+        assert!(AssetKind::detect("script.js", "image/png", b"\x89PNG").is_err());
+        assert!(AssetKind::detect("no_extension", "image/png", b"\x89PNG").is_err());
+    }
+
+    #[test]
+    fn detect_rejects_the_wrong_content_type() {
+        assert!(AssetKind::detect("hero.png", "audio/wav", b"\x89PNG").is_err());
+        assert!(AssetKind::detect("horn.mp3", "image/png", b"ID3").is_err());
+    }
+
+    #[test]
+    fn detect_rejects_bytes_that_are_not_the_file_type() {
+        assert!(AssetKind::detect("hero.png", "image/png", b"<svg>").is_err());
+        assert!(AssetKind::detect("cheer.wav", "audio/wav", b"RIFF\0\0\0\0WEBP").is_err());
+        assert!(AssetKind::detect("horn.mp3", "audio/mpeg", &[0xFF]).is_err());
+    }
+}
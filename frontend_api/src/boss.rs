@@ -0,0 +1,115 @@
+//! The hype train boss fight on the overlay, and the admin buttons to start and end one.
+//!
+//! The AIManager runs the fight, we draw it on the `hype_train` topic every time it changes.
+//! Starting and ending by hand sends the same hype train events Twitch would.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{extract::State, http::StatusCode};
+use maud::{html, Markup};
+use messages::{Boss, HypeTrainEvent, NewTwitchEventMessage, TwitchEvent};
+
+use crate::UnitedStates;
+
+/// How often we look for changes to the fight.
+pub const REDRAW_INTERVAL_MS: u64 = 250;
+
+/// The boss panel, empty when there is no fight.
+pub fn boss_html(boss: Option<&Boss>, sequence: u64) -> Markup {
+    html! {
+        div id="boss" data-seq=(sequence) {
+            @if let Some(boss) = boss {
+                div class={ "boss" @if boss.is_defeated() { " defeated" } } {
+                    h2 class="boss-name" { (boss.name) }
+                    div class="health-bar" {
+                        div class="health" style={ "width: " (health_percent(boss)) "%" } {}
+                    }
+                    p class="health-text" { (boss.health) " / " (boss.max_health) }
+                    @if let Some(last_hit) = &boss.last_hit {
+                        p class="last-hit" { (last_hit) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn health_percent(boss: &Boss) -> i64 {
+    if boss.max_health <= 0 {
+        return 0;
+    }
+    boss.health * 100 / boss.max_health
+}
+
+pub async fn get_boss_fight(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    Ok(boss_status(state.boss_battle.get().as_ref()))
+}
+
+pub async fn start_boss_fight(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    send_hype_train(&state, |level| {
+        TwitchEvent::ChannelHypeTrainBegin(HypeTrainEvent {
+            level,
+            total: 0,
+            goal: Some(0),
+        })
+    })?;
+    Ok(html! { p { "Starting the boss fight..." } })
+}
+
+pub async fn end_boss_fight(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    send_hype_train(&state, |level| {
+        TwitchEvent::ChannelHypeTrainEnd(HypeTrainEvent {
+            level,
+            total: 0,
+            goal: None,
+        })
+    })?;
+    Ok(html! { p { "Ending the boss fight..." } })
+}
+
+/// Sends a hype train event at the level of the fight going on, or level 1.
+fn send_hype_train(
+    state: &UnitedStates,
+    event: impl FnOnce(i64) -> TwitchEvent,
+) -> Result<(), (StatusCode, String)> {
+    let Some(sender) = &state.event_sender else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "boss fights can't be started from here".to_string(),
+        ));
+    };
+    let level = state.boss_battle.get().map(|boss| boss.level).unwrap_or(1);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    sender
+        .send(NewTwitchEventMessage {
+            event: event(level),
+            message_id: format!("admin-hype-train-{}", now.as_nanos()),
+            message_at: now.as_secs().to_string(),
+        })
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+fn boss_status(boss: Option<&Boss>) -> Markup {
+    html! {
+        div id="boss-fight" hx-get="/admin/boss" hx-trigger="every 2s" hx-swap="outerHTML" {
+            @match boss {
+                Some(boss) => {
+                    p { (boss.name) ": " (boss.health) " / " (boss.max_health) }
+                    button hx-get="/admin/boss/end" hx-target="#boss-fight-result" { "End Fight" }
+                }
+                None => {
+                    p { "No boss fight" }
+                    button hx-get="/admin/boss/start" hx-target="#boss-fight-result" { "Start Fight" }
+                }
+            }
+            div id="boss-fight-result" {}
+        }
+    }
+}
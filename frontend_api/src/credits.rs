@@ -0,0 +1,83 @@
+//! The credits roll on the overlay, and the admin buttons to start and stop it.
+//!
+//! The AIManager puts the credits together when the stream goes offline, we roll them on the
+//! `credits` topic. Starting them by hand sends the same stream offline event Twitch would.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{extract::State, http::StatusCode};
+use maud::{html, Markup};
+use messages::{CreditsRoll, NewTwitchEventMessage, StreamOfflineEvent, TwitchEvent};
+
+use crate::UnitedStates;
+
+/// How often we look for new credits.
+pub const REDRAW_INTERVAL_MS: u64 = 500;
+
+/// Seconds the roll takes before the first line, and for each line after.
+const ROLL_BASE_SECS: usize = 10;
+const ROLL_SECS_PER_LINE: f64 = 1.5;
+
+/// The credits, empty when they are not rolling.
+pub fn credits_html(roll: Option<&CreditsRoll>, sequence: u64) -> Markup {
+    html! {
+        div id="credits" data-seq=(sequence) {
+            @if let Some(roll) = roll {
+                div class="credits" {
+                    div class="credits-roll" style={ "animation-duration: " (roll_secs(roll)) "s" } {
+                        h1 class="credits-title" { "The Null Party" }
+                        @for section in &roll.sections {
+                            h2 class="credits-section" { (section.title) }
+                            @for name in &section.names {
+                                p class="credits-name" { (name) }
+                            }
+                        }
+                        h2 class="credits-section" { "Epilogue" }
+                        p class="credits-epilogue" { (roll.epilogue) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn roll_secs(roll: &CreditsRoll) -> f64 {
+    let lines: usize = roll
+        .sections
+        .iter()
+        .map(|section| section.names.len() + 1)
+        .sum::<usize>()
+        + roll.epilogue.split(' ').count() / 10
+        + 2;
+    ROLL_BASE_SECS as f64 + lines as f64 * ROLL_SECS_PER_LINE
+}
+
+pub async fn start_credits(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    let Some(sender) = &state.event_sender else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "the credits can't be started from here".to_string(),
+        ));
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    sender
+        .send(NewTwitchEventMessage {
+            event: TwitchEvent::StreamOffline(StreamOfflineEvent {
+                broadcaster_user_name: "the admin page".to_string(),
+            }),
+            message_id: format!("admin-stream-offline-{}", now.as_nanos()),
+            message_at: now.as_secs().to_string(),
+        })
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(html! { p { "Writing the epilogue, the credits roll when it's done..." } })
+}
+
+pub async fn stop_credits(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    state.credits.stop();
+    Ok(html! { p { "Credits stopped" } })
+}
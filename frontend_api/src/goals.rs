@@ -0,0 +1,31 @@
+//! Progress bars for the goals on the overlay.
+//!
+//! The AIManager counts the goals up, we draw them on the `goals` topic every time they change.
+use maud::{html, Markup};
+use messages::Goal;
+
+/// How often we look for changes to the goals.
+pub const REDRAW_INTERVAL_MS: u64 = 500;
+
+pub fn goals_html(goals: &[Goal], sequence: u64) -> Markup {
+    html! {
+        div id="goals" data-seq=(sequence) {
+            @for goal in goals {
+                div class={ "goal goal-" (goal.kind.as_str()) @if goal.is_reached() { " reached" } } {
+                    p class="goal-label" { (goal.label) }
+                    div class="goal-bar" {
+                        div class="goal-progress" style={ "width: " (progress_percent(goal)) "%" } {}
+                    }
+                    p class="goal-text" { (goal.current) " / " (goal.target) }
+                }
+            }
+        }
+    }
+}
+
+fn progress_percent(goal: &Goal) -> i64 {
+    if goal.target <= 0 {
+        return 100;
+    }
+    (goal.current * 100 / goal.target).clamp(0, 100)
+}
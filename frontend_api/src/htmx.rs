@@ -3,37 +3,124 @@ use messages::{DisplayMessage, TwitchEvent};
 
 pub fn get_display_html(message: DisplayMessage) -> Markup {
     html! {
+        @if !message.title.is_empty() {
+            h3 class="title" { (message.title) }
+        }
         @match &message.payload {
             TwitchEvent::ChannelFollow(follow) => {
                 p class="event follow" { "Followed" }
-                p class="message" { (message.message) }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
                 (get_html_name_follow(follow))
             }
             TwitchEvent::ChannelSubscribe(sub) => {
                 p class="event subscribe" { "Subscribed" }
-                p class="message" { (message.message) }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
                 (get_html_name_channel_subscribe(sub))
             }
             TwitchEvent::ChannelRaid(raid) => {
                 p class="event raid" { "Raided" }
-                p class="message" { (message.message) }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
                 (get_html_name_raid(raid))
             }
             TwitchEvent::ChannelSubGift(gift) => {
                 p class="event subgift" { "Gifted Sub!" }
-                p class="message" { (message.message) }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
                 (get_html_name_sub_gift(gift))
             }
             TwitchEvent::ChannelCheer(cheer) => {
                 p class="event cheer" { "Cheered!" }
-                p class="message" { (message.message) }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
                 (get_html_name_cheer(cheer))
             }
             TwitchEvent::ChannelResubscribe(sub) => {
                 p class="event resubscribe" { "Resubscribed" }
-                p class="message" { (message.message) }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
                 (get_html_name_channel_subscribe(sub))
             }
+            TwitchEvent::FollowBurst(_) => {
+                p class="event follow-burst" { "Followed" }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
+            }
+            TwitchEvent::ChannelHypeTrainBegin(_) => {
+                p class="event hype-train" { "Hype Train!" }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
+            }
+            TwitchEvent::ChannelHypeTrainEnd(_) => {
+                p class="event hype-train" { "Boss Fight Over!" }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
+            }
+            TwitchEvent::StreamOnline(_) => {
+                p class="event stream-online" { "The Adventure Begins" }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
+            }
+            TwitchEvent::StreamOffline(_) => {
+                p class="event stream-offline" { "The Adventure Ends" }
+                p class="message" { (emphasized(&message.message, &message.emphasis_words)) }
+            }
+        }
+        (get_html_level_up(&message))
+        (get_html_avatar(&message))
+        (get_html_image(&message))
+    }
+}
+
+/// The d20 the overlay rolls before the story shows up, see `div.rolled` in alert.css.
+pub fn get_html_dice(message: &DisplayMessage) -> Markup {
+    html! {
+        @if let Some(roll) = message.roll {
+            div class={ "dice " (roll.outcome().replace(' ', "-")) } {
+                span class="d20" { (roll.natural) }
+                @if roll.modifier != 0 {
+                    span class="modifier" { (format!("{:+}", roll.modifier)) }
+                }
+                span class="outcome" { (roll.outcome()) }
+            }
+        }
+    }
+}
+
+/// Shown when the event got the viewer's character to a new level.
+pub fn get_html_level_up(message: &DisplayMessage) -> Markup {
+    html! {
+        @if let Some(sheet) = message.character.as_ref().filter(|sheet| sheet.leveled_up) {
+            p class="level-up" {
+                "Level " (sheet.level) " " (sheet.race) " " (sheet.class) "!"
+                @if let Some(item) = sheet.inventory.last() {
+                    " Found " (item) "."
+                }
+            }
+        }
+    }
+}
+
+/// The story with its emphasis words wrapped in `em.emphasis`, ignoring case and punctuation.
+pub fn emphasized(message: &str, words: &[String]) -> Markup {
+    html! {
+        @for (i, word) in message.split(' ').enumerate() {
+            @if i > 0 { " " }
+            @let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+            @if !bare.is_empty() && words.iter().any(|w| w.eq_ignore_ascii_case(bare)) {
+                em class="emphasis" { (word) }
+            } @else {
+                (word)
+            }
+        }
+    }
+}
+
+/// The viewer's Twitch avatar, when their profile was looked up.
+fn get_html_avatar(message: &DisplayMessage) -> Markup {
+    html! {
+        @if let Some(url) = message.payload.profile().and_then(|p| p.profile_image_url.as_ref()) {
+            img class="alert-avatar" src=(url);
+        }
+    }
+}
+
+fn get_html_image(message: &DisplayMessage) -> Markup {
+    html! {
+        @if message.image_url != "none" {
+            img class="alert-image" src=(message.image_url);
         }
     }
 }
@@ -51,14 +138,21 @@ fn get_html_name_raid(raid: &messages::RaidEvent) -> Markup {
 }
 
 fn get_html_name_sub_gift(gift: &messages::ChannelGiftMessage) -> Markup {
-    if let Some(gifter) = gift.clone().user_name {
-        html! {
+    html! {
+        @if let Some(gifter) = gift.clone().user_name {
             h2  class="message" { (format!("{}", gifter)) }
-        }
-    } else {
-        html! {
+        } @else {
             h2  class="message" { "Anonymous" }
         }
+        (get_html_recipients(gift))
+    }
+}
+
+fn get_html_recipients(gift: &messages::ChannelGiftMessage) -> Markup {
+    html! {
+        @if !gift.recipients.is_empty() {
+            p class="recipients" { (format!("To {}", gift.recipients.join(", "))) }
+        }
     }
 }
 
@@ -0,0 +1,321 @@
+//! Leaderboards of the channel's top supporters.
+//!
+//! Top gifters, top cheerers and the biggest raids over this stream, this month or all time.
+//! This stream is everything since the latest stream.online the AIManager saved.
+//! The routes serve them as HTMX and JSON, and the overlay gets a widget on the `leaderboard`
+//! topic that rotates through every board and period.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::sqlite::{self, LeaderRow};
+
+/// How many supporters a board shows.
+pub const LEADERBOARD_SIZE: i64 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Board {
+    Gifters,
+    Cheerers,
+    Raids,
+}
+
+impl Board {
+    pub const ALL: [Board; 3] = [Board::Gifters, Board::Cheerers, Board::Raids];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Board::Gifters => "Top Gifters",
+            Board::Cheerers => "Top Cheerers",
+            Board::Raids => "Biggest Raids",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Board::Gifters => "subs",
+            Board::Cheerers => "bits",
+            Board::Raids => "viewers",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    #[default]
+    Stream,
+    Month,
+    AllTime,
+}
+
+impl Period {
+    pub const ALL: [Period; 3] = [Period::Stream, Period::Month, Period::AllTime];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Period::Stream => "This Stream",
+            Period::Month => "This Month",
+            Period::AllTime => "All Time",
+        }
+    }
+
+    /// Arguments to sqlite's datetime() for the start of the period. This stream starts when
+    /// the latest stream went live, or at `fallback` before the first one was seen.
+    fn since(&self, stream_started_at: Option<i64>, fallback: u64) -> (String, &'static str) {
+        match self {
+            Period::Stream => (
+                stream_started_at.map_or(fallback.to_string(), |at| at.to_string()),
+                "unixepoch",
+            ),
+            Period::Month => ("now".to_string(), "start of month"),
+            Period::AllTime => ("0".to_string(), "unixepoch"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Leaderboard {
+    pub board: Board,
+    pub period: Period,
+    pub entries: Vec<LeaderRow>,
+}
+
+#[derive(Clone)]
+pub struct Leaderboards {
+    pool: SqlitePool,
+    /// Unix seconds the app started, "this stream" starts here until a stream goes live.
+    started_at: u64,
+    /// How long the overlay widget shows each board.
+    pub rotate_interval: std::time::Duration,
+}
+
+impl Leaderboards {
+    pub fn new(pool: SqlitePool, rotate_interval: std::time::Duration) -> Leaderboards {
+        Leaderboards {
+            pool,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            rotate_interval,
+        }
+    }
+
+    pub async fn get(&self, board: Board, period: Period) -> anyhow::Result<Leaderboard> {
+        let stream_started_at = match period {
+            Period::Stream => sqlite::get_stream_started_at(self.pool.acquire().await?).await?,
+            _ => None,
+        };
+        let (since, modifier) = period.since(stream_started_at, self.started_at);
+        let conn = self.pool.acquire().await?;
+        let entries = match board {
+            Board::Gifters => {
+                sqlite::get_top_gifters(conn, &since, modifier, LEADERBOARD_SIZE).await?
+            }
+            Board::Cheerers => {
+                sqlite::get_top_cheerers(conn, &since, modifier, LEADERBOARD_SIZE).await?
+            }
+            Board::Raids => {
+                sqlite::get_biggest_raids(conn, &since, modifier, LEADERBOARD_SIZE).await?
+            }
+        };
+        Ok(Leaderboard {
+            board,
+            period,
+            entries,
+        })
+    }
+}
+
+pub fn leaderboard_html(leaderboard: &Leaderboard) -> Markup {
+    html! {
+        div class="leaderboard" {
+            h2 class="leaderboard-title" { (leaderboard.board.title()) }
+            p class="leaderboard-period" { (leaderboard.period.title()) }
+            @if leaderboard.entries.is_empty() {
+                p class="leaderboard-empty" { "No one yet, be the first!" }
+            } @else {
+                ol {
+                    @for entry in &leaderboard.entries {
+                        li {
+                            span class="leader-name" { (entry.name) }
+                            span class="leader-total" { (entry.total) " " (leaderboard.board.unit()) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The overlay widget, sent on the `leaderboard` topic.
+pub fn widget_html(leaderboard: &Leaderboard, sequence: u64) -> Markup {
+    html! {
+        div id="leaderboard" data-seq=(sequence) {
+            (leaderboard_html(leaderboard))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("../ai_manager_service/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    async fn cheer(pool: &SqlitePool, user_id: i64, user_name: &str, bits: i64, at: &str) {
+        sqlx::query(
+            "INSERT INTO cheer_events ( user_id, user_name, bits, cheered_at ) VALUES ( ?, ?, ?, ? )",
+        )
+        .bind(user_id)
+        .bind(user_name)
+        .bind(bits)
+        .bind(at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn gift(pool: &SqlitePool, user_id: &str, user_name: &str, total: i64) {
+        sqlx::query(
+            "INSERT INTO gift_subs_events ( broadcaster_user_id, cumulative_total, is_anonymous, tier, total, user_id, user_login, user_name, story_segment, gifted_at ) VALUES ( '1', 0, 0, 1000, ?, ?, ?, ?, '', '2024-01-01 00:00:00' )",
+        )
+        .bind(total)
+        .bind(user_id)
+        .bind(user_name.to_lowercase())
+        .bind(user_name)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn raid(pool: &SqlitePool, channel_id: &str, channel: &str, viewers: i64) {
+        sqlx::query(
+            "INSERT INTO raid_events ( from_broadcaster_user_id, from_broadcaster_user_name, to_broadcaster_user_id, to_broadcaster_user_name, viewers, story_segment, raid_at ) VALUES ( ?, ?, '1', 'null', ?, '', '2024-01-01 00:00:00' )",
+        )
+        .bind(channel_id)
+        .bind(channel)
+        .bind(viewers)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn stream_started(pool: &SqlitePool, at: &str) {
+        sqlx::query("INSERT INTO streams ( started_at ) VALUES ( ? )")
+            .bind(at)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn leaders(leaderboard: &Leaderboard) -> Vec<(&str, i64)> {
+        leaderboard
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.total))
+            .collect()
+    }
+
+    fn leaderboards(pool: SqlitePool) -> Leaderboards {
+        Leaderboards::new(pool, std::time::Duration::from_secs(10))
+    }
+
+    #[test]
+    fn since_starts_this_stream_at_the_latest_stream() {
+        assert_eq!(
+            Period::Stream.since(Some(1700000000), 5),
+            ("1700000000".to_string(), "unixepoch")
+        );
+        assert_eq!(
+            Period::Stream.since(None, 5),
+            ("5".to_string(), "unixepoch")
+        );
+        assert_eq!(
+            Period::Month.since(Some(1700000000), 5),
+            ("now".to_string(), "start of month")
+        );
+        assert_eq!(
+            Period::AllTime.since(Some(1700000000), 5),
+            ("0".to_string(), "unixepoch")
+        );
+    }
+
+    #[tokio::test]
+    async fn cheerers_are_told_apart_by_id_under_their_latest_name() {
+        let pool = pool().await;
+        cheer(&pool, 7, "sam", 100, "2024-01-01 00:00:00").await;
+        cheer(&pool, 7, "sam_renamed", 50, "2024-01-01 00:01:00").await;
+        cheer(&pool, 8, "sam", 120, "2024-01-01 00:02:00").await;
+        cheer(&pool, 0, "Anonymous", 5000, "2024-01-01 00:03:00").await;
+
+        let board = leaderboards(pool)
+            .get(Board::Cheerers, Period::AllTime)
+            .await
+            .unwrap();
+        assert_eq!(leaders(&board), vec![("sam_renamed", 150), ("sam", 120)]);
+    }
+
+    #[tokio::test]
+    async fn gifters_and_raids_are_grouped_by_id() {
+        let pool = pool().await;
+        gift(&pool, "7", "Sam", 5).await;
+        gift(&pool, "7", "SamNew", 3).await;
+        gift(&pool, "8", "Sam", 6).await;
+        raid(&pool, "20", "raider", 30).await;
+        raid(&pool, "20", "raider_new", 10).await;
+        raid(&pool, "21", "raider", 15).await;
+        let leaderboards = leaderboards(pool);
+
+        let gifters = leaderboards
+            .get(Board::Gifters, Period::AllTime)
+            .await
+            .unwrap();
+        assert_eq!(leaders(&gifters), vec![("SamNew", 8), ("Sam", 6)]);
+        let raids = leaderboards
+            .get(Board::Raids, Period::AllTime)
+            .await
+            .unwrap();
+        assert_eq!(leaders(&raids), vec![("raider_new", 30), ("raider", 15)]);
+    }
+
+    #[tokio::test]
+    async fn this_stream_starts_when_the_latest_stream_went_live() {
+        let pool = pool().await;
+        stream_started(&pool, "2024-01-01 00:00:00").await;
+        cheer(&pool, 7, "last_stream", 500, "2024-01-01 01:00:00").await;
+        stream_started(&pool, "2024-01-02 00:00:00").await;
+        cheer(&pool, 8, "this_stream", 100, "2024-01-02 01:00:00").await;
+        let leaderboards = leaderboards(pool);
+
+        let stream = leaderboards
+            .get(Board::Cheerers, Period::Stream)
+            .await
+            .unwrap();
+        assert_eq!(leaders(&stream), vec![("this_stream", 100)]);
+        let all_time = leaderboards
+            .get(Board::Cheerers, Period::AllTime)
+            .await
+            .unwrap();
+        assert_eq!(all_time.entries.len(), 2);
+    }
+}
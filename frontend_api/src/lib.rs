@@ -1,12 +1,18 @@
-use axum::{routing::get, Router};
+use axum::{
+    extract::{DefaultBodyLimit, Query},
+    http::Uri,
+    routing::{delete, get, post},
+    Router,
+};
 use futures_channel::mpsc::unbounded;
 use futures_util::sink::With;
 use futures_util::{SinkExt, StreamExt};
-use maud::html;
-use messages::DisplayMessage;
+use maud::{html, Markup, PreEscaped};
+use messages::{Backlog, BossBattle, Credits, DisplayMessage, Goals, NewTwitchEventMessage};
+use serde::Deserialize;
 use std::net::SocketAddr;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
 };
 
@@ -17,21 +23,64 @@ use tokio::{
 use tower_http::services::ServeDir;
 
 use tokio_tungstenite::{
-    accept_async,
-    tungstenite::{Error, Message, Result},
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{Request, Response},
+        Error, Message, Result,
+    },
 };
 
+mod api;
+mod assets;
+mod boss;
+mod credits;
+mod goals;
 mod htmx;
+mod leaderboards;
 mod routes;
+mod sqlite;
+mod themes;
+mod ticker;
+mod tts;
 mod types;
+pub use assets::AssetManager;
+pub use leaderboards::Leaderboards;
 use routes::{admin, index};
-
-use crate::types::{ConnectionMap, EventQueues, Queues};
+pub use ticker::TickerStore;
+pub use tts::{engine_by_name, Pronunciations, TtsEngine, TtsManager};
+
+use crate::api::{ApiEventKind, EventStream};
+use crate::themes::{ThemeManager, Themes};
+use crate::types::{
+    ClientMessage, ConnectionMap, EventQueues, OverlayAck, OverlayClient, OverlayConnections,
+    OverlayLog, OverlayState, PrimaryOverlay, Queues, SequencedFrame, Topic,
+};
 
 pub struct FrontendApi {
     pub host_info: HostInfo,
     pub connection_state: ConnectionMap,
+    pub overlay_state: OverlayLog,
+    pub primary_overlay: PrimaryOverlay,
+    pub event_stream: EventStream,
+    pub themes: Themes,
+    pub asset_manager: Option<AssetManager>,
+    pub leaderboards: Option<Leaderboards>,
+    /// Keeps the ticker in sqlite so it survives restarts. Without it the ticker starts empty.
+    pub ticker: Option<TickerStore>,
+    pub tts: Option<TtsManager>,
+    /// Shared with the AIManager, switches both to catch-up mode when alerts fall behind.
+    pub backlog: Backlog,
+    /// The hype train boss fight the AIManager runs, drawn on the `hype_train` topic.
+    pub boss_battle: BossBattle,
+    /// The goals the AIManager counts up, drawn on the `goals` topic.
+    pub goals: Goals,
+    /// The credits the AIManager puts together at the end of the stream, rolled on the
+    /// `credits` topic.
+    pub credits: Credits,
+    /// Lets the admin page send events to the AIManager, like starting a boss fight.
+    pub event_sender: Option<mpsc::UnboundedSender<NewTwitchEventMessage>>,
     pub asset_path: String,
+    pub themes_path: String,
 }
 
 #[derive(Clone)]
@@ -59,14 +108,41 @@ impl HostInfo {
 pub struct UnitedStates {
     pub host_info: HostInfo,
     pub event_queues: EventQueues,
+    pub event_stream: EventStream,
+    pub themes: Themes,
+    pub connection_state: ConnectionMap,
+    pub asset_manager: Option<AssetManager>,
+    pub leaderboards: Option<Leaderboards>,
+    pub boss_battle: BossBattle,
+    pub credits: Credits,
+    pub event_sender: Option<mpsc::UnboundedSender<NewTwitchEventMessage>>,
 }
 
 impl FrontendApi {
-    pub fn new(host_info: HostInfo, asset_path: String) -> FrontendApi {
+    pub fn new(
+        host_info: HostInfo,
+        asset_path: String,
+        themes_path: String,
+        theme: String,
+    ) -> FrontendApi {
         FrontendApi {
             host_info,
             connection_state: ConnectionMap::new(Mutex::new(HashMap::new())),
+            overlay_state: Arc::new(Mutex::new(OverlayState::new())),
+            primary_overlay: PrimaryOverlay::new(Mutex::new(None)),
+            event_stream: EventStream::new(),
+            themes: Arc::new(Mutex::new(ThemeManager::new(themes_path.clone(), theme))),
+            asset_manager: None,
+            leaderboards: None,
+            ticker: None,
+            tts: None,
+            backlog: Backlog::default(),
+            boss_battle: BossBattle::default(),
+            goals: Goals::default(),
+            credits: Credits::default(),
+            event_sender: None,
             asset_path,
+            themes_path,
         }
     }
 
@@ -84,19 +160,72 @@ impl FrontendApi {
 
         //TODO: Need to fetch un presented messages from database
 
+        if let Some(asset_manager) = &self.asset_manager {
+            if let Err(e) = asset_manager.apply_assignments().await {
+                println!("could not load asset assignments: {}", e);
+            }
+        }
+
+        if let Some(tts) = &self.tts {
+            if let Err(e) = tts.prepare().await {
+                println!("could not set up the speech dir: {}", e);
+            }
+        }
+
+        if let Some(ticker) = &self.ticker {
+            if let Err(e) = ticker.restore(&message_queue_arc).await {
+                println!("could not restore the ticker: {}", e);
+            }
+        }
+        publish_frame(
+            &connection_state,
+            &self.overlay_state,
+            Topic::Ticker,
+            |sequence| ticker::ticker_html(&message_queue_arc.lock().unwrap(), sequence),
+        );
+
         let queue = message_queue_arc.clone();
         let state = connection_state.clone();
+        let overlay_state = self.overlay_state.clone();
+        let events = self.event_stream.clone();
+        let backlog = self.backlog.clone();
+        let ticker = self.ticker.clone();
         // Listen for incoming events and store them in the queues
         tokio::spawn(async move {
             loop {
                 let msg = (&mut receiver).recv().await;
+                if let Some(message) = &msg {
+                    events.publish(ApiEventKind::Queued, message);
+
+                    // The ticker updates as the alerts come in, not when they are shown
+                    let slot = queue.lock().unwrap().update_ticker(message);
+                    if let Some(slot) = slot {
+                        publish_frame(&state, &overlay_state, Topic::Ticker, |sequence| {
+                            ticker::ticker_html(&queue.lock().unwrap(), sequence)
+                        });
+                        if let Some(ticker) = &ticker {
+                            if let Err(e) = ticker.save(slot, message).await {
+                                println!("could not save the ticker: {}", e);
+                            }
+                        }
+                    }
+                }
                 handle_message(state.clone(), queue.clone(), msg).await;
+                backlog.set_unpublished(queue.lock().unwrap().unpublished_events.len());
             }
         });
 
+        // Acks from the overlays come back from the websocket connections
+        let (ack_sender, mut ack_receiver) = mpsc::unbounded_channel();
+
         // Process the Queues on a new thread
         let queue_connection_state = connection_state.clone();
+        let overlay_state = self.overlay_state.clone();
         let event_queue = message_queue_arc.clone();
+        let events = self.event_stream.clone();
+        let themes = self.themes.clone();
+        let tts = self.tts.clone();
+        let backlog = self.backlog.clone();
         tokio::spawn(async move {
             loop {
                 let active = types::EVENT_QUEUE_ACTIVE.load(std::sync::atomic::Ordering::SeqCst);
@@ -105,9 +234,12 @@ impl FrontendApi {
                     continue;
                 }
 
+                let catching_up = backlog.catching_up();
                 let message = {
                     let mut queues = event_queue.lock().unwrap();
-                    queues.unpublished_events.pop_front()
+                    let message = queues.pop_alert(catching_up);
+                    backlog.set_unpublished(queues.unpublished_events.len());
+                    message
                 };
 
                 let Some(message) = message else {
@@ -117,47 +249,71 @@ impl FrontendApi {
 
                 //Make html message to send to frontend
                 //<div id="alerts" hx-swap-oob="true">
-                let html_message = html! {
-                    div id="notifications" class="alert" hx-swap="afterend" hx-target="notifications" {
-                        div class="wrapper" {
-                            (htmx::get_display_html(message.clone()))
+                let sequence = publish_frame(
+                    &queue_connection_state,
+                    &overlay_state,
+                    Topic::Alerts,
+                    |sequence| {
+                        html! {
+                            div id="notifications" class="alert" data-seq=(sequence) data-display-time=(message.display_time)
+                                data-sound=(message.sound_url) data-volume=(message.volume) data-mood=(message.mood.as_str())
+                                data-roll=[message.roll.map(|roll| roll.total())] hx-swap="afterend" hx-target="notifications" {
+                                div class=(wrapper_class(&message)) {
+                                    (htmx::get_html_dice(&message))
+                                    @match themes.lock().unwrap().render_alert(&message) {
+                                        Some(themed) => (PreEscaped(themed)),
+                                        None => (htmx::get_display_html(message.clone())),
+                                    }
+                                }
+                            }
                         }
-                    }
-                };
-
-                let mut bad_websockets = vec![];
-
-                //Send message to all connected websockets
-                {
-                    let mut websocket_state = queue_connection_state.lock().unwrap();
-                    for (&addr, tx) in websocket_state.iter_mut() {
-                        if tx
-                            .unbounded_send(Message::Text(html_message.clone().into()))
-                            .is_err()
-                        {
-                            println!("closing websocket message to: {} ==========", addr);
-                            bad_websockets.push(addr);
+                    },
+                );
+                events.publish(ApiEventKind::Alert, &message);
+
+                // Render the story while the alert is up so it's ready when it ends.
+                // Nobody wants to hear the stories read out while we are catching up.
+                let speech = tts.clone().filter(|_| !catching_up).map(|tts| {
+                    let message = message.clone();
+                    tokio::spawn(async move { tts.render(&message).await })
+                });
+
+                //Wait for the overlay to tell us the alert is done, or give up after a while
+                let fallback = tokio::time::Duration::from_millis(
+                    (message.display_time as u64).max(types::MIN_ALERT_DISPLAY_MS)
+                        + types::ALERT_ACK_GRACE_MS,
+                );
+                wait_for_alert_finished(&mut ack_receiver, sequence, fallback).await;
+
+                publish_frame(
+                    &queue_connection_state,
+                    &overlay_state,
+                    Topic::Alerts,
+                    |sequence| {
+                        html! {
+                            div id="notifications" data-seq=(sequence) hx-swap="delete" hx-target="notifications" {
+                            }
                         }
+                    },
+                );
+
+                if let Some(speech) = speech {
+                    match speech.await {
+                        Ok(Ok(Some(clip))) => event_queue.lock().unwrap().tts.push_back(clip),
+                        Ok(Ok(None)) => {}
+                        Ok(Err(e)) => println!("could not render tts: {}", e),
+                        Err(e) => println!("tts task failed: {}", e),
                     }
                 }
 
-                //Pause for a bit to allow the message to be displayed
-                tokio::time::sleep(tokio::time::Duration::from_millis(10000)).await;
-
-                let html_message = html! {
-                    div id="notifications" hx-swap="delete" hx-target="notifications" {
-                    }
-                };
-                {
-                    let mut websocket_state = queue_connection_state.lock().unwrap();
-                    for (&addr, tx) in websocket_state.iter_mut() {
-                        if tx
-                            .unbounded_send(Message::Text(html_message.clone().into()))
-                            .is_err()
-                        {
-                            println!("closing websocket message to: {} ==========", addr);
-                            bad_websockets.push(addr);
-                        }
+                if tts.as_ref().is_some_and(|tts| tts.autoplay) {
+                    let clip = event_queue.lock().unwrap().tts.pop_front();
+                    if let Some(clip) = clip {
+                        tts::play_clip(&queue_connection_state, &clip);
+                        tokio::time::sleep(tokio::time::Duration::from_millis(
+                            clip.duration_ms.unwrap_or_default(),
+                        ))
+                        .await;
                     }
                 }
 
@@ -166,39 +322,190 @@ impl FrontendApi {
             }
         });
 
+        // Redraw the boss fight on the overlays whenever it changes
+        let boss_connection_state = connection_state.clone();
+        let overlay_state = self.overlay_state.clone();
+        let boss_battle = self.boss_battle.clone();
+        tokio::spawn(async move {
+            let mut drawn = boss_battle.revision();
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_millis(boss::REDRAW_INTERVAL_MS))
+                    .await;
+                let revision = boss_battle.revision();
+                if revision == drawn {
+                    continue;
+                }
+                drawn = revision;
+                let boss = boss_battle.get();
+                publish_frame(
+                    &boss_connection_state,
+                    &overlay_state,
+                    Topic::HypeTrain,
+                    |sequence| boss::boss_html(boss.as_ref(), sequence),
+                );
+            }
+        });
+
+        // Redraw the goals on the overlays whenever they change
+        let goals_connection_state = connection_state.clone();
+        let overlay_state = self.overlay_state.clone();
+        let goals = self.goals.clone();
+        tokio::spawn(async move {
+            let mut drawn = 0;
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_millis(
+                    goals::REDRAW_INTERVAL_MS,
+                ))
+                .await;
+                let revision = goals.revision();
+                if revision == drawn {
+                    continue;
+                }
+                drawn = revision;
+                publish_frame(
+                    &goals_connection_state,
+                    &overlay_state,
+                    Topic::Goals,
+                    |sequence| goals::goals_html(&goals.get(), sequence),
+                );
+            }
+        });
+
+        // Roll the credits on the overlays when the AIManager has them ready
+        let credits_connection_state = connection_state.clone();
+        let overlay_state = self.overlay_state.clone();
+        let credits = self.credits.clone();
+        tokio::spawn(async move {
+            let mut drawn = credits.revision();
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_millis(
+                    credits::REDRAW_INTERVAL_MS,
+                ))
+                .await;
+                let revision = credits.revision();
+                if revision == drawn {
+                    continue;
+                }
+                drawn = revision;
+                let roll = credits.get();
+                publish_frame(
+                    &credits_connection_state,
+                    &overlay_state,
+                    Topic::Credits,
+                    |sequence| credits::credits_html(roll.as_ref(), sequence),
+                );
+            }
+        });
+
+        // Rotate the leaderboard widget through every board and period
+        if let Some(leaderboards) = self.leaderboards.clone() {
+            let leaderboard_connection_state = connection_state.clone();
+            let overlay_state = self.overlay_state.clone();
+            tokio::spawn(async move {
+                let boards = leaderboards::Period::ALL
+                    .into_iter()
+                    .flat_map(|period| leaderboards::Board::ALL.map(|board| (board, period)));
+                for (board, period) in boards.cycle() {
+                    match leaderboards.get(board, period).await {
+                        Ok(leaderboard) => {
+                            publish_frame(
+                                &leaderboard_connection_state,
+                                &overlay_state,
+                                Topic::Leaderboard,
+                                |sequence| leaderboards::widget_html(&leaderboard, sequence),
+                            );
+                        }
+                        Err(e) => println!("could not load the {:?} leaderboard: {}", board, e),
+                    }
+                    tokio::time::sleep(leaderboards.rotate_interval).await;
+                }
+            });
+        }
+
         let https_address = self.host_info.get_http_address();
 
         let united_states = UnitedStates {
             host_info: self.host_info.clone(),
             event_queues: message_queue_arc.clone(),
+            event_stream: self.event_stream.clone(),
+            themes: self.themes.clone(),
+            connection_state: self.connection_state.clone(),
+            asset_manager: self.asset_manager.clone(),
+            leaderboards: self.leaderboards.clone(),
+            boss_battle: self.boss_battle.clone(),
+            credits: self.credits.clone(),
+            event_sender: self.event_sender.clone(),
         };
 
         print!("Frontend HTTP is Listening on: {}", https_address);
         let asset_path = self.asset_path.clone();
+        let themes_path = self.themes_path.clone();
+        let uploads_path = self
+            .asset_manager
+            .as_ref()
+            .map(|manager| manager.data_dir().to_path_buf());
+        let speech_path = self.tts.as_ref().map(|tts| tts.output_dir().to_path_buf());
         tokio::spawn(async move {
             let listener = TcpListener::bind(&https_address)
                 .await
                 .expect("Can't listen");
             // build our application
-            let app = Router::new()
+            let mut app = Router::new()
                 .route("/", get(index))
                 .route("/admin", get(admin))
                 .route("/events/latest", get(routes::get_latest_unpublished_events))
-                .route("/tts", get(routes::get_all_events_in_queue))
+                .route("/tts", get(tts::get_tts_queue))
+                .route("/tts/next", get(tts::play_next))
                 .route("/events", get(routes::get_all_events_in_queue))
                 .route("/events/latest/all", get(routes::get_latest_events))
                 .route("/events/pause", get(routes::pause_events))
                 .route("/events/start", get(routes::resume_events))
+                .route("/admin/themes", get(routes::list_themes))
+                .route("/admin/themes/:name", get(routes::select_theme))
+                .route("/admin/boss", get(boss::get_boss_fight))
+                .route("/admin/boss/start", get(boss::start_boss_fight))
+                .route("/admin/boss/end", get(boss::end_boss_fight))
+                .route("/admin/credits/start", get(credits::start_credits))
+                .route("/admin/credits/stop", get(credits::stop_credits))
+                .route(
+                    "/admin/assets",
+                    get(assets::list_assets)
+                        .post(assets::upload_asset)
+                        .layer(DefaultBodyLimit::max(assets::MAX_SOUND_BYTES + 64 * 1024)),
+                )
+                .route("/admin/assets/:id", delete(assets::delete_asset))
+                .route("/admin/assets/:id/assign", post(assets::assign_asset))
+                .route("/api/v1/assets", get(assets::assets_json))
+                .route("/leaderboards/:board", get(routes::get_leaderboard))
+                .route(
+                    "/api/v1/leaderboards/:board",
+                    get(routes::get_leaderboard_json),
+                )
+                .route("/api/v1/events/ws", get(api::events_ws))
+                .route("/api/v1/events/sse", get(api::events_sse))
                 //TODO: understand where to put our assets
                 // Remember that these need served by nginx in production
                 .nest_service("/assets", ServeDir::new(asset_path.clone()))
+                .nest_service("/themes", ServeDir::new(themes_path.clone()))
                 .with_state(united_states.clone());
 
+            if let Some(uploads_path) = uploads_path {
+                app = app.nest_service(assets::UPLOADS_PATH, ServeDir::new(uploads_path));
+            }
+            if let Some(speech_path) = speech_path {
+                app = app.nest_service(tts::SPEECH_PATH, ServeDir::new(speech_path));
+            }
+
             // run it
             axum::serve(listener, app).await.unwrap();
         });
 
-        let new_connection_state = self.connection_state.clone();
+        let overlay_connections = OverlayConnections {
+            connections: self.connection_state.clone(),
+            overlay_state: self.overlay_state.clone(),
+            acks: ack_sender,
+            primary: self.primary_overlay.clone(),
+        };
 
         while let Ok((stream, _)) = listener.accept().await {
             let peer = stream
@@ -206,16 +513,114 @@ impl FrontendApi {
                 .expect("connected streams should have a peer address");
             println!("Peer address: {}", peer);
 
-            tokio::spawn(accept_connection(
-                peer,
-                stream,
-                new_connection_state.clone(),
-            ));
+            tokio::spawn(accept_connection(peer, stream, overlay_connections.clone()));
         }
         Ok(())
     }
 }
 
+/// The alert wrapper is styled by the story's mood and plays the animation configured
+/// for the message, if any.
+fn wrapper_class(message: &DisplayMessage) -> String {
+    let mut class = format!("wrapper mood-{}", message.mood.as_str());
+    if message.roll.is_some() {
+        class.push_str(" rolled");
+    }
+    if message.animation == "none" {
+        class
+    } else {
+        format!("{} animation-{}", class, message.animation)
+    }
+}
+
+/// Stamps the next sequence number on a fragment, records it for reconnecting overlays
+/// and sends it to every websocket subscribed to `topic`. Returns the sequence number used.
+fn publish_frame(
+    connection_state: &ConnectionMap,
+    overlay_state: &OverlayLog,
+    topic: Topic,
+    render: impl FnOnce(u64) -> Markup,
+) -> u64 {
+    let frame = {
+        let mut overlay = overlay_state.lock().unwrap();
+        let sequence = overlay.next_sequence();
+        let frame = SequencedFrame {
+            sequence,
+            topic,
+            html: render(sequence).into_string(),
+        };
+        overlay.record(frame.clone());
+        frame
+    };
+
+    send_to_clients(connection_state, &frame.html, |client| {
+        client.topics.contains(&topic)
+    });
+    frame.sequence
+}
+
+/// Sends an html fragment to every connected websocket `wanted` returns true for,
+/// dropping the ones that went away.
+pub(crate) fn send_to_clients(
+    connection_state: &ConnectionMap,
+    html: &str,
+    wanted: impl Fn(&OverlayClient) -> bool,
+) {
+    let mut websocket_state = connection_state.lock().unwrap();
+    let mut bad_websockets = vec![];
+    for (&addr, client) in websocket_state.iter_mut() {
+        if !wanted(client) {
+            continue;
+        }
+        if client
+            .tx
+            .unbounded_send(Message::Text(html.to_string()))
+            .is_err()
+        {
+            println!("closing websocket message to: {} ==========", addr);
+            bad_websockets.push(addr);
+        }
+    }
+    for addr in bad_websockets {
+        websocket_state.remove(&addr);
+    }
+}
+
+/// Waits until the overlay reports the alert with `sequence` finished playing.
+///
+/// Gives up after `fallback` so a missing or broken overlay can't stall the queue.
+/// The overlay reporting the alert started resets the clock once, since it may have been slow
+/// to get it. Reporting it again doesn't, so an overlay stuck on the alert can't hold the queue.
+async fn wait_for_alert_finished(
+    acks: &mut mpsc::UnboundedReceiver<OverlayAck>,
+    sequence: u64,
+    fallback: tokio::time::Duration,
+) {
+    let mut deadline = tokio::time::Instant::now() + fallback;
+    let mut started = false;
+    loop {
+        tokio::select! {
+            ack = acks.recv() => match ack {
+                Some(OverlayAck::Started(seq)) if seq == sequence && !started => {
+                    started = true;
+                    deadline = tokio::time::Instant::now() + fallback;
+                }
+                Some(OverlayAck::Finished(seq)) if seq == sequence => return,
+                // Stale ack for an alert we already moved on from
+                Some(_) => {}
+                None => {
+                    tokio::time::sleep_until(deadline).await;
+                    return;
+                }
+            },
+            _ = tokio::time::sleep_until(deadline) => {
+                println!("No ack for alert {}, moving on", sequence);
+                return;
+            }
+        }
+    }
+}
+
 async fn handle_message(
     connection_state: ConnectionMap,
     event_queues: EventQueues,
@@ -226,7 +631,7 @@ async fn handle_message(
             let mut queues = event_queues.lock().unwrap();
 
             //TODO: Store different types of messages in different queues
-            queues.unpublished_events.push_back(message.clone());
+            queues.push_alert(message.clone());
 
             //add to latest events, remove oldest if over 10
             queues.latest_events.push_back(message.clone());
@@ -237,39 +642,104 @@ async fn handle_message(
         None => panic!("Error receiving message"),
     }
 }
-pub async fn accept_connection(peer: SocketAddr, stream: TcpStream, state: ConnectionMap) {
-    if let Err(e) = handle_connection(peer, stream, state).await {
+#[derive(Deserialize)]
+struct TopicsQuery {
+    topics: Option<String>,
+}
+
+/// The topics asked for with `?topics=` on the websocket url, if any.
+fn topics_from_uri(uri: &Uri) -> Option<HashSet<Topic>> {
+    let query = Query::<TopicsQuery>::try_from_uri(uri).ok()?;
+    query.0.topics.map(|list| Topic::parse_list(&list))
+}
+
+pub async fn accept_connection(peer: SocketAddr, stream: TcpStream, overlay: OverlayConnections) {
+    if let Err(e) = handle_connection(peer, stream, overlay.clone()).await {
         match e {
             Error::ConnectionClosed | Error::Protocol(_) | Error::Utf8 => (),
             err => println!("Error processing connection: {}", err),
         }
     }
+
+    // Give up the primary role so acks from the other overlays count again
+    let mut primary = overlay.primary.lock().unwrap();
+    if *primary == Some(peer) {
+        *primary = None;
+    }
 }
 
+// The handshake callback has to return tungstenite's large error response
+#[allow(clippy::result_large_err)]
 async fn handle_connection(
     peer: SocketAddr,
     stream: TcpStream,
-    state: ConnectionMap,
+    overlay: OverlayConnections,
 ) -> Result<()> {
     println!("New WebSocket connection: {}", peer);
-    let ws_stream = accept_async(stream).await.expect("Failed to accept");
+    // Browser sources can pick their topics with `?topics=alerts,ticker` on the websocket url
+    let mut topics = Topic::defaults();
+    let ws_stream = accept_hdr_async(stream, |request: &Request, response: Response| {
+        if let Some(requested) = topics_from_uri(request.uri()) {
+            topics = requested;
+        }
+        Ok(response)
+    })
+    .await
+    .expect("Failed to accept");
+    println!("{} subscribed to {:?}", peer, topics);
 
     let (tx, mut rx) = unbounded();
     {
-        state.lock().unwrap().insert(peer, tx);
+        let client = OverlayClient {
+            tx,
+            topics: topics.clone(),
+        };
+        overlay.connections.lock().unwrap().insert(peer, client);
     }
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-    println!("Connection state: {:?}", state.lock().unwrap().keys());
+    println!(
+        "Connection state: {:?}",
+        overlay.connections.lock().unwrap().keys()
+    );
     loop {
         tokio::select! {
             msg = ws_receiver.next() => {
                 match msg {
                     Some(msg) => {
                         let msg = msg?;
-                        // TODO: handle message
                         if msg.is_text() ||msg.is_binary() {
                             println!("Received a message from {}: {}", peer, msg.to_text()?);
-                            ws_sender.send(msg).await?;
+                            match serde_json::from_str::<ClientMessage>(msg.to_text()?) {
+                                Ok(ClientMessage::Resume { last_seq, primary }) => {
+                                    if primary {
+                                        println!("{} is now the primary overlay", peer);
+                                        *overlay.primary.lock().unwrap() = Some(peer);
+                                    }
+                                    let missed = overlay.overlay_state.lock().unwrap().missed_since(last_seq, &topics);
+                                    println!("Catching up {} from {} with {} frames", peer, last_seq, missed.len());
+                                    for frame in missed {
+                                        ws_sender.send(Message::Text(frame.html)).await?;
+                                    }
+                                }
+                                Ok(ClientMessage::AlertStarted { seq }) => {
+                                    if overlay.counts_ack(peer) {
+                                        let _ = overlay.acks.send(OverlayAck::Started(seq));
+                                    }
+                                }
+                                Ok(ClientMessage::AlertFinished { seq }) => {
+                                    if overlay.counts_ack(peer) {
+                                        let _ = overlay.acks.send(OverlayAck::Finished(seq));
+                                    }
+                                }
+                                Ok(ClientMessage::Subscribe { topics: new_topics }) => {
+                                    topics = new_topics.into_iter().collect();
+                                    println!("{} subscribed to {:?}", peer, topics);
+                                    if let Some(client) = overlay.connections.lock().unwrap().get_mut(&peer) {
+                                        client.topics = topics.clone();
+                                    }
+                                }
+                                Err(e) => println!("Unknown message from {}: {}", peer, e),
+                            }
                         } else if msg.is_close() {
                             println!("Issue with connection: {}", peer);
                             break;
@@ -294,3 +764,75 @@ async fn handle_connection(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{Duration, Instant};
+
+    use super::*;
+
+    const FALLBACK: Duration = Duration::from_secs(10);
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_alert_finished_returns_on_the_finished_ack() {
+        let (acks, mut receiver) = mpsc::unbounded_channel();
+        acks.send(OverlayAck::Finished(6)).unwrap();
+        acks.send(OverlayAck::Finished(7)).unwrap();
+
+        let start = Instant::now();
+        wait_for_alert_finished(&mut receiver, 7, FALLBACK).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_alert_finished_gives_up_without_an_ack() {
+        let (_acks, mut receiver) = mpsc::unbounded_channel();
+        let start = Instant::now();
+        wait_for_alert_finished(&mut receiver, 7, FALLBACK).await;
+        assert_eq!(start.elapsed(), FALLBACK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_alert_finished_resets_the_clock_only_once() {
+        let (acks, mut receiver) = mpsc::unbounded_channel();
+        let start = Instant::now();
+        // An overlay stuck reporting the alert started over and over
+        tokio::spawn(async move {
+            for _ in 0..20 {
+                tokio::time::sleep(Duration::from_secs(4)).await;
+                if acks.send(OverlayAck::Started(7)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        wait_for_alert_finished(&mut receiver, 7, FALLBACK).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(4) + FALLBACK);
+    }
+
+    fn topics_of(uri: &str) -> Option<HashSet<Topic>> {
+        topics_from_uri(&uri.parse().unwrap())
+    }
+
+    #[test]
+    fn topics_from_uri_reads_the_topics_param() {
+        assert_eq!(
+            topics_of("/?theme=dark&topics=alerts,ticker"),
+            Some(HashSet::from([Topic::Alerts, Topic::Ticker]))
+        );
+        assert_eq!(topics_of("/"), None);
+        assert_eq!(topics_of("/?theme=dark"), None);
+    }
+
+    #[test]
+    fn topics_from_uri_decodes_the_list() {
+        assert_eq!(
+            topics_of("/?topics=alerts%2Cgoals"),
+            Some(HashSet::from([Topic::Alerts, Topic::Goals]))
+        );
+        assert_eq!(
+            topics_of("/?topics=hype_train%2C%20chat"),
+            Some(HashSet::from([Topic::HypeTrain, Topic::Chat]))
+        );
+    }
+}
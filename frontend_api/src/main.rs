@@ -19,7 +19,12 @@ async fn main() {
         ws_port: ws_port.parse().unwrap(),
         http_port: http_address.parse().unwrap(),
     };
-    let api = FrontendApi::new(host_info, "assets".to_string());
+    let api = FrontendApi::new(
+        host_info,
+        "assets".to_string(),
+        "themes".to_string(),
+        "default".to_string(),
+    );
 
     let (tx, rx) = mpsc::unbounded_channel();
 
@@ -34,13 +39,25 @@ async fn main() {
         count += 1;
         let display_message = messages::DisplayMessage {
             message: format!("hello from htmx {}", count),
-            image_url: "".to_string(),
-            sound_url: "".to_string(),
+            image_url: "none".to_string(),
+            sound_url: "none".to_string(),
+            animation: "none".to_string(),
+            volume: 1.0,
             display_time: 10000,
             payload: messages::TwitchEvent::ChannelFollow(messages::FollowEvent {
                 user_name: "some user".to_string(),
                 user_id: 123,
+                broadcaster_user_id: 456,
+                profile: None,
             }),
+            title: "A new hero".to_string(),
+            mood: messages::Mood::Epic,
+            emphasis_words: vec!["htmx".to_string()],
+            flag: None,
+            theme: None,
+            priority: 0,
+            character: None,
+            roll: None,
         };
 
         tx.send(display_message).unwrap();
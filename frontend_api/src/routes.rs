@@ -1,12 +1,26 @@
+use crate::leaderboards::{leaderboard_html, Board, Leaderboard, Period};
 use crate::UnitedStates;
-use axum::{extract::State, http::StatusCode};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
 use maud::{html, Markup};
+use serde::Deserialize;
 
 #[derive(askama::Template)]
 #[template(path = "index.html")]
 pub struct IndexTemplate {
     pub hostname: String,
     pub port: u16,
+    pub topics: String,
+    pub theme_css: String,
+}
+
+#[derive(Deserialize)]
+pub struct IndexQuery {
+    /// Comma separated topics this browser source shows, e.g. `alerts,ticker`.
+    pub topics: Option<String>,
 }
 
 #[derive(askama::Template)]
@@ -17,10 +31,15 @@ pub struct AdminTemplate {
     pub port: u16,
 }
 
-pub async fn index(State(sw_state): State<UnitedStates>) -> IndexTemplate {
+pub async fn index(
+    State(sw_state): State<UnitedStates>,
+    Query(query): Query<IndexQuery>,
+) -> IndexTemplate {
     IndexTemplate {
         hostname: sw_state.host_info.websocket_host,
         port: sw_state.host_info.ws_port,
+        topics: query.topics.unwrap_or_else(|| "alerts".to_string()),
+        theme_css: sw_state.themes.lock().unwrap().stylesheet_url(),
     }
 }
 
@@ -51,7 +70,7 @@ pub async fn get_latest_unpublished_events(
     Ok(html! {
         ul class=(class) {
             @for event in events {
-                li { (event.message) }
+                (event_item(event))
             }
         }
     })
@@ -66,7 +85,7 @@ pub async fn get_latest_events(
     Ok(html! {
         ul class="running" {
             @for event in events {
-                li { (event.message) }
+                (event_item(&event))
             }
         }
     })
@@ -94,8 +113,104 @@ pub async fn get_all_events_in_queue(
     Ok(html! {
         ul {
             @for event in events {
-                li { (event.message) }
+                (event_item(&event))
             }
         }
     })
 }
+
+pub async fn list_themes(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    let themes = state.themes.lock().unwrap();
+    Ok(theme_list(themes.list(), themes.active()))
+}
+
+pub async fn select_theme(
+    State(state): State<UnitedStates>,
+    Path(name): Path<String>,
+) -> Result<Markup, (StatusCode, String)> {
+    let (list, stylesheet) = {
+        let mut themes = state.themes.lock().unwrap();
+        themes
+            .set_active(&name)
+            .map_err(|e| (StatusCode::NOT_FOUND, e))?;
+        (
+            theme_list(themes.list(), themes.active()),
+            themes.stylesheet_url(),
+        )
+    };
+
+    // Swap the stylesheet on every overlay that is already open
+    let css = html! {
+        link id="theme-css" rel="stylesheet" href=(stylesheet) hx-swap-oob="true";
+    };
+    crate::send_to_clients(&state.connection_state, &css.into_string(), |_| true);
+
+    Ok(list)
+}
+
+#[derive(Deserialize)]
+pub struct LeaderboardQuery {
+    /// `stream`, `month` or `all_time`, this stream when left out.
+    pub period: Option<Period>,
+}
+
+pub async fn get_leaderboard(
+    State(state): State<UnitedStates>,
+    Path(board): Path<Board>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Markup, (StatusCode, String)> {
+    let leaderboard = load_leaderboard(&state, board, query.period.unwrap_or_default()).await?;
+    Ok(leaderboard_html(&leaderboard))
+}
+
+pub async fn get_leaderboard_json(
+    State(state): State<UnitedStates>,
+    Path(board): Path<Board>,
+    Query(query): Query<LeaderboardQuery>,
+) -> Result<Json<Leaderboard>, (StatusCode, String)> {
+    let leaderboard = load_leaderboard(&state, board, query.period.unwrap_or_default()).await?;
+    Ok(Json(leaderboard))
+}
+
+async fn load_leaderboard(
+    state: &UnitedStates,
+    board: Board,
+    period: Period,
+) -> Result<Leaderboard, (StatusCode, String)> {
+    let Some(leaderboards) = &state.leaderboards else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "leaderboards are not set up".to_string(),
+        ));
+    };
+    leaderboards
+        .get(board, period)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// An alert in the dashboard lists, flagged ones stand out with the reason.
+fn event_item(event: &messages::DisplayMessage) -> Markup {
+    html! {
+        @match &event.flag {
+            Some(flag) => li class="flagged" { span class="flag" { (flag) } " " (event.message) },
+            None => li { (event.message) },
+        }
+    }
+}
+
+fn theme_list(themes: Vec<String>, active: &str) -> Markup {
+    html! {
+        ul id="themes" {
+            @for theme in themes {
+                @if theme == active {
+                    li class="active" { (theme) }
+                } @else {
+                    li hx-get=(format!("/admin/themes/{}", theme)) hx-target="#themes" hx-swap="outerHTML" { (theme) }
+                }
+            }
+        }
+    }
+}
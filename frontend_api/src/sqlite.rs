@@ -0,0 +1,291 @@
+use anyhow::Ok;
+use serde::Serialize;
+use sqlx::{pool::PoolConnection, Connection, Sqlite};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AssetRow {
+    pub id: i64,
+    pub file_name: String,
+    pub original_name: String,
+    pub kind: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AssignmentRow {
+    pub event_type: String,
+    pub kind: String,
+    pub asset_id: i64,
+    pub file_name: String,
+}
+
+pub async fn write_new_asset(
+    mut conn: PoolConnection<Sqlite>,
+    file_name: &str,
+    original_name: &str,
+    kind: &str,
+    content_type: &str,
+    size_bytes: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO alert_assets ( file_name, original_name, kind, content_type, size_bytes )
+VALUES ( ?, ?, ?, ?, ? )
+        "#,
+        file_name,
+        original_name,
+        kind,
+        content_type,
+        size_bytes
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_assets(mut conn: PoolConnection<Sqlite>) -> anyhow::Result<Vec<AssetRow>> {
+    let db_results = sqlx::query_as!(
+        AssetRow,
+        r#"
+SELECT id, file_name, original_name, kind, content_type, size_bytes
+FROM alert_assets
+ORDER BY id DESC
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(db_results)
+}
+
+pub async fn get_asset(
+    mut conn: PoolConnection<Sqlite>,
+    id: i64,
+) -> anyhow::Result<Option<AssetRow>> {
+    let db_results = sqlx::query_as!(
+        AssetRow,
+        r#"
+SELECT id, file_name, original_name, kind, content_type, size_bytes
+FROM alert_assets
+WHERE id = ?
+        "#,
+        id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(db_results)
+}
+
+/// Deletes the asset along with its assignments, both or neither.
+pub async fn delete_asset(mut conn: PoolConnection<Sqlite>, id: i64) -> anyhow::Result<()> {
+    let mut tx = conn.begin().await?;
+    sqlx::query!(
+        r#"
+DELETE FROM alert_asset_assignments WHERE asset_id = ?
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+DELETE FROM alert_assets WHERE id = ?
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn assign_asset(
+    mut conn: PoolConnection<Sqlite>,
+    event_type: &str,
+    kind: &str,
+    asset_id: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT OR REPLACE INTO alert_asset_assignments ( event_type, kind, asset_id )
+VALUES ( ?, ?, ? )
+        "#,
+        event_type,
+        kind,
+        asset_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_assignments(
+    mut conn: PoolConnection<Sqlite>,
+) -> anyhow::Result<Vec<AssignmentRow>> {
+    let db_results = sqlx::query_as!(
+        AssignmentRow,
+        r#"
+SELECT a.event_type, a.kind, a.asset_id, s.file_name
+FROM alert_asset_assignments a
+JOIN alert_assets s ON s.id = a.asset_id
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(db_results)
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LeaderRow {
+    pub name: String,
+    pub total: i64,
+}
+
+/// Unix seconds the latest stream went live, `None` before the first one.
+pub async fn get_stream_started_at(
+    mut conn: PoolConnection<Sqlite>,
+) -> anyhow::Result<Option<i64>> {
+    let db_results = sqlx::query!(
+        r#"
+SELECT CAST(strftime('%s', started_at) AS INTEGER) AS "started_at!: i64"
+FROM streams
+ORDER BY id DESC
+LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(db_results.map(|row| row.started_at))
+}
+
+/// Top gifters since `datetime(since, modifier)`, anonymous gifts don't count. Viewers are
+/// told apart by id and shown with the name they last gifted under.
+pub async fn get_top_gifters(
+    mut conn: PoolConnection<Sqlite>,
+    since: &str,
+    modifier: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<LeaderRow>> {
+    let db_results = sqlx::query_as!(
+        LeaderRow,
+        r#"
+SELECT (
+    SELECT latest.user_name FROM gift_subs_events latest
+    WHERE latest.user_id = gifts.user_id
+    ORDER BY latest.id DESC LIMIT 1
+) AS "name!: String", SUM(total) AS "total!: i64"
+FROM gift_subs_events gifts
+WHERE is_anonymous = 0
+AND COALESCE(gifted_at, '1970-01-01 00:00:00') >= datetime(?, ?)
+GROUP BY user_id
+ORDER BY 2 DESC
+LIMIT ?
+        "#,
+        since,
+        modifier,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(db_results)
+}
+
+/// Top cheerers by bits since `datetime(since, modifier)`, anonymous cheers (user id 0)
+/// don't count. Shown with the name they last cheered under.
+pub async fn get_top_cheerers(
+    mut conn: PoolConnection<Sqlite>,
+    since: &str,
+    modifier: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<LeaderRow>> {
+    let db_results = sqlx::query_as!(
+        LeaderRow,
+        r#"
+SELECT (
+    SELECT latest.user_name FROM cheer_events latest
+    WHERE latest.user_id = cheers.user_id
+    ORDER BY latest.id DESC LIMIT 1
+) AS "name!: String", SUM(bits) AS "total!: i64"
+FROM cheer_events cheers
+WHERE user_id <> 0
+AND cheered_at >= datetime(?, ?)
+GROUP BY user_id
+ORDER BY 2 DESC
+LIMIT ?
+        "#,
+        since,
+        modifier,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(db_results)
+}
+
+/// The biggest raid of each channel since `datetime(since, modifier)`, shown with the name
+/// the channel last raided under.
+pub async fn get_biggest_raids(
+    mut conn: PoolConnection<Sqlite>,
+    since: &str,
+    modifier: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<LeaderRow>> {
+    let db_results = sqlx::query_as!(
+        LeaderRow,
+        r#"
+SELECT (
+    SELECT latest.from_broadcaster_user_name FROM raid_events latest
+    WHERE latest.from_broadcaster_user_id = raids.from_broadcaster_user_id
+    ORDER BY latest.id DESC LIMIT 1
+) AS "name!: String", MAX(viewers) AS "total!: i64"
+FROM raid_events raids
+WHERE raid_at >= datetime(?, ?)
+GROUP BY from_broadcaster_user_id
+ORDER BY 2 DESC
+LIMIT ?
+        "#,
+        since,
+        modifier,
+        limit
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(db_results)
+}
+
+#[derive(Debug, Clone)]
+pub struct TickerRow {
+    pub slot: String,
+    pub message: String,
+}
+
+pub async fn get_ticker_events(mut conn: PoolConnection<Sqlite>) -> anyhow::Result<Vec<TickerRow>> {
+    let db_results = sqlx::query_as!(
+        TickerRow,
+        r#"
+SELECT slot, message
+FROM ticker_events
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(db_results)
+}
+
+pub async fn save_ticker_event(
+    mut conn: PoolConnection<Sqlite>,
+    slot: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT OR REPLACE INTO ticker_events ( slot, message )
+VALUES ( ?, ? )
+        "#,
+        slot,
+        message
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
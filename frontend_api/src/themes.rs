@@ -0,0 +1,348 @@
+//! Alert themes loaded from disk.
+//!
+//! A theme is a directory under the themes dir:
+//!
+//! ```text
+//! themes/<name>/theme.css
+//! themes/<name>/templates/<event_type>.html   e.g. follow.html, raid.html
+//! themes/<name>/templates/partials/...        shared with `{% include "partials/..." %}`
+//! themes/<name>/sounds/..., themes/<name>/img/...
+//! ```
+//!
+//! Templates are minijinja and get `message`, `event_type`, `name` and `event` (the twitch
+//! event fields), plus the story's `title`, `mood` and `emphasis_words`, and the viewer's
+//! `character` sheet when they have one (check `character.leveled_up`) and the `roll`
+//! (`natural`, `modifier`, `total` and `outcome`), which the overlay already animates before
+//! the template shows up. The `emphasize` filter highlights the words like the built in
+//! markup does: `{{ message | emphasize(emphasis_words) }}`.
+//! Event types without a template fall back to the built in maud markup.
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use messages::{DisplayMessage, TwitchEvent};
+use minijinja::{context, Environment, ErrorKind, Value};
+
+pub type Themes = Arc<Mutex<ThemeManager>>;
+
+pub struct ThemeManager {
+    themes_dir: PathBuf,
+    active: String,
+    env: Environment<'static>,
+    /// Re-read templates from disk on every render, so edits show up without a restart.
+    hot_reload: bool,
+}
+
+impl ThemeManager {
+    pub fn new(themes_dir: String, active: String) -> ThemeManager {
+        let themes_dir = PathBuf::from(themes_dir);
+        let env = theme_environment(&themes_dir, &active);
+        ThemeManager {
+            themes_dir,
+            active,
+            env,
+            hot_reload: std::env::var("ENV") != Ok("production".to_string()),
+        }
+    }
+
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+
+    /// Every directory in the themes dir is a theme.
+    pub fn list(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(&self.themes_dir) else {
+            println!("Could not read themes dir: {:?}", self.themes_dir);
+            return vec![];
+        };
+        let mut themes: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        themes.sort();
+        themes
+    }
+
+    pub fn set_active(&mut self, name: &str) -> Result<(), String> {
+        if !self.list().iter().any(|theme| theme == name) {
+            return Err(format!("no theme named {}", name));
+        }
+        self.active = name.to_string();
+        self.env = theme_environment(&self.themes_dir, name);
+        Ok(())
+    }
+
+    /// Url the overlay loads the active theme's css from.
+    pub fn stylesheet_url(&self) -> String {
+        format!("/themes/{}/theme.css", self.active)
+    }
+
+    /// Renders the alert with the active theme, or the one a rule picked for it along with
+    /// that theme's css. `None` if the theme has no template for it.
+    pub fn render_alert(&mut self, message: &DisplayMessage) -> Option<String> {
+        if self.hot_reload {
+            self.env.clear_templates();
+        }
+
+        let other_theme = message
+            .theme
+            .as_deref()
+            .filter(|theme| *theme != self.active)
+            .filter(|theme| {
+                let exists = self.list().iter().any(|name| name == theme);
+                if !exists {
+                    println!("No theme named {}, using {}", theme, self.active);
+                }
+                exists
+            });
+        let other_env = other_theme.map(|theme| theme_environment(&self.themes_dir, theme));
+        let env = other_env.as_ref().unwrap_or(&self.env);
+        let theme_name = other_theme.unwrap_or(&self.active);
+
+        let event_type = message.payload.event_type();
+        let template = match env.get_template(&format!("{}.html", event_type)) {
+            Ok(template) => template,
+            Err(e) if e.kind() == ErrorKind::TemplateNotFound => return None,
+            Err(e) => {
+                println!(
+                    "Could not load {} template from theme {}: {}",
+                    event_type, theme_name, e
+                );
+                return None;
+            }
+        };
+
+        let rendered = template.render(context! {
+            message => message.message,
+            event_type => event_type,
+            name => display_name(&message.payload),
+            event => event_fields(&message.payload),
+            image_url => message.image_url,
+            avatar_url => message
+                .payload
+                .profile()
+                .and_then(|profile| profile.profile_image_url.clone()),
+            sound_url => message.sound_url,
+            animation => message.animation,
+            display_time => message.display_time,
+            title => message.title,
+            mood => message.mood.as_str(),
+            emphasis_words => message.emphasis_words,
+            character => message.character,
+            roll => message.roll.map(|roll| context! {
+                natural => roll.natural,
+                modifier => roll.modifier,
+                total => roll.total(),
+                outcome => roll.outcome(),
+            }),
+        });
+
+        match rendered {
+            Ok(html) => match other_theme {
+                Some(theme) => Some(format!(
+                    r#"<link rel="stylesheet" href="/themes/{}/theme.css">{}"#,
+                    theme, html
+                )),
+                None => Some(html),
+            },
+            Err(e) => {
+                println!(
+                    "Could not render {} template from theme {}: {}",
+                    event_type, theme_name, e
+                );
+                None
+            }
+        }
+    }
+}
+
+fn theme_environment(themes_dir: &Path, theme: &str) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_loader(minijinja::path_loader(
+        themes_dir.join(theme).join("templates"),
+    ));
+    env.add_filter("emphasize", |text: String, words: Vec<String>| {
+        Value::from_safe_string(crate::htmx::emphasized(&text, &words).into_string())
+    });
+    env
+}
+
+/// The name shown on the alert for the user behind the event.
+pub fn display_name(event: &TwitchEvent) -> String {
+    event.user_name().unwrap_or("Anonymous").to_string()
+}
+
+/// The fields of the event itself, without the enum variant wrapped around them.
+fn event_fields(event: &TwitchEvent) -> minijinja::Value {
+    let fields = serde_json::to_value(event)
+        .ok()
+        .and_then(|value| value.as_object().and_then(|o| o.values().next().cloned()))
+        .unwrap_or_default();
+    minijinja::Value::from_serialize(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{CharacterSheet, FollowEvent, Mood};
+
+    use super::*;
+
+    fn themes_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("themes-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for theme in ["plain", "fancy"] {
+            std::fs::create_dir_all(dir.join(theme).join("templates")).unwrap();
+        }
+        write_template(
+            &dir,
+            "plain",
+            "follow.html",
+            "<p>{{ name }}: {{ title }}</p>",
+        );
+        write_template(&dir, "fancy", "follow.html", "<h1>{{ name }}</h1>");
+        dir
+    }
+
+    fn write_template(dir: &Path, theme: &str, file: &str, contents: &str) {
+        std::fs::write(dir.join(theme).join("templates").join(file), contents).unwrap();
+    }
+
+    fn manager(dir: &Path, hot_reload: bool) -> ThemeManager {
+        let mut themes = ThemeManager::new(dir.to_string_lossy().into_owned(), "plain".into());
+        themes.hot_reload = hot_reload;
+        themes
+    }
+
+    fn follow(theme: Option<&str>) -> DisplayMessage {
+        DisplayMessage {
+            message: "a story".to_string(),
+            image_url: "none".to_string(),
+            sound_url: "none".to_string(),
+            animation: "none".to_string(),
+            volume: 1.0,
+            display_time: 1000,
+            payload: TwitchEvent::ChannelFollow(FollowEvent {
+                user_name: "ferris".to_string(),
+                user_id: 1,
+                broadcaster_user_id: 2,
+                profile: None,
+            }),
+            title: "A Title".to_string(),
+            mood: Mood::Epic,
+            emphasis_words: vec![],
+            flag: None,
+            theme: theme.map(str::to_string),
+            priority: 0,
+            character: None,
+            roll: None,
+        }
+    }
+
+    #[test]
+    fn render_alert_uses_the_active_theme_template() {
+        let dir = themes_dir("render");
+        let mut themes = manager(&dir, false);
+
+        assert_eq!(themes.list(), vec!["fancy", "plain"]);
+        assert_eq!(
+            themes.render_alert(&follow(None)).as_deref(),
+            Some("<p>ferris: A Title</p>")
+        );
+    }
+
+    #[test]
+    fn render_alert_is_none_without_a_template() {
+        let dir = themes_dir("missing");
+        let mut themes = manager(&dir, false);
+        std::fs::remove_file(dir.join("plain").join("templates").join("follow.html")).unwrap();
+
+        assert_eq!(themes.render_alert(&follow(None)), None);
+    }
+
+    #[test]
+    fn render_alert_uses_the_theme_a_rule_picked_with_its_css() {
+        let dir = themes_dir("rule");
+        let mut themes = manager(&dir, false);
+
+        assert_eq!(
+            themes.render_alert(&follow(Some("fancy"))).as_deref(),
+            Some(r#"<link rel="stylesheet" href="/themes/fancy/theme.css"><h1>ferris</h1>"#)
+        );
+        assert_eq!(
+            themes.render_alert(&follow(Some("unknown"))).as_deref(),
+            Some("<p>ferris: A Title</p>")
+        );
+    }
+
+    #[test]
+    fn hot_reload_picks_up_edited_templates() {
+        let dir = themes_dir("reload");
+        let mut themes = manager(&dir, true);
+        assert_eq!(
+            themes.render_alert(&follow(None)).as_deref(),
+            Some("<p>ferris: A Title</p>")
+        );
+
+        write_template(&dir, "plain", "follow.html", "<p>{{ name }} followed</p>");
+        assert_eq!(
+            themes.render_alert(&follow(None)).as_deref(),
+            Some("<p>ferris followed</p>")
+        );
+    }
+
+    #[test]
+    fn without_hot_reload_templates_stay_cached() {
+        let dir = themes_dir("cached");
+        let mut themes = manager(&dir, false);
+        themes.render_alert(&follow(None));
+
+        write_template(&dir, "plain", "follow.html", "<p>{{ name }} followed</p>");
+        assert_eq!(
+            themes.render_alert(&follow(None)).as_deref(),
+            Some("<p>ferris: A Title</p>")
+        );
+    }
+
+    #[test]
+    fn set_active_switches_to_known_themes_only() {
+        let dir = themes_dir("active");
+        let mut themes = manager(&dir, false);
+
+        assert!(themes.set_active("unknown").is_err());
+        assert_eq!(themes.active(), "plain");
+        themes.set_active("fancy").unwrap();
+        assert_eq!(themes.stylesheet_url(), "/themes/fancy/theme.css");
+        assert_eq!(
+            themes.render_alert(&follow(None)).as_deref(),
+            Some("<h1>ferris</h1>")
+        );
+    }
+
+    #[test]
+    fn default_theme_shows_level_ups_from_the_shared_partial() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("themes");
+        let mut themes = manager(&dir, false);
+        themes.set_active("default").unwrap();
+        let mut message = follow(None);
+        message.character = Some(CharacterSheet {
+            user_name: "ferris".to_string(),
+            class: "bard".to_string(),
+            race: "gnome".to_string(),
+            level: 3,
+            xp: 300,
+            inventory: vec!["a lucky coin".to_string()],
+            leveled_up: true,
+        });
+
+        let html = themes.render_alert(&message).unwrap();
+        assert!(
+            html.contains(r#"<p class="level-up">Level 3 gnome bard! Found a lucky coin.</p>"#),
+            "{}",
+            html
+        );
+        message.character.as_mut().unwrap().leveled_up = false;
+        assert!(!themes.render_alert(&message).unwrap().contains("level-up"));
+    }
+}
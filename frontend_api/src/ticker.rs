@@ -0,0 +1,141 @@
+//! The recent events ticker on the overlay.
+//!
+//! It shows the latest follower, sub and raid and the top cheer, taken from the alerts as
+//! they come in from the AIManager. The slots are kept in sqlite so the ticker survives
+//! restarts, and it is sent on the `ticker` topic every time one changes.
+use maud::{html, Markup};
+use messages::{DisplayMessage, TwitchEvent};
+use sqlx::SqlitePool;
+
+use crate::{sqlite, themes::display_name, types::Queues};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TickerSlot {
+    Follower,
+    /// Subs, resubs and gifts.
+    Sub,
+    Raid,
+    /// The biggest cheer.
+    Cheer,
+}
+
+impl TickerSlot {
+    pub const ALL: [TickerSlot; 4] = [
+        TickerSlot::Follower,
+        TickerSlot::Sub,
+        TickerSlot::Raid,
+        TickerSlot::Cheer,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TickerSlot::Follower => "follower",
+            TickerSlot::Sub => "sub",
+            TickerSlot::Raid => "raid",
+            TickerSlot::Cheer => "cheer",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            TickerSlot::Follower => "Latest Follower",
+            TickerSlot::Sub => "Latest Sub",
+            TickerSlot::Raid => "Latest Raid",
+            TickerSlot::Cheer => "Top Cheer",
+        }
+    }
+
+    /// The slot the event goes in, `None` for events the ticker doesn't show.
+    pub fn for_event(event: &TwitchEvent) -> Option<TickerSlot> {
+        match event {
+            TwitchEvent::ChannelFollow(_) => Some(TickerSlot::Follower),
+            TwitchEvent::ChannelSubscribe(_)
+            | TwitchEvent::ChannelResubscribe(_)
+            | TwitchEvent::ChannelSubGift(_) => Some(TickerSlot::Sub),
+            TwitchEvent::ChannelRaid(_) => Some(TickerSlot::Raid),
+            TwitchEvent::ChannelCheer(_) => Some(TickerSlot::Cheer),
+            TwitchEvent::FollowBurst(_)
+            | TwitchEvent::ChannelHypeTrainBegin(_)
+            | TwitchEvent::ChannelHypeTrainEnd(_)
+            | TwitchEvent::StreamOnline(_)
+            | TwitchEvent::StreamOffline(_) => None,
+        }
+    }
+}
+
+impl std::str::FromStr for TickerSlot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TickerSlot::ALL
+            .into_iter()
+            .find(|slot| slot.as_str() == s)
+            .ok_or_else(|| format!("unknown ticker slot: {}", s))
+    }
+}
+
+/// Keeps the ticker slots in sqlite.
+#[derive(Clone)]
+pub struct TickerStore {
+    pool: SqlitePool,
+}
+
+impl TickerStore {
+    pub fn new(pool: SqlitePool) -> TickerStore {
+        TickerStore { pool }
+    }
+
+    /// Puts the slots saved before the restart back in the queues.
+    pub async fn restore(&self, queues: &std::sync::Mutex<Queues>) -> anyhow::Result<()> {
+        let conn = self.pool.acquire().await?;
+        let rows = sqlite::get_ticker_events(conn).await?;
+        let mut queues = queues.lock().unwrap();
+        for row in rows {
+            let (Ok(slot), Ok(message)) = (
+                row.slot.parse::<TickerSlot>(),
+                serde_json::from_str::<DisplayMessage>(&row.message),
+            ) else {
+                println!("skipping the saved {} ticker slot", row.slot);
+                continue;
+            };
+            queues.set_ticker_slot(slot, message);
+        }
+        Ok(())
+    }
+
+    pub async fn save(&self, slot: TickerSlot, message: &DisplayMessage) -> anyhow::Result<()> {
+        let json = serde_json::to_string(message)?;
+        let conn = self.pool.acquire().await?;
+        sqlite::save_ticker_event(conn, slot.as_str(), &json).await
+    }
+}
+
+pub fn ticker_html(queues: &Queues, sequence: u64) -> Markup {
+    html! {
+        div id="ticker" data-seq=(sequence) {
+            @for slot in TickerSlot::ALL {
+                @if let Some(message) = queues.ticker_slot(slot) {
+                    span class={ "ticker-item ticker-" (slot.as_str()) } {
+                        span class="ticker-title" { (slot.title()) }
+                        " "
+                        span class="ticker-name" { (display_name(&message.payload)) }
+                        @if let Some(detail) = detail(&message.payload) {
+                            " "
+                            span class="ticker-detail" { (detail) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn detail(event: &TwitchEvent) -> Option<String> {
+    match event {
+        TwitchEvent::ChannelResubscribe(sub) => Some(format!("{} months", sub.cumulative_months)),
+        TwitchEvent::ChannelSubGift(gift) => Some(format!("{} gifted", gift.total)),
+        TwitchEvent::ChannelRaid(raid) => Some(format!("{} viewers", raid.viewers)),
+        TwitchEvent::ChannelCheer(cheer) => Some(format!("{} bits", cheer.bits)),
+        _ => None,
+    }
+}
@@ -0,0 +1,339 @@
+//! Text to speech for the AI stories.
+//!
+//! A [`TtsEngine`] renders the text of each alert into a wav in the speech dir, served under
+//! `/speech`. The clips wait in the tts queue until they are played on the overlay, either
+//! right after their alert or with "Play Next" on the admin page, and are deleted once they
+//! have had time to play.
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail};
+use axum::{extract::State, http::StatusCode};
+use maud::{html, Markup};
+use messages::DisplayMessage;
+use serde::Deserialize;
+
+use crate::{types::ConnectionMap, types::Topic, UnitedStates};
+
+/// Url path the speech dir is served under.
+pub const SPEECH_PATH: &str = "/speech";
+
+/// How long a played clip is kept on top of its length, for overlays that load it late.
+const PLAYED_CLIP_GRACE: Duration = Duration::from_secs(60);
+
+/// Turns text into speech.
+pub trait TtsEngine: Send + Sync {
+    /// Renders `text` into a wav file at `output`.
+    fn synthesize(&self, text: &str, output: &Path) -> anyhow::Result<()>;
+}
+
+/// Runs `espeak-ng`, with its default voice unless one is given.
+pub struct EspeakEngine {
+    pub voice: Option<String>,
+}
+
+impl TtsEngine for EspeakEngine {
+    fn synthesize(&self, text: &str, output: &Path) -> anyhow::Result<()> {
+        let mut command = Command::new("espeak-ng");
+        if let Some(voice) = &self.voice {
+            command.arg("-v").arg(voice);
+        }
+        command.arg("-w").arg(output).arg("--stdin");
+        run_with_stdin(command, text)
+    }
+}
+
+/// Runs `piper` with the given `.onnx` voice model.
+pub struct PiperEngine {
+    pub model: String,
+}
+
+impl TtsEngine for PiperEngine {
+    fn synthesize(&self, text: &str, output: &Path) -> anyhow::Result<()> {
+        let mut command = Command::new("piper");
+        command
+            .arg("--model")
+            .arg(&self.model)
+            .arg("--output_file")
+            .arg(output);
+        run_with_stdin(command, text)
+    }
+}
+
+fn run_with_stdin(mut command: Command, text: &str) -> anyhow::Result<()> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or(anyhow!("no stdin for the tts engine"))?
+        .write_all(text.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "tts engine exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// Picks the engine by name, `espeak` or `piper`. Piper needs a voice model.
+pub fn engine_by_name(name: &str, voice: Option<String>) -> anyhow::Result<Arc<dyn TtsEngine>> {
+    match name {
+        "espeak" | "espeak-ng" => Ok(Arc::new(EspeakEngine { voice })),
+        "piper" => {
+            let model = voice.ok_or(anyhow!("piper needs a voice model"))?;
+            Ok(Arc::new(PiperEngine { model }))
+        }
+        other => bail!("unknown tts engine {}", other),
+    }
+}
+
+/// How to say user names the engine gets wrong, keyed by lowercase user name.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Pronunciations(HashMap<String, String>);
+
+impl Pronunciations {
+    pub fn load(path: &str) -> anyhow::Result<Pronunciations> {
+        let file = std::fs::read_to_string(path)?;
+        let table: HashMap<String, String> = serde_json::from_str(&file)?;
+        Ok(Pronunciations(
+            table
+                .into_iter()
+                .map(|(name, spoken)| (name.to_lowercase(), spoken))
+                .collect(),
+        ))
+    }
+
+    /// Swaps every word that is a known user name for how it should be said,
+    /// ignoring case and the punctuation around it like `@name!`.
+    pub fn apply(&self, text: &str) -> String {
+        text.split(' ')
+            .map(|word| {
+                let name = word.trim_matches(|c: char| !c.is_alphanumeric() && c != '_');
+                match self.0.get(&name.to_lowercase()) {
+                    Some(spoken) if !name.is_empty() => word.replacen(name, spoken, 1),
+                    _ => word.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// A rendered story waiting to be played.
+#[derive(Clone, Debug)]
+pub struct TtsClip {
+    pub text: String,
+    pub audio_url: String,
+    pub duration_ms: Option<u64>,
+    /// The wav in the speech dir, deleted after it is played.
+    pub path: PathBuf,
+}
+
+#[derive(Clone)]
+pub struct TtsManager {
+    engine: Arc<dyn TtsEngine>,
+    pronunciations: Arc<Pronunciations>,
+    output_dir: PathBuf,
+    /// Play each clip right after its alert instead of waiting for "Play Next".
+    pub autoplay: bool,
+}
+
+impl TtsManager {
+    pub fn new(
+        engine: Arc<dyn TtsEngine>,
+        pronunciations: Pronunciations,
+        output_dir: String,
+        autoplay: bool,
+    ) -> TtsManager {
+        TtsManager {
+            engine,
+            pronunciations: Arc::new(pronunciations),
+            output_dir: PathBuf::from(output_dir),
+            autoplay,
+        }
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// Makes sure the speech dir exists and clears out clips left from the last run.
+    pub async fn prepare(&self) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.output_dir).await?;
+        let mut entries = tokio::fs::read_dir(&self.output_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().is_some_and(|ext| ext == "wav") {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the message text, returning `None` when there is nothing to say.
+    pub async fn render(&self, message: &DisplayMessage) -> anyhow::Result<Option<TtsClip>> {
+        if message.message.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let file_name = format!("{}.wav", nanos);
+        let output = self.output_dir.join(&file_name);
+
+        let spoken = self.pronunciations.apply(&message.message);
+        let engine = self.engine.clone();
+        let path = output.clone();
+        tokio::task::spawn_blocking(move || engine.synthesize(&spoken, &path)).await??;
+
+        let bytes = tokio::fs::read(&output).await?;
+        Ok(Some(TtsClip {
+            text: message.message.clone(),
+            audio_url: format!("{}/{}", SPEECH_PATH, file_name),
+            duration_ms: wav_duration_ms(&bytes),
+            path: output,
+        }))
+    }
+}
+
+/// Length of a pcm wav from its header, so autoplay knows how long to wait.
+fn wav_duration_ms(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() < 44 || !bytes.starts_with(b"RIFF") {
+        return None;
+    }
+    let byte_rate = u32::from_le_bytes(bytes[28..32].try_into().ok()?) as u64;
+    if byte_rate == 0 {
+        return None;
+    }
+
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().ok()?) as usize;
+        if &bytes[offset..offset + 4] == b"data" {
+            // Streamed wavs leave the size as a placeholder, so only count what is there
+            let available = bytes.len() - offset - 8;
+            return Some(size.min(available) as u64 * 1000 / byte_rate);
+        }
+        offset += 8 + size;
+    }
+    None
+}
+
+/// Tells the overlays showing alerts to play the clip.
+pub fn play_clip(connection_state: &ConnectionMap, clip: &TtsClip) {
+    let frame = html! {
+        div id="tts-clip" data-audio=(clip.audio_url) {}
+    };
+    crate::send_to_clients(connection_state, &frame.into_string(), |client| {
+        client.topics.contains(&Topic::Alerts)
+    });
+
+    // Played clips aren't needed again, don't let them pile up for the whole stream
+    let path = clip.path.clone();
+    let keep_for = Duration::from_millis(clip.duration_ms.unwrap_or_default()) + PLAYED_CLIP_GRACE;
+    tokio::spawn(async move {
+        tokio::time::sleep(keep_for).await;
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            println!("could not remove played tts clip {}: {}", path.display(), e);
+        }
+    });
+}
+
+pub async fn get_tts_queue(
+    State(state): State<UnitedStates>,
+) -> Result<Markup, (StatusCode, String)> {
+    let queues = state.event_queues.lock().unwrap();
+    Ok(html! {
+        ul {
+            @for clip in queues.tts.iter() {
+                li { (clip.text) }
+            }
+        }
+    })
+}
+
+pub async fn play_next(State(state): State<UnitedStates>) -> Result<Markup, (StatusCode, String)> {
+    let clip = state.event_queues.lock().unwrap().tts.pop_front();
+    match clip {
+        Some(clip) => {
+            play_clip(&state.connection_state, &clip);
+            Ok(html! {})
+        }
+        None => Err((StatusCode::NOT_FOUND, "nothing to play".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pronunciations() -> Pronunciations {
+        Pronunciations(HashMap::from([(
+            "nullvoxpopuli".to_string(),
+            "null vox populi".to_string(),
+        )]))
+    }
+
+    /// A pcm wav header for `data_len` bytes of audio at `byte_rate`.
+    fn wav(byte_rate: u32, data_len: u32, audio: usize) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend(b"fmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend([1, 0, 1, 0]);
+        bytes.extend((byte_rate / 2).to_le_bytes());
+        bytes.extend(byte_rate.to_le_bytes());
+        bytes.extend([2, 0, 16, 0]);
+        bytes.extend(b"data");
+        bytes.extend(data_len.to_le_bytes());
+        bytes.extend(vec![0; audio]);
+        bytes
+    }
+
+    #[test]
+    fn apply_swaps_names_ignoring_case_and_punctuation() {
+        assert_eq!(
+            pronunciations().apply("Welcome @NullVoxPopuli! to the party"),
+            "Welcome @null vox populi! to the party"
+        );
+    }
+
+    #[test]
+    fn apply_leaves_other_words_alone() {
+        assert_eq!(
+            pronunciations().apply("nullvox joined the  party"),
+            "nullvox joined the  party"
+        );
+    }
+
+    #[test]
+    fn wav_duration_ms_reads_the_data_chunk() {
+        assert_eq!(wav_duration_ms(&wav(32000, 16000, 16000)), Some(500));
+    }
+
+    #[test]
+    fn wav_duration_ms_only_counts_the_audio_there_is() {
+        // Streamed wavs leave the data size as a placeholder
+        assert_eq!(wav_duration_ms(&wav(32000, u32::MAX, 32000)), Some(1000));
+    }
+
+    #[test]
+    fn wav_duration_ms_rejects_other_files() {
+        assert_eq!(wav_duration_ms(b"ID3 not a wav"), None);
+        assert_eq!(wav_duration_ms(&wav(0, 100, 100)), None);
+    }
+}
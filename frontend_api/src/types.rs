@@ -1,20 +1,46 @@
+use crate::themes::display_name;
+use crate::ticker::TickerSlot;
+use crate::tts::TtsClip;
 use futures_channel::mpsc::UnboundedSender;
-use messages::DisplayMessage;
+use messages::{DisplayMessage, TwitchEvent};
+use serde::Deserialize;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     net::SocketAddr,
     sync::{Arc, Mutex},
 };
 use tokio_tungstenite::tungstenite::Message;
 pub type Tx = UnboundedSender<Message>;
-pub type ConnectionMap = Arc<Mutex<HashMap<SocketAddr, Tx>>>;
+pub type ConnectionMap = Arc<Mutex<HashMap<SocketAddr, OverlayClient>>>;
 pub type EventQueues = Arc<Mutex<Queues>>;
+pub type OverlayLog = Arc<Mutex<OverlayState>>;
+pub type AckSender = tokio::sync::mpsc::UnboundedSender<OverlayAck>;
+pub type PrimaryOverlay = Arc<Mutex<Option<SocketAddr>>>;
+
+/// How many broadcast frames we keep around for overlays that reconnect.
+pub const OVERLAY_HISTORY_LEN: usize = 50;
+
+/// Shortest time an alert stays up when no overlay acks it.
+pub const MIN_ALERT_DISPLAY_MS: u64 = 10000;
+
+/// Extra time we give the overlay to report an alert finished before moving on without it.
+pub const ALERT_ACK_GRACE_MS: u64 = 5000;
+
+/// Most events folded into one alert in catch-up mode.
+pub const MAX_COMBINED_ALERTS: usize = 20;
+
+/// Names listed on a combined alert before the rest are counted.
+const MAX_COMBINED_NAMES: usize = 5;
 
 pub struct Queues {
     pub unpublished_events: VecDeque<DisplayMessage>,
-    pub tts: VecDeque<DisplayMessage>,
+    pub tts: VecDeque<TtsClip>,
     pub latest_events: VecDeque<DisplayMessage>,
+    /// What the ticker shows, see [`Queues::update_ticker`].
+    pub last_follow: Option<DisplayMessage>,
     pub last_sub: Option<DisplayMessage>,
+    pub last_raid: Option<DisplayMessage>,
+    pub top_cheer: Option<DisplayMessage>,
 }
 
 pub static EVENT_QUEUE_ACTIVE: std::sync::atomic::AtomicBool =
@@ -26,7 +52,606 @@ impl Queues {
             unpublished_events: VecDeque::new(),
             tts: VecDeque::new(),
             latest_events: VecDeque::new(),
+            last_follow: None,
             last_sub: None,
+            last_raid: None,
+            top_cheer: None,
+        }
+    }
+
+    pub fn ticker_slot(&self, slot: TickerSlot) -> Option<&DisplayMessage> {
+        match slot {
+            TickerSlot::Follower => self.last_follow.as_ref(),
+            TickerSlot::Sub => self.last_sub.as_ref(),
+            TickerSlot::Raid => self.last_raid.as_ref(),
+            TickerSlot::Cheer => self.top_cheer.as_ref(),
+        }
+    }
+
+    fn ticker_slot_mut(&mut self, slot: TickerSlot) -> &mut Option<DisplayMessage> {
+        match slot {
+            TickerSlot::Follower => &mut self.last_follow,
+            TickerSlot::Sub => &mut self.last_sub,
+            TickerSlot::Raid => &mut self.last_raid,
+            TickerSlot::Cheer => &mut self.top_cheer,
+        }
+    }
+
+    /// Puts the alert in the ticker slot for its event, returning the slot if it changed.
+    /// The cheer slot only takes a cheer bigger than the one in it.
+    pub fn update_ticker(&mut self, message: &DisplayMessage) -> Option<TickerSlot> {
+        let slot = TickerSlot::for_event(&message.payload)?;
+        let current = self.ticker_slot_mut(slot);
+        if let (Some(TwitchEvent::ChannelCheer(top)), TwitchEvent::ChannelCheer(cheer)) = (
+            current.as_ref().map(|current| &current.payload),
+            &message.payload,
+        ) {
+            if top.bits >= cheer.bits {
+                return None;
+            }
+        }
+        *current = Some(message.clone());
+        Some(slot)
+    }
+
+    /// Restores a ticker slot saved before a restart.
+    pub fn set_ticker_slot(&mut self, slot: TickerSlot, message: DisplayMessage) {
+        *self.ticker_slot_mut(slot) = Some(message);
+    }
+
+    /// Queues the alert behind the waiting ones with the same or a higher priority.
+    pub fn push_alert(&mut self, message: DisplayMessage) {
+        let index = self
+            .unpublished_events
+            .iter()
+            .position(|waiting| waiting.priority < message.priority)
+            .unwrap_or(self.unpublished_events.len());
+        self.unpublished_events.insert(index, message);
+    }
+
+    /// Takes the next alert to show. In catch-up mode the plain alerts of the same type
+    /// waiting right behind it are folded into one alert listing everyone.
+    pub fn pop_alert(&mut self, catching_up: bool) -> Option<DisplayMessage> {
+        let first = self.unpublished_events.pop_front()?;
+        if !catching_up || !is_foldable(&first) {
+            return Some(first);
+        }
+
+        let event_type = first.payload.event_type();
+        let mut names = vec![display_name(&first.payload)];
+        while names.len() < MAX_COMBINED_ALERTS
+            && self
+                .unpublished_events
+                .front()
+                .is_some_and(|next| is_foldable(next) && next.payload.event_type() == event_type)
+        {
+            if let Some(next) = self.unpublished_events.pop_front() {
+                names.push(display_name(&next.payload));
+            }
+        }
+        if names.len() == 1 {
+            return Some(first);
+        }
+
+        // What belonged to one viewer's alert doesn't fit the combined one
+        Some(DisplayMessage {
+            message: format!("{} {}", name_list(&names), combined_action(event_type)),
+            title: String::new(),
+            emphasis_words: vec![],
+            image_url: "none".to_string(),
+            character: None,
+            roll: None,
+            ..first
+        })
+    }
+}
+
+/// Story alerts for a single follow, sub, resub, raid or cheer. Flagged alerts, like follow
+/// bursts and goal celebrations, and gifts with their recipients are always shown on their own.
+fn is_foldable(message: &DisplayMessage) -> bool {
+    message.flag.is_none()
+        && matches!(
+            message.payload,
+            TwitchEvent::ChannelFollow(_)
+                | TwitchEvent::ChannelSubscribe(_)
+                | TwitchEvent::ChannelResubscribe(_)
+                | TwitchEvent::ChannelRaid(_)
+                | TwitchEvent::ChannelCheer(_)
+        )
+}
+
+/// `a, b and c`, or `a, b, c, d, e and 3 others` for long lists.
+fn name_list(names: &[String]) -> String {
+    if names.len() > MAX_COMBINED_NAMES {
+        return format!(
+            "{} and {} others",
+            names[..MAX_COMBINED_NAMES].join(", "),
+            names.len() - MAX_COMBINED_NAMES
+        );
+    }
+    let (last, rest) = names.split_last().expect("combined alerts have names");
+    format!("{} and {}", rest.join(", "), last)
+}
+
+fn combined_action(event_type: &str) -> &'static str {
+    match event_type {
+        "follow" => "joined the party!",
+        "subscribe" => "pledged their support to the party!",
+        "resubscribe" => "stand with the party once more!",
+        "raid" => "arrived with their parties!",
+        "subgift" => "gifted subs to the party!",
+        "cheer" => "cheered for the party!",
+        _ => "joined the adventure!",
+    }
+}
+
+/// What an overlay browser source wants to be sent. Each OBS source can pick its own.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    Alerts,
+    HypeTrain,
+    Goals,
+    Chat,
+    Ticker,
+    Leaderboard,
+    Credits,
+}
+
+impl Topic {
+    /// Parses a comma separated list like `alerts,ticker`, ignoring anything we don't know.
+    pub fn parse_list(list: &str) -> HashSet<Topic> {
+        list.split(',')
+            .filter_map(|topic| topic.trim().parse().ok())
+            .collect()
+    }
+
+    /// Clients that don't ask for anything get the alert box, like before topics existed.
+    pub fn defaults() -> HashSet<Topic> {
+        HashSet::from([Topic::Alerts])
+    }
+}
+
+impl std::str::FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alerts" => Ok(Topic::Alerts),
+            "hype_train" => Ok(Topic::HypeTrain),
+            "goals" => Ok(Topic::Goals),
+            "chat" => Ok(Topic::Chat),
+            "ticker" => Ok(Topic::Ticker),
+            "leaderboard" => Ok(Topic::Leaderboard),
+            "credits" => Ok(Topic::Credits),
+            other => Err(format!("unknown topic: {}", other)),
+        }
+    }
+}
+
+/// A connected overlay websocket and the topics it subscribed to.
+pub struct OverlayClient {
+    pub tx: Tx,
+    pub topics: HashSet<Topic>,
+}
+
+/// An html fragment that was broadcast to the overlays, tagged with its sequence number.
+#[derive(Clone, Debug)]
+pub struct SequencedFrame {
+    pub sequence: u64,
+    pub topic: Topic,
+    pub html: String,
+}
+
+/// Everything the overlays have been sent, so a reconnecting client can catch up.
+pub struct OverlayState {
+    pub sequence: u64,
+    pub history: VecDeque<SequencedFrame>,
+    /// The last frame sent per topic, which is what a correctly synced overlay is showing right now.
+    pub current: HashMap<Topic, SequencedFrame>,
+}
+
+impl OverlayState {
+    pub fn new() -> OverlayState {
+        OverlayState {
+            sequence: 0,
+            history: VecDeque::new(),
+            current: HashMap::new(),
+        }
+    }
+
+    pub fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    pub fn record(&mut self, frame: SequencedFrame) {
+        self.history.push_back(frame.clone());
+        if self.history.len() > OVERLAY_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.current.insert(frame.topic, frame);
+    }
+
+    /// Frames a client that last saw `last_seq` needs to get back in sync on its `topics`.
+    ///
+    /// If the client is new (`last_seq` of 0) or fell further behind than the history we keep,
+    /// it only gets the current state. Otherwise it gets every frame it missed, in order.
+    pub fn missed_since(&self, last_seq: u64, topics: &HashSet<Topic>) -> Vec<SequencedFrame> {
+        if last_seq >= self.sequence {
+            return vec![];
+        }
+
+        let oldest = self.history.front().map(|f| f.sequence).unwrap_or(0);
+        if last_seq == 0 || last_seq + 1 < oldest {
+            let mut current: Vec<SequencedFrame> = self
+                .current
+                .values()
+                .filter(|f| topics.contains(&f.topic))
+                .cloned()
+                .collect();
+            current.sort_by_key(|f| f.sequence);
+            return current;
+        }
+
+        self.history
+            .iter()
+            .filter(|f| f.sequence > last_seq && topics.contains(&f.topic))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Messages the overlay sends back to us over the websocket.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Sent when the overlay (re)connects with the last sequence number it saw.
+    /// A primary overlay is the one whose acks advance the queue.
+    Resume {
+        last_seq: u64,
+        #[serde(default)]
+        primary: bool,
+    },
+    /// The overlay started playing the alert with this sequence number.
+    AlertStarted { seq: u64 },
+    /// The overlay finished playing the alert with this sequence number.
+    AlertFinished { seq: u64 },
+    /// Replaces the topics this client gets sent.
+    Subscribe { topics: Vec<Topic> },
+}
+
+/// An ack from the overlay that counts towards advancing the queue.
+#[derive(Debug, Clone, Copy)]
+pub enum OverlayAck {
+    Started(u64),
+    Finished(u64),
+}
+
+/// Shared state every overlay websocket connection needs.
+#[derive(Clone)]
+pub struct OverlayConnections {
+    pub connections: ConnectionMap,
+    pub overlay_state: OverlayLog,
+    pub acks: AckSender,
+    pub primary: PrimaryOverlay,
+}
+
+impl OverlayConnections {
+    /// Acks only count from the primary overlay, or from anyone if no overlay claimed it.
+    pub fn counts_ack(&self, peer: SocketAddr) -> bool {
+        match *self.primary.lock().unwrap() {
+            Some(primary) => primary == peer,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(overlay: &mut OverlayState, topic: Topic) -> u64 {
+        let sequence = overlay.next_sequence();
+        overlay.record(SequencedFrame {
+            sequence,
+            topic,
+            html: format!("frame {}", sequence),
+        });
+        sequence
+    }
+
+    fn sequences(frames: Vec<SequencedFrame>) -> Vec<u64> {
+        frames.into_iter().map(|f| f.sequence).collect()
+    }
+
+    fn follow(user_name: &str) -> TwitchEvent {
+        TwitchEvent::ChannelFollow(messages::FollowEvent {
+            user_name: user_name.to_string(),
+            user_id: 1,
+            broadcaster_user_id: 2,
+            profile: None,
+        })
+    }
+
+    fn cheer(user_name: &str, bits: i64) -> TwitchEvent {
+        TwitchEvent::ChannelCheer(messages::CheerEvent {
+            user_name: user_name.to_string(),
+            user_id: 1,
+            bits,
+            message: String::new(),
+            profile: None,
+        })
+    }
+
+    fn alert(payload: TwitchEvent) -> DisplayMessage {
+        DisplayMessage {
+            message: format!("a story about {}", display_name(&payload)),
+            image_url: "none".to_string(),
+            sound_url: "none".to_string(),
+            animation: "none".to_string(),
+            volume: 1.0,
+            display_time: 1000,
+            payload,
+            title: "A Title".to_string(),
+            mood: messages::Mood::Epic,
+            emphasis_words: vec![],
+            flag: None,
+            theme: None,
+            priority: 0,
+            character: None,
+            roll: None,
+        }
+    }
+
+    fn queue(alerts: Vec<DisplayMessage>) -> Queues {
+        let mut queues = Queues::new();
+        for alert in alerts {
+            queues.push_alert(alert);
+        }
+        queues
+    }
+
+    #[test]
+    fn missed_since_replays_missed_frames_in_order() {
+        let mut overlay = OverlayState::new();
+        for _ in 0..5 {
+            frame(&mut overlay, Topic::Alerts);
+        }
+        let topics = Topic::defaults();
+        assert_eq!(sequences(overlay.missed_since(2, &topics)), vec![3, 4, 5]);
+        assert!(overlay.missed_since(5, &topics).is_empty());
+        assert!(overlay.missed_since(9, &topics).is_empty());
+    }
+
+    #[test]
+    fn missed_since_gives_new_clients_the_current_state() {
+        let mut overlay = OverlayState::new();
+        frame(&mut overlay, Topic::Alerts);
+        frame(&mut overlay, Topic::Ticker);
+        frame(&mut overlay, Topic::Alerts);
+        let topics = HashSet::from([Topic::Alerts, Topic::Ticker]);
+        assert_eq!(sequences(overlay.missed_since(0, &topics)), vec![2, 3]);
+    }
+
+    #[test]
+    fn missed_since_gives_clients_behind_the_history_the_current_state() {
+        let mut overlay = OverlayState::new();
+        for _ in 0..OVERLAY_HISTORY_LEN + 10 {
+            frame(&mut overlay, Topic::Alerts);
+        }
+        let last = overlay.sequence;
+        assert_eq!(
+            sequences(overlay.missed_since(3, &Topic::defaults())),
+            vec![last]
+        );
+    }
+
+    #[test]
+    fn missed_since_only_sends_the_client_topics() {
+        let mut overlay = OverlayState::new();
+        frame(&mut overlay, Topic::Alerts);
+        frame(&mut overlay, Topic::Ticker);
+        frame(&mut overlay, Topic::Alerts);
+        assert_eq!(
+            sequences(overlay.missed_since(1, &HashSet::from([Topic::Ticker]))),
+            vec![2]
+        );
+    }
+
+    fn overlay_connections(primary: Option<SocketAddr>) -> OverlayConnections {
+        let (acks, _) = tokio::sync::mpsc::unbounded_channel();
+        OverlayConnections {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            overlay_state: Arc::new(Mutex::new(OverlayState::new())),
+            acks,
+            primary: Arc::new(Mutex::new(primary)),
         }
     }
+
+    #[test]
+    fn counts_ack_only_from_the_primary_overlay() {
+        let primary: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:4001".parse().unwrap();
+
+        let overlay = overlay_connections(Some(primary));
+        assert!(overlay.counts_ack(primary));
+        assert!(!overlay.counts_ack(other));
+
+        let overlay = overlay_connections(None);
+        assert!(overlay.counts_ack(primary));
+        assert!(overlay.counts_ack(other));
+    }
+
+    #[test]
+    fn parse_list_reads_known_topics() {
+        assert_eq!(
+            Topic::parse_list("alerts, ticker,goals"),
+            HashSet::from([Topic::Alerts, Topic::Ticker, Topic::Goals])
+        );
+        assert_eq!(
+            Topic::parse_list("hype_train,hype_train"),
+            HashSet::from([Topic::HypeTrain])
+        );
+    }
+
+    #[test]
+    fn parse_list_ignores_unknown_topics() {
+        assert_eq!(
+            Topic::parse_list("alerts,confetti,"),
+            HashSet::from([Topic::Alerts])
+        );
+        assert!(Topic::parse_list("").is_empty());
+    }
+
+    #[test]
+    fn pop_alert_shows_alerts_one_by_one_normally() {
+        let mut queues = queue(vec![alert(follow("a")), alert(follow("b"))]);
+        assert_eq!(queues.pop_alert(false).unwrap().message, "a story about a");
+        assert_eq!(queues.pop_alert(false).unwrap().message, "a story about b");
+        assert!(queues.pop_alert(false).is_none());
+    }
+
+    #[test]
+    fn pop_alert_folds_alerts_of_the_same_type_while_catching_up() {
+        let mut first = alert(follow("a"));
+        first.image_url = "/assets/generated/a.png".to_string();
+        first.roll = Some(messages::DiceRoll {
+            natural: 20,
+            modifier: 0,
+        });
+        let mut queues = queue(vec![
+            first,
+            alert(follow("b")),
+            alert(follow("c")),
+            alert(cheer("d", 100)),
+        ]);
+
+        let combined = queues.pop_alert(true).unwrap();
+        assert_eq!(combined.message, "a, b and c joined the party!");
+        assert_eq!(combined.title, "");
+        assert_eq!(combined.image_url, "none");
+        assert!(combined.roll.is_none());
+        assert_eq!(queues.pop_alert(true).unwrap().message, "a story about d");
+    }
+
+    #[test]
+    fn pop_alert_counts_the_rest_of_long_lists() {
+        let names = ["a", "b", "c", "d", "e", "f", "g"];
+        let mut queues = queue(names.iter().map(|name| alert(follow(name))).collect());
+        assert_eq!(
+            queues.pop_alert(true).unwrap().message,
+            "a, b, c, d, e and 2 others joined the party!"
+        );
+    }
+
+    #[test]
+    fn pop_alert_keeps_flagged_alerts_on_their_own() {
+        let mut celebration = alert(follow("b"));
+        celebration.message = "The party celebrates 100 followers!".to_string();
+        celebration.flag = Some("Milestone: 100 followers".to_string());
+        let mut queues = queue(vec![alert(follow("a")), celebration, alert(follow("c"))]);
+
+        assert_eq!(queues.pop_alert(true).unwrap().message, "a story about a");
+        assert_eq!(
+            queues.pop_alert(true).unwrap().message,
+            "The party celebrates 100 followers!"
+        );
+        assert_eq!(queues.pop_alert(true).unwrap().message, "a story about c");
+    }
+
+    #[test]
+    fn pop_alert_keeps_gifts_on_their_own() {
+        let gift = |user_name: &str| {
+            alert(TwitchEvent::ChannelSubGift(messages::ChannelGiftMessage {
+                broadcaster_user_id: "2".to_string(),
+                broadcaster_user_login: "null".to_string(),
+                broadcaster_user_name: "Null".to_string(),
+                cumulative_total: None,
+                is_anonymous: false,
+                tier: messages::NullSubTier::Tier1("1000".to_string()),
+                total: 2,
+                user_id: Some("1".to_string()),
+                user_login: Some(user_name.to_string()),
+                user_name: Some(user_name.to_string()),
+                recipients: vec!["x".to_string(), "y".to_string()],
+            }))
+        };
+        let mut queues = queue(vec![gift("a"), gift("b")]);
+        assert_eq!(queues.pop_alert(true).unwrap().message, "a story about a");
+        assert_eq!(queues.pop_alert(true).unwrap().message, "a story about b");
+    }
+
+    #[test]
+    fn push_alert_puts_higher_priorities_first_and_keeps_the_order_otherwise() {
+        let mut urgent = alert(follow("urgent"));
+        urgent.priority = 10;
+        let mut queues = queue(vec![alert(follow("a")), alert(follow("b")), urgent]);
+        let mut also_urgent = alert(follow("also urgent"));
+        also_urgent.priority = 10;
+        queues.push_alert(also_urgent);
+
+        let order: Vec<String> = queues
+            .unpublished_events
+            .iter()
+            .map(|message| display_name(&message.payload))
+            .collect();
+        assert_eq!(order, vec!["urgent", "also urgent", "a", "b"]);
+    }
+
+    #[test]
+    fn update_ticker_keeps_the_latest_alerts_and_the_top_cheer() {
+        let mut queues = Queues::new();
+        assert_eq!(
+            queues.update_ticker(&alert(follow("a"))),
+            Some(TickerSlot::Follower)
+        );
+        assert_eq!(
+            queues.update_ticker(&alert(follow("b"))),
+            Some(TickerSlot::Follower)
+        );
+        let last_follow = queues.ticker_slot(TickerSlot::Follower).unwrap();
+        assert_eq!(display_name(&last_follow.payload), "b");
+
+        assert_eq!(
+            queues.update_ticker(&alert(cheer("big", 500))),
+            Some(TickerSlot::Cheer)
+        );
+        assert_eq!(queues.update_ticker(&alert(cheer("small", 100))), None);
+        assert_eq!(queues.update_ticker(&alert(cheer("same", 500))), None);
+        let top_cheer = queues.ticker_slot(TickerSlot::Cheer).unwrap();
+        assert_eq!(display_name(&top_cheer.payload), "big");
+        assert_eq!(
+            queues.update_ticker(&alert(cheer("bigger", 501))),
+            Some(TickerSlot::Cheer)
+        );
+    }
+
+    #[test]
+    fn update_ticker_ignores_events_the_ticker_does_not_show() {
+        let mut queues = Queues::new();
+        let train = alert(TwitchEvent::ChannelHypeTrainBegin(
+            messages::HypeTrainEvent {
+                level: 1,
+                total: 100,
+                goal: Some(500),
+            },
+        ));
+        assert_eq!(queues.update_ticker(&train), None);
+        assert!(queues.ticker_slot(TickerSlot::Sub).is_none());
+    }
+
+    #[test]
+    fn update_ticker_leaves_the_latest_follower_for_follow_bursts() {
+        let mut queues = Queues::new();
+        queues.update_ticker(&alert(follow("a")));
+        let burst = TwitchEvent::FollowBurst(messages::FollowBurstEvent {
+            user_names: vec!["bot1".to_string(), "bot2".to_string()],
+            follows: 2,
+        });
+
+        assert_eq!(queues.update_ticker(&alert(burst)), None);
+        let last_follow = queues.ticker_slot(TickerSlot::Follower).unwrap();
+        assert_eq!(display_name(&last_follow.payload), "a");
+    }
 }
@@ -1,9 +1,15 @@
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum EventMessage {
     /// A new event message from Twitch.
-    TwitchEvent(NewTwitchEventMessage),
+    TwitchEvent(Box<NewTwitchEventMessage>),
     /// A new message to display.
     YoutubeEvent(YoutubeEventMessage),
 }
@@ -35,20 +41,203 @@ pub enum TwitchEvent {
     ChannelRaid(RaidEvent),
     ChannelSubGift(ChannelGiftMessage),
     ChannelCheer(CheerEvent),
+    FollowBurst(FollowBurstEvent),
+    ChannelHypeTrainBegin(HypeTrainEvent),
+    ChannelHypeTrainEnd(HypeTrainEvent),
+    StreamOnline(StreamOnlineEvent),
+    StreamOffline(StreamOfflineEvent),
+}
+
+/// Every value [`TwitchEvent::event_type`] can return.
+pub const EVENT_TYPES: [&str; 11] = [
+    "follow",
+    "subscribe",
+    "resubscribe",
+    "raid",
+    "subgift",
+    "cheer",
+    "follow_burst",
+    "hype_train_begin",
+    "hype_train_end",
+    "stream_online",
+    "stream_offline",
+];
+
+impl TwitchEvent {
+    /// Short name of the event type, used by clients to filter and style events.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            TwitchEvent::ChannelFollow(_) => "follow",
+            TwitchEvent::ChannelSubscribe(_) => "subscribe",
+            TwitchEvent::ChannelResubscribe(_) => "resubscribe",
+            TwitchEvent::ChannelRaid(_) => "raid",
+            TwitchEvent::ChannelSubGift(_) => "subgift",
+            TwitchEvent::ChannelCheer(_) => "cheer",
+            TwitchEvent::FollowBurst(_) => "follow_burst",
+            TwitchEvent::ChannelHypeTrainBegin(_) => "hype_train_begin",
+            TwitchEvent::ChannelHypeTrainEnd(_) => "hype_train_end",
+            TwitchEvent::StreamOnline(_) => "stream_online",
+            TwitchEvent::StreamOffline(_) => "stream_offline",
+        }
+    }
+
+    /// Name of the viewer behind the event, `None` for anonymous gifts, follow bursts, hype
+    /// trains and the start and end of the stream.
+    pub fn user_name(&self) -> Option<&str> {
+        match self {
+            TwitchEvent::ChannelFollow(follow) => Some(&follow.user_name),
+            TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+                Some(&sub.user_name)
+            }
+            TwitchEvent::ChannelRaid(raid) => Some(&raid.from_broadcaster_user_name),
+            TwitchEvent::ChannelSubGift(gift) => gift.user_name.as_deref(),
+            TwitchEvent::ChannelCheer(cheer) => Some(&cheer.user_name),
+            TwitchEvent::FollowBurst(_)
+            | TwitchEvent::ChannelHypeTrainBegin(_)
+            | TwitchEvent::ChannelHypeTrainEnd(_)
+            | TwitchEvent::StreamOnline(_)
+            | TwitchEvent::StreamOffline(_) => None,
+        }
+    }
+
+    /// Twitch id of the viewer whose profile is looked up for the event, `None` for events
+    /// without one and anonymous cheers. Raids look up the raiding channel on their own.
+    pub fn viewer_id(&self) -> Option<i64> {
+        match self {
+            TwitchEvent::ChannelFollow(follow) => Some(follow.user_id),
+            TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+                Some(sub.user_id)
+            }
+            TwitchEvent::ChannelCheer(cheer) if !cheer.is_anonymous() => Some(cheer.user_id),
+            TwitchEvent::ChannelCheer(_)
+            | TwitchEvent::ChannelRaid(_)
+            | TwitchEvent::ChannelSubGift(_)
+            | TwitchEvent::FollowBurst(_)
+            | TwitchEvent::ChannelHypeTrainBegin(_)
+            | TwitchEvent::ChannelHypeTrainEnd(_)
+            | TwitchEvent::StreamOnline(_)
+            | TwitchEvent::StreamOffline(_) => None,
+        }
+    }
+
+    /// The profile of the viewer behind the event, once it was looked up.
+    pub fn profile(&self) -> Option<&ViewerProfile> {
+        match self {
+            TwitchEvent::ChannelFollow(follow) => follow.profile.as_ref(),
+            TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+                sub.profile.as_ref()
+            }
+            TwitchEvent::ChannelCheer(cheer) => cheer.profile.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Attaches the viewer's profile, see [`TwitchEvent::viewer_id`].
+    pub fn set_profile(&mut self, profile: ViewerProfile) {
+        match self {
+            TwitchEvent::ChannelFollow(follow) => follow.profile = Some(profile),
+            TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+                sub.profile = Some(profile)
+            }
+            TwitchEvent::ChannelCheer(cheer) => cheer.profile = Some(profile),
+            _ => {}
+        }
+    }
+
+    /// Replaces the name of the viewer behind the event, see [`TwitchEvent::user_name`].
+    pub fn set_user_name(&mut self, name: &str) {
+        match self {
+            TwitchEvent::ChannelFollow(follow) => follow.user_name = name.to_string(),
+            TwitchEvent::ChannelSubscribe(sub) | TwitchEvent::ChannelResubscribe(sub) => {
+                sub.user_name = name.to_string()
+            }
+            TwitchEvent::ChannelRaid(raid) => raid.from_broadcaster_user_name = name.to_string(),
+            TwitchEvent::ChannelSubGift(gift) => gift.user_name = Some(name.to_string()),
+            TwitchEvent::ChannelCheer(cheer) => cheer.user_name = name.to_string(),
+            TwitchEvent::FollowBurst(_)
+            | TwitchEvent::ChannelHypeTrainBegin(_)
+            | TwitchEvent::ChannelHypeTrainEnd(_)
+            | TwitchEvent::StreamOnline(_)
+            | TwitchEvent::StreamOffline(_) => {}
+        }
+    }
+}
+
+/// A follow-bot wave, shown as one muted alert instead of one per follow.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FollowBurstEvent {
+    /// The new followers in the wave, refollows left out.
+    pub user_names: Vec<String>,
+    /// How many follows came in, refollows included.
+    pub follows: i64,
+}
+
+/// The stream went live. The leaderboards count "this stream" from the latest one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamOnlineEvent {
+    pub broadcaster_user_name: String,
+}
+
+/// The stream ended. The admin page sends this too, to roll the credits by hand.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamOfflineEvent {
+    pub broadcaster_user_name: String,
+}
+
+/// What Twitch has on the viewer behind an event, looked up before the event reaches the
+/// AIManager.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ViewerProfile {
+    pub display_name: String,
+    pub profile_image_url: Option<String>,
+    /// "partner", "affiliate" or empty.
+    pub broadcaster_type: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FollowEvent {
     pub user_name: String,
     pub user_id: i64,
+    /// The channel that was followed.
+    #[serde(default)]
+    pub broadcaster_user_id: i64,
+    #[serde(default)]
+    pub profile: Option<ViewerProfile>,
+}
+
+/// A hype train starting or ending. The admin page sends these too, to start and end a boss
+/// fight by hand.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HypeTrainEvent {
+    pub level: i64,
+    /// Points contributed so far.
+    pub total: i64,
+    /// Points needed for the next level, `None` once the train has ended.
+    #[serde(default)]
+    pub goal: Option<i64>,
 }
 
+/// The `user_id` of anonymous cheers, Twitch doesn't say who they are from.
+pub const ANONYMOUS_VIEWER_ID: i64 = 0;
+
+/// The `user_name` of anonymous cheers.
+pub const ANONYMOUS_CHEERER: &str = "Anonymous";
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CheerEvent {
     pub user_name: String,
+    /// [`ANONYMOUS_VIEWER_ID`] for anonymous cheers.
     pub user_id: i64,
     pub bits: i64,
     pub message: String,
+    #[serde(default)]
+    pub profile: Option<ViewerProfile>,
+}
+
+impl CheerEvent {
+    pub fn is_anonymous(&self) -> bool {
+        self.user_id == ANONYMOUS_VIEWER_ID
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -74,6 +263,8 @@ pub struct SubscribeEvent {
     pub duration_months: i64,
     pub message: String,
     pub streak_months: Option<i64>,
+    #[serde(default)]
+    pub profile: Option<ViewerProfile>,
 }
 
 // Path: messages/src/lib.rs
@@ -83,8 +274,442 @@ pub struct DisplayMessage {
     pub message: String,
     pub image_url: String,
     pub sound_url: String,
+    /// Css animation the overlay plays the alert with, "none" for the theme's default.
+    pub animation: String,
+    /// Volume between 0.0 and 1.0 the sound is played at.
+    pub volume: f32,
     pub display_time: usize,
     pub payload: TwitchEvent,
+    /// Short headline for the story, empty when the model gave none.
+    pub title: String,
+    pub mood: Mood,
+    /// Words from the message the overlay makes stand out.
+    pub emphasis_words: Vec<String>,
+    /// Why the dashboard should point this alert out, like a follow burst.
+    #[serde(default)]
+    pub flag: Option<String>,
+    /// Theme the alert is rendered with, `None` for the active one.
+    #[serde(default)]
+    pub theme: Option<String>,
+    /// Alerts with a higher priority skip ahead of lower ones waiting to be shown.
+    #[serde(default)]
+    pub priority: i32,
+    /// The party member behind the event, after the XP it earned them.
+    #[serde(default)]
+    pub character: Option<CharacterSheet>,
+    /// The d20 rolled for the event, the overlay animates it before showing the story.
+    #[serde(default)]
+    pub roll: Option<DiceRoll>,
+}
+
+/// A d20 roll against [`DiceRoll::DIFFICULTY`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiceRoll {
+    /// What the die showed, 1 to 20.
+    pub natural: i64,
+    pub modifier: i64,
+}
+
+impl DiceRoll {
+    pub const SIDES: i64 = 20;
+    /// Totals at or above this succeed.
+    pub const DIFFICULTY: i64 = 10;
+
+    pub fn total(&self) -> i64 {
+        self.natural + self.modifier
+    }
+
+    /// A natural 20 always succeeds, a natural 1 always fails.
+    pub fn outcome(&self) -> &'static str {
+        match self.natural {
+            DiceRoll::SIDES => "critical success",
+            1 => "critical failure",
+            _ if self.total() >= DiceRoll::DIFFICULTY => "success",
+            _ => "failure",
+        }
+    }
+}
+
+/// A viewer's character in the Null party. Support events earn XP, which earns levels and
+/// an item for the inventory with each one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CharacterSheet {
+    pub user_name: String,
+    pub class: String,
+    pub race: String,
+    pub level: i64,
+    pub xp: i64,
+    pub inventory: Vec<String>,
+    /// Whether the event this sheet came with got them to a new level.
+    #[serde(default)]
+    pub leveled_up: bool,
+}
+
+/// The feel of a story, picked by the model. Overlays pick colors, animation and sound by it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mood {
+    #[default]
+    Epic,
+    Heroic,
+    Funny,
+    Mysterious,
+    Ominous,
+    Wholesome,
+}
+
+impl Mood {
+    pub const ALL: [Mood; 6] = [
+        Mood::Epic,
+        Mood::Heroic,
+        Mood::Funny,
+        Mood::Mysterious,
+        Mood::Ominous,
+        Mood::Wholesome,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mood::Epic => "epic",
+            Mood::Heroic => "heroic",
+            Mood::Funny => "funny",
+            Mood::Mysterious => "mysterious",
+            Mood::Ominous => "ominous",
+            Mood::Wholesome => "wholesome",
+        }
+    }
+
+    /// Looks the mood up by name, ignoring case and surrounding whitespace.
+    pub fn parse(name: &str) -> Option<Mood> {
+        let name = name.trim().to_lowercase();
+        Mood::ALL.into_iter().find(|mood| mood.as_str() == name)
+    }
+}
+
+/// Image, animation and sound used for an alert. Anything left out falls back to the next level.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AssetSet {
+    pub image_url: Option<String>,
+    pub animation: Option<String>,
+    pub sound_url: Option<String>,
+    pub volume: Option<f32>,
+}
+
+impl AssetSet {
+    /// Fills in everything this set leaves out from `fallback`.
+    pub fn or(&self, fallback: &AssetSet) -> AssetSet {
+        AssetSet {
+            image_url: self
+                .image_url
+                .clone()
+                .or_else(|| fallback.image_url.clone()),
+            animation: self
+                .animation
+                .clone()
+                .or_else(|| fallback.animation.clone()),
+            sound_url: self
+                .sound_url
+                .clone()
+                .or_else(|| fallback.sound_url.clone()),
+            volume: self.volume.or(fallback.volume),
+        }
+    }
+}
+
+/// Which assets each alert gets.
+///
+/// A viewer override wins over the story's mood, which wins over the event type, which wins over
+/// the defaults. Events are keyed by [`TwitchEvent::event_type`], moods by [`Mood::as_str`] and
+/// viewers by lowercase user name.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AlertAssets {
+    #[serde(default)]
+    pub default: AssetSet,
+    #[serde(default)]
+    pub events: HashMap<String, AssetSet>,
+    #[serde(default)]
+    pub moods: HashMap<String, AssetSet>,
+    #[serde(default)]
+    pub viewers: HashMap<String, AssetSet>,
+}
+
+impl AlertAssets {
+    pub fn load(path: &str) -> anyhow::Result<AlertAssets> {
+        let file = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    pub fn for_event(&self, event: &TwitchEvent, mood: Mood) -> AssetSet {
+        let by_event = self
+            .events
+            .get(event.event_type())
+            .cloned()
+            .unwrap_or_default()
+            .or(&self.default);
+        let by_mood = self
+            .moods
+            .get(mood.as_str())
+            .map(|assets| assets.or(&by_event))
+            .unwrap_or(by_event);
+
+        event
+            .user_name()
+            .and_then(|name| self.viewers.get(&name.to_lowercase()))
+            .map(|viewer| viewer.or(&by_mood))
+            .unwrap_or(by_mood)
+    }
+}
+
+/// How far behind the alerts are, shared by the AIManager and the frontend.
+///
+/// Above the threshold both switch to catch-up mode: short templated messages instead of full
+/// stories, and events of the same type combined into one alert.
+#[derive(Debug, Clone)]
+pub struct Backlog {
+    threshold: usize,
+    waiting_for_ai: Arc<AtomicUsize>,
+    unpublished: Arc<AtomicUsize>,
+    catching_up: Arc<AtomicBool>,
+}
+
+impl Default for Backlog {
+    fn default() -> Self {
+        Backlog::new(10)
+    }
+}
+
+impl Backlog {
+    pub fn new(threshold: usize) -> Backlog {
+        Backlog {
+            threshold,
+            waiting_for_ai: Arc::new(AtomicUsize::new(0)),
+            unpublished: Arc::new(AtomicUsize::new(0)),
+            catching_up: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Events the AIManager has not turned into alerts yet.
+    pub fn set_waiting_for_ai(&self, count: usize) {
+        self.waiting_for_ai.store(count, Ordering::SeqCst);
+    }
+
+    /// Alerts queued in the frontend that have not been shown yet.
+    pub fn set_unpublished(&self, count: usize) {
+        self.unpublished.store(count, Ordering::SeqCst);
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiting_for_ai.load(Ordering::SeqCst) + self.unpublished.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Switches on above the threshold, and only back off once the backlog is down to half
+    /// of it so a backlog hovering around the threshold doesn't flip every alert.
+    pub fn catching_up(&self) -> bool {
+        let len = self.len();
+        if len > self.threshold {
+            if !self.catching_up.swap(true, Ordering::SeqCst) {
+                println!("Backlog of {} alerts, switching to catch-up mode", len);
+            }
+            true
+        } else if len <= self.threshold / 2 {
+            if self.catching_up.swap(false, Ordering::SeqCst) {
+                println!("Backlog drained, back to full stories");
+            }
+            false
+        } else {
+            self.catching_up.load(Ordering::SeqCst)
+        }
+    }
+}
+
+/// The boss the party fights during a hype train.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Boss {
+    pub name: String,
+    pub level: i64,
+    pub max_health: i64,
+    pub health: i64,
+    /// Narration of the last big hit.
+    pub last_hit: Option<String>,
+}
+
+impl Boss {
+    pub fn is_defeated(&self) -> bool {
+        self.health <= 0
+    }
+}
+
+/// The boss fight going on, shared by the AIManager, which runs it, and the frontend, which
+/// shows it. The revision goes up with every change so the frontend knows when to redraw.
+#[derive(Debug, Clone, Default)]
+pub struct BossBattle {
+    boss: Arc<Mutex<Option<Boss>>>,
+    revision: Arc<AtomicU64>,
+}
+
+impl BossBattle {
+    pub fn get(&self) -> Option<Boss> {
+        self.boss.lock().unwrap().clone()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    /// Starts the fight, unless one is already going on.
+    pub fn start(&self, boss: Boss) -> bool {
+        let mut current = self.boss.lock().unwrap();
+        if current.is_some() {
+            return false;
+        }
+        *current = Some(boss);
+        self.revision.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    /// Takes the damage off the boss, returning it after the hit. `None` without a fight.
+    pub fn hit(&self, damage: i64) -> Option<Boss> {
+        let mut current = self.boss.lock().unwrap();
+        let boss = current.as_mut()?;
+        boss.health = (boss.health - damage).max(0);
+        self.revision.fetch_add(1, Ordering::SeqCst);
+        Some(boss.clone())
+    }
+
+    pub fn set_last_hit(&self, narration: String) {
+        if let Some(boss) = self.boss.lock().unwrap().as_mut() {
+            boss.last_hit = Some(narration);
+            self.revision.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Ends the fight, returning the boss as it was left.
+    pub fn end(&self) -> Option<Boss> {
+        let boss = self.boss.lock().unwrap().take();
+        if boss.is_some() {
+            self.revision.fetch_add(1, Ordering::SeqCst);
+        }
+        boss
+    }
+}
+
+/// Everyone who supported the stream, grouped by what they did, with the campaign recap
+/// as the epilogue.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreditsRoll {
+    pub sections: Vec<CreditsSection>,
+    pub epilogue: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreditsSection {
+    /// What the party calls them, like "Knights of the Null Order" for the subs.
+    pub title: String,
+    pub names: Vec<String>,
+}
+
+/// The credits rolling at the end of the stream, shared by the AIManager, which puts them
+/// together, and the frontend, which shows them. The revision goes up with every change so
+/// the frontend knows when to redraw.
+#[derive(Debug, Clone, Default)]
+pub struct Credits {
+    roll: Arc<Mutex<Option<CreditsRoll>>>,
+    revision: Arc<AtomicU64>,
+}
+
+impl Credits {
+    pub fn get(&self) -> Option<CreditsRoll> {
+        self.roll.lock().unwrap().clone()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    pub fn start(&self, roll: CreditsRoll) {
+        *self.roll.lock().unwrap() = Some(roll);
+        self.revision.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Takes the credits off the overlay.
+    pub fn stop(&self) {
+        if self.roll.lock().unwrap().take().is_some() {
+            self.revision.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// What a goal counts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum GoalKind {
+    Followers,
+    /// Tier 1 and prime subs are worth 1 point, tier 2 2 and tier 3 6, like on Twitch.
+    SubPoints,
+    /// Starts from 0 every time the app starts.
+    StreamBits,
+}
+
+impl GoalKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GoalKind::Followers => "followers",
+            GoalKind::SubPoints => "sub_points",
+            GoalKind::StreamBits => "stream_bits",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Goal {
+    pub kind: GoalKind,
+    pub label: String,
+    pub current: i64,
+    pub target: i64,
+}
+
+impl Goal {
+    pub fn is_reached(&self) -> bool {
+        self.target > 0 && self.current >= self.target
+    }
+}
+
+/// The goals, shared by the AIManager, which counts them up, and the frontend, which shows
+/// them. The revision goes up with every change so the frontend knows when to redraw.
+#[derive(Debug, Clone, Default)]
+pub struct Goals {
+    goals: Arc<Mutex<Vec<Goal>>>,
+    revision: Arc<AtomicU64>,
+}
+
+impl Goals {
+    pub fn get(&self) -> Vec<Goal> {
+        self.goals.lock().unwrap().clone()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, goals: Vec<Goal>) {
+        *self.goals.lock().unwrap() = goals;
+        self.revision.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts the goal up, returning what it was before and the goal after.
+    /// `None` when there is no goal of that kind.
+    pub fn add(&self, kind: GoalKind, amount: i64) -> Option<(i64, Goal)> {
+        let mut goals = self.goals.lock().unwrap();
+        let goal = goals.iter_mut().find(|goal| goal.kind == kind)?;
+        let before = goal.current;
+        goal.current += amount;
+        self.revision.fetch_add(1, Ordering::SeqCst);
+        Some((before, goal.clone()))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -109,6 +734,9 @@ pub struct ChannelGiftMessage {
     pub user_login: Option<String>,
     /// The user display name of the user who sent the gift. Set to null if it was an anonymous subscription gift.
     pub user_name: Option<String>,
+    /// Names of the viewers that got the gifted subs, filled in from their subscribe events.
+    #[serde(default)]
+    pub recipients: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -124,3 +752,146 @@ pub enum NullSubTier {
     /// Other
     Other(String),
 }
+
+impl NullSubTier {
+    /// What the party calls the tier, like "rare" or "legendary".
+    pub fn name(&self) -> &str {
+        match self {
+            NullSubTier::Tier1(name)
+            | NullSubTier::Tier2(name)
+            | NullSubTier::Tier3(name)
+            | NullSubTier::Prime(name)
+            | NullSubTier::Other(name) => name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn follow(user_name: &str) -> TwitchEvent {
+        TwitchEvent::ChannelFollow(FollowEvent {
+            user_name: user_name.to_string(),
+            user_id: 1,
+            broadcaster_user_id: 2,
+            profile: None,
+        })
+    }
+
+    fn image(url: &str) -> AssetSet {
+        AssetSet {
+            image_url: Some(url.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn alert_assets() -> AlertAssets {
+        AlertAssets {
+            default: AssetSet {
+                image_url: Some("default.png".to_string()),
+                sound_url: Some("default.wav".to_string()),
+                ..Default::default()
+            },
+            events: HashMap::from([("follow".to_string(), image("follow.png"))]),
+            moods: HashMap::from([("funny".to_string(), image("funny.png"))]),
+            viewers: HashMap::from([("nullvoxpopuli".to_string(), image("viewer.png"))]),
+        }
+    }
+
+    #[test]
+    fn for_event_uses_the_event_over_the_default() {
+        let assets = alert_assets().for_event(&follow("someone"), Mood::Epic);
+        assert_eq!(assets.image_url.as_deref(), Some("follow.png"));
+        assert_eq!(assets.sound_url.as_deref(), Some("default.wav"));
+    }
+
+    #[test]
+    fn for_event_uses_the_mood_over_the_event() {
+        let assets = alert_assets().for_event(&follow("someone"), Mood::Funny);
+        assert_eq!(assets.image_url.as_deref(), Some("funny.png"));
+        assert_eq!(assets.sound_url.as_deref(), Some("default.wav"));
+    }
+
+    #[test]
+    fn for_event_uses_the_viewer_over_everything_ignoring_case() {
+        let assets = alert_assets().for_event(&follow("NullVoxPopuli"), Mood::Funny);
+        assert_eq!(assets.image_url.as_deref(), Some("viewer.png"));
+        assert_eq!(assets.sound_url.as_deref(), Some("default.wav"));
+    }
+
+    #[test]
+    fn for_event_falls_back_to_the_default() {
+        let event = TwitchEvent::ChannelHypeTrainBegin(HypeTrainEvent {
+            level: 1,
+            total: 0,
+            goal: Some(100),
+        });
+        let assets = alert_assets().for_event(&event, Mood::Epic);
+        assert_eq!(assets.image_url.as_deref(), Some("default.png"));
+        assert_eq!(
+            AlertAssets::default()
+                .for_event(&event, Mood::Epic)
+                .image_url,
+            None
+        );
+    }
+
+    #[test]
+    fn catching_up_switches_back_at_half_the_threshold() {
+        let backlog = Backlog::new(10);
+        backlog.set_waiting_for_ai(8);
+        assert!(!backlog.catching_up());
+        backlog.set_unpublished(3);
+        assert!(backlog.catching_up());
+        backlog.set_unpublished(0);
+        assert!(backlog.catching_up(), "still above half the threshold");
+        backlog.set_waiting_for_ai(5);
+        assert!(!backlog.catching_up());
+    }
+
+    #[test]
+    fn set_profile_only_attaches_to_events_with_a_viewer() {
+        let profile = ViewerProfile {
+            display_name: "Sam".to_string(),
+            profile_image_url: Some("https://example.com/sam.png".to_string()),
+            broadcaster_type: String::new(),
+        };
+        let mut event = follow("sam");
+        assert_eq!(event.viewer_id(), Some(1));
+        assert!(event.profile().is_none());
+        event.set_profile(profile.clone());
+        assert_eq!(
+            event.profile().map(|p| p.display_name.as_str()),
+            Some("Sam")
+        );
+
+        let mut train = TwitchEvent::ChannelHypeTrainBegin(HypeTrainEvent {
+            level: 1,
+            total: 0,
+            goal: Some(100),
+        });
+        assert_eq!(train.viewer_id(), None);
+        train.set_profile(profile);
+        assert!(train.profile().is_none());
+    }
+
+    #[test]
+    fn anonymous_cheers_have_no_viewer_to_look_up() {
+        let cheer = |user_id| {
+            TwitchEvent::ChannelCheer(CheerEvent {
+                user_name: ANONYMOUS_CHEERER.to_string(),
+                user_id,
+                bits: 100,
+                message: String::new(),
+                profile: None,
+            })
+        };
+        assert_eq!(cheer(ANONYMOUS_VIEWER_ID).viewer_id(), None);
+        assert_eq!(cheer(7).viewer_id(), Some(7));
+        assert_eq!(
+            cheer(ANONYMOUS_VIEWER_ID).user_name(),
+            Some(ANONYMOUS_CHEERER)
+        );
+    }
+}
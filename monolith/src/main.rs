@@ -1,9 +1,23 @@
 #![warn(clippy::unwrap_in_result)]
 mod util;
-use ai_manager_service::AIManager;
+use ai_manager_service::{
+    boss::BossConfig,
+    goals::GoalsConfig,
+    images::{generator_by_name, ImageStep},
+    profiles::ViewerProfiles,
+    raiders::RaiderLookup,
+    rules::{Rules, RulesConfig},
+    safety::{SafetyConfig, SafetyFilter},
+    AIManager,
+};
 use clap::Parser;
-use forntend_api_lib::{FrontendApi, HostInfo};
+use forntend_api_lib::{
+    engine_by_name, AssetManager, FrontendApi, HostInfo, Leaderboards, Pronunciations, TickerStore,
+    TtsManager,
+};
+use messages::{AlertAssets, Backlog, BossBattle, Credits, Goals};
 use twitch_api::twitch_oauth2::UserToken;
+use twitch_listener_service_lib::helix;
 use twitch_listener_service_lib::opts::Opts;
 use twitch_listener_service_lib::websocket::WebsocketClient;
 
@@ -88,12 +102,152 @@ pub async fn run(opts: &Opts) -> eyre::Result<()> {
     let (sender, receiver) = mpsc::unbounded_channel();
     let (frentend_sender, frontend_receiver) = mpsc::unbounded_channel();
 
-    let ai_manager_res = AIManager::new(sqlite_pool, gpt_key, frentend_sender);
+    let alert_assets = match AlertAssets::load(&opts.alert_assets) {
+        Ok(assets) => assets,
+        Err(e) => {
+            println!(
+                "could not load alert assets from {}, using none: {}",
+                opts.alert_assets, e
+            );
+            AlertAssets::default()
+        }
+    };
+    let alert_assets = Arc::new(std::sync::RwLock::new(alert_assets));
+
+    let asset_manager = AssetManager::new(
+        sqlite_pool.clone(),
+        opts.uploads_dir.clone(),
+        alert_assets.clone(),
+    );
+
+    let ticker = TickerStore::new(sqlite_pool.clone());
+    let leaderboards = Leaderboards::new(
+        sqlite_pool.clone(),
+        std::time::Duration::from_secs(opts.leaderboard_rotate_secs),
+    );
+
+    let ai_manager_res = AIManager::new(
+        sqlite_pool.clone(),
+        gpt_key.clone(),
+        frentend_sender,
+        alert_assets,
+    );
 
-    let Ok(ai_manager) = ai_manager_res else {
+    let Ok(mut ai_manager) = ai_manager_res else {
         panic!("failed to create the ai manager");
     };
 
+    let safety_config = match SafetyConfig::load(&opts.safety_config) {
+        Ok(config) => config,
+        Err(e) => {
+            println!(
+                "could not load safety config from {}, using the defaults: {}",
+                opts.safety_config, e
+            );
+            SafetyConfig::default()
+        }
+    };
+    ai_manager.safety = SafetyFilter::new(safety_config);
+
+    let rules_config = match RulesConfig::load(&opts.rules_config) {
+        Ok(config) => config,
+        Err(e) => {
+            println!(
+                "could not load rules from {}, alerts get no rules: {}",
+                opts.rules_config, e
+            );
+            RulesConfig::default()
+        }
+    };
+    ai_manager.rules = Rules::new(rules_config);
+
+    ai_manager.boss_config = match BossConfig::load(&opts.boss_config) {
+        Ok(config) => config,
+        Err(e) => {
+            println!(
+                "could not load boss config from {}, using the defaults: {}",
+                opts.boss_config, e
+            );
+            BossConfig::default()
+        }
+    };
+    let boss_battle = BossBattle::default();
+    ai_manager.boss_battle = boss_battle.clone();
+
+    ai_manager.goals_config = match GoalsConfig::load(&opts.goals_config) {
+        Ok(config) => config,
+        Err(e) => {
+            println!(
+                "could not load goals from {}, using the defaults: {}",
+                opts.goals_config, e
+            );
+            GoalsConfig::default()
+        }
+    };
+    let goals = Goals::default();
+    ai_manager.goals = goals.clone();
+    // Start the goals from what Twitch counts, falling back to where we left them
+    let followers = helix::follower_count(&client, &*token.read().await, &user_id)
+        .await
+        .unwrap_or_else(|e| {
+            println!("could not get the follower count: {}", e);
+            None
+        });
+    let sub_points = helix::sub_points(&client, &*token.read().await, &user_id)
+        .await
+        .unwrap_or_else(|e| {
+            println!("could not get the sub points: {}", e);
+            None
+        });
+    ai_manager.seed_goals(followers, sub_points).await;
+    let credits = Credits::default();
+    ai_manager.credits = credits.clone();
+    ai_manager.concurrency = opts.ai_concurrency;
+    ai_manager.event_timeout = std::time::Duration::from_secs(opts.ai_timeout_secs);
+    ai_manager.gift_window = std::time::Duration::from_secs(opts.gift_window_secs);
+    ai_manager.refollow_cooldown =
+        std::time::Duration::from_secs(opts.refollow_cooldown_hours * 60 * 60);
+    ai_manager.follow_burst_threshold = opts.follow_burst_threshold;
+    ai_manager.follow_burst_window = std::time::Duration::from_secs(opts.follow_burst_window_secs);
+
+    ai_manager.raider_lookup = Some(RaiderLookup::new(
+        Box::new(helix::HelixChannelLookup {
+            client: client.clone(),
+            token: token.clone(),
+        }),
+        std::time::Duration::from_secs(opts.raider_cache_hours * 60 * 60),
+    ));
+
+    let backlog = Backlog::new(opts.catch_up_threshold);
+    ai_manager.backlog = backlog.clone();
+
+    if let Some(backend) = &opts.image_backend {
+        match generator_by_name(backend, &gpt_key, opts.image_backend_url.clone()) {
+            Ok(generator) => {
+                // Generated images live with the frontend assets so they are served under /assets
+                ai_manager.image_step = Some(ImageStep::new(
+                    generator,
+                    Path::new(&opts.frontend_assets).join("generated"),
+                    "/assets/generated".to_string(),
+                    std::time::Duration::from_secs(opts.image_timeout_secs),
+                ))
+            }
+            Err(e) => println!("image generation is off: {}", e),
+        }
+    }
+
+    // Viewer profiles are attached before the events reach the AIManager
+    let sender = ViewerProfiles::new(
+        Box::new(helix::HelixUserLookup {
+            client: client.clone(),
+            token: token.clone(),
+        }),
+        sqlite_pool,
+        std::time::Duration::from_secs(opts.viewer_profile_cache_hours * 60 * 60),
+    )
+    .spawn(sender);
+    // The admin page sends events down the same channel as Twitch
+    let admin_sender = sender.clone();
     let twitch_websocket_client = WebsocketClient {
         session_id: None,
         token,
@@ -113,7 +267,44 @@ pub async fn run(opts: &Opts) -> eyre::Result<()> {
         http_port: opts.http_port.parse().expect("http port is required"),
     };
 
-    let frontend_api = FrontendApi::new(host_info, opts.frontend_assets.clone());
+    let mut frontend_api = FrontendApi::new(
+        host_info,
+        opts.frontend_assets.clone(),
+        opts.frontend_themes.clone(),
+        opts.theme.clone(),
+    );
+    frontend_api.asset_manager = Some(asset_manager);
+    frontend_api.leaderboards = Some(leaderboards);
+    frontend_api.ticker = Some(ticker);
+    frontend_api.backlog = backlog;
+    frontend_api.boss_battle = boss_battle;
+    frontend_api.goals = goals;
+    frontend_api.credits = credits;
+    frontend_api.event_sender = Some(admin_sender);
+
+    if let Some(engine) = &opts.tts_engine {
+        let pronunciations = match Pronunciations::load(&opts.tts_pronunciations) {
+            Ok(pronunciations) => pronunciations,
+            Err(e) => {
+                println!(
+                    "could not load pronunciations from {}, using none: {}",
+                    opts.tts_pronunciations, e
+                );
+                Pronunciations::default()
+            }
+        };
+        match engine_by_name(engine, opts.tts_voice.clone()) {
+            Ok(engine) => {
+                frontend_api.tts = Some(TtsManager::new(
+                    engine,
+                    pronunciations,
+                    opts.tts_dir.clone(),
+                    opts.tts_autoplay,
+                ))
+            }
+            Err(e) => println!("text to speech is off: {}", e),
+        }
+    }
 
     let twithc_clinet = twitch_websocket_client.clone();
 
@@ -0,0 +1,100 @@
+//! What we ask the Helix api for outside of the EventSub events.
+use std::sync::Arc;
+
+use ai_manager_service::profiles::UserLookup;
+use ai_manager_service::raiders::{ChannelInfo, ChannelLookup};
+use async_trait::async_trait;
+use messages::ViewerProfile;
+use tokio::sync::RwLock;
+use twitch_api::{helix, twitch_oauth2::UserToken, types, HelixClient};
+
+/// How many follow the channel. Needs the `moderator:read:followers` scope.
+pub async fn follower_count(
+    client: &HelixClient<'static, reqwest::Client>,
+    token: &UserToken,
+    broadcaster_id: &types::UserIdRef,
+) -> Result<Option<i64>, eyre::Report> {
+    let req = helix::channels::GetChannelFollowersRequest::broadcaster_id(broadcaster_id);
+    let response = client.req_get(req, token).await?;
+    Ok(response.total)
+}
+
+/// The channel's sub points, as counted for the sub goals on Twitch. Needs the
+/// `channel:read:subscriptions` scope.
+pub async fn sub_points(
+    client: &HelixClient<'static, reqwest::Client>,
+    token: &UserToken,
+    broadcaster_id: &types::UserIdRef,
+) -> Result<Option<i64>, eyre::Report> {
+    let req =
+        helix::subscriptions::GetBroadcasterSubscriptionsRequest::broadcaster_id(broadcaster_id);
+    let response = client.req_get(req, token).await?;
+    Ok(response.get_other("points")?)
+}
+
+/// Looks up raiding channels for the raid story.
+pub struct HelixChannelLookup {
+    pub client: HelixClient<'static, reqwest::Client>,
+    pub token: Arc<RwLock<UserToken>>,
+}
+
+#[async_trait]
+impl ChannelLookup for HelixChannelLookup {
+    async fn channel_info(&self, broadcaster_id: &str) -> anyhow::Result<ChannelInfo> {
+        let token = self.token.read().await;
+        let channel = self
+            .client
+            .get_channel_from_id(broadcaster_id, &*token)
+            .await?;
+        let user = self
+            .client
+            .get_user_from_id(broadcaster_id, &*token)
+            .await?;
+        Ok(ChannelInfo {
+            // Empty when they weren't streaming anything in particular
+            game_name: channel
+                .as_ref()
+                .map(|channel| channel.game_name.to_string())
+                .filter(|game| !game.is_empty()),
+            title: channel
+                .map(|channel| channel.title)
+                .filter(|title| !title.is_empty()),
+            profile_image_url: user.and_then(|user| user.profile_image_url),
+        })
+    }
+}
+
+/// Looks up the viewers behind events for their profiles.
+pub struct HelixUserLookup {
+    pub client: HelixClient<'static, reqwest::Client>,
+    pub token: Arc<RwLock<UserToken>>,
+}
+
+#[async_trait]
+impl UserLookup for HelixUserLookup {
+    async fn viewer_profiles(&self, user_ids: &[i64]) -> anyhow::Result<Vec<(i64, ViewerProfile)>> {
+        let ids: Vec<types::UserId> = user_ids.iter().map(|id| id.to_string().into()).collect();
+        let ids: Vec<&types::UserIdRef> = ids.iter().map(|id| id.as_ref()).collect();
+        let req = helix::users::GetUsersRequest::ids(ids.as_slice());
+        let response = self.client.req_get(req, &*self.token.read().await).await?;
+
+        let mut profiles = vec![];
+        for user in response.data {
+            let user_id = user.id.to_string().parse::<i64>()?;
+            let broadcaster_type = match user.broadcaster_type {
+                Some(types::BroadcasterType::Partner) => "partner",
+                Some(types::BroadcasterType::Affiliate) => "affiliate",
+                _ => "",
+            };
+            profiles.push((
+                user_id,
+                ViewerProfile {
+                    display_name: user.display_name.to_string(),
+                    profile_image_url: user.profile_image_url,
+                    broadcaster_type: broadcaster_type.to_string(),
+                },
+            ));
+        }
+        Ok(profiles)
+    }
+}
@@ -1,3 +1,4 @@
+pub mod helix;
 pub mod opts;
 pub mod util;
 pub mod websocket;
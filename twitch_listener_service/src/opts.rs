@@ -84,6 +84,115 @@ pub struct Opts {
 
     #[clap(long, env, hide_env = true, default_value = "frontend_api/assets")]
     pub frontend_assets: String,
+
+    /// Directory holding the overlay themes, one sub directory per theme.
+    #[clap(long, env, hide_env = true, default_value = "frontend_api/themes")]
+    pub frontend_themes: String,
+
+    /// Theme the overlay starts with. Can be switched from the admin page.
+    #[clap(long, env, hide_env = true, default_value = "default")]
+    pub theme: String,
+
+    /// Json file mapping event types and viewers to alert images, animations and sounds.
+    #[clap(long, env, hide_env = true, default_value = "alert_assets.json")]
+    pub alert_assets: String,
+
+    /// Directory the sounds and images uploaded from the admin page are kept in.
+    #[clap(long, env, hide_env = true, default_value = "uploads")]
+    pub uploads_dir: String,
+
+    /// Engine the AI stories are read out with, `espeak` or `piper`. Leave out to turn tts off.
+    #[clap(long, env, hide_env = true)]
+    pub tts_engine: Option<String>,
+
+    /// Voice for espeak, or the path to the `.onnx` model for piper.
+    #[clap(long, env, hide_env = true)]
+    pub tts_voice: Option<String>,
+
+    /// Json file mapping user names to how the tts engine should say them.
+    #[clap(long, env, hide_env = true, default_value = "pronunciations.json")]
+    pub tts_pronunciations: String,
+
+    /// Directory the rendered speech is written to.
+    #[clap(long, env, hide_env = true, default_value = "speech")]
+    pub tts_dir: String,
+
+    /// Read each story out right after its alert instead of waiting for "Play Next".
+    #[clap(long, env, hide_env = true)]
+    pub tts_autoplay: bool,
+
+    /// Where alert illustrations are generated, `openai`, `stable-diffusion` or `stub`.
+    /// Leave out to only use the configured images.
+    #[clap(long, env, hide_env = true)]
+    pub image_backend: Option<String>,
+
+    /// Url of the Stable Diffusion server, e.g. `http://localhost:7860`.
+    #[clap(long, env, hide_env = true)]
+    pub image_backend_url: Option<String>,
+
+    /// Seconds an alert waits for its illustration before going out without one.
+    #[clap(long, env, hide_env = true, default_value = "8")]
+    pub image_timeout_secs: u64,
+
+    /// Json file with the blocklist and length limits for viewer text and stories.
+    #[clap(long, env, hide_env = true, default_value = "safety.json")]
+    pub safety_config: String,
+
+    /// Json file with the rules that change prompts, sounds, themes and priority by event.
+    #[clap(long, env, hide_env = true, default_value = "rules.json")]
+    pub rules_config: String,
+
+    /// Seconds the overlay leaderboard widget shows each board before moving to the next.
+    #[clap(long, env, hide_env = true, default_value = "15")]
+    pub leaderboard_rotate_secs: u64,
+
+    /// Json file with the bosses, health and damage weights of hype train boss fights.
+    #[clap(long, env, hide_env = true, default_value = "boss.json")]
+    pub boss_config: String,
+
+    /// Json file with the follower, sub point and bits goals and their milestones.
+    #[clap(long, env, hide_env = true, default_value = "goals.json")]
+    pub goals_config: String,
+
+    /// Hours the game, title and avatar looked up for a raiding channel are reused before
+    /// asking Twitch again.
+    #[clap(long, env, hide_env = true, default_value = "24")]
+    pub raider_cache_hours: u64,
+
+    /// Hours the avatar, display name and broadcaster type looked up for a viewer are reused
+    /// before asking Twitch again.
+    #[clap(long, env, hide_env = true, default_value = "24")]
+    pub viewer_profile_cache_hours: u64,
+
+    /// How many stories are generated at once. Alerts still play in the order the events came in.
+    #[clap(long, env, hide_env = true, default_value = "4")]
+    pub ai_concurrency: usize,
+
+    /// Seconds an event waits for its story, retries included, before the neutral story is shown.
+    #[clap(long, env, hide_env = true, default_value = "30")]
+    pub ai_timeout_secs: u64,
+
+    /// Alerts waiting before we switch to short messages and combined alerts to catch up.
+    /// Full stories come back once the backlog is down to half of this.
+    #[clap(long, env, hide_env = true, default_value = "10")]
+    pub catch_up_threshold: usize,
+
+    /// Seconds a gift waits for the subscribe events of its recipients, so a gift bomb
+    /// becomes one alert.
+    #[clap(long, env, hide_env = true, default_value = "5")]
+    pub gift_window_secs: u64,
+
+    /// Hours after a follow during which the same user following again gets no alert.
+    #[clap(long, env, hide_env = true, default_value = "720")]
+    pub refollow_cooldown_hours: u64,
+
+    /// Follows within `follow_burst_window_secs` above which the rest of the wave is taken as
+    /// follow bots and shown as one muted alert.
+    #[clap(long, env, hide_env = true, default_value = "5")]
+    pub follow_burst_threshold: usize,
+
+    #[clap(long, env, hide_env = true, default_value = "10")]
+    pub follow_burst_window_secs: u64,
 }
 
 pub fn is_token(s: String) -> eyre::Result<()> {
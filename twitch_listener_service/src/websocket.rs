@@ -3,16 +3,19 @@ use std::time::Duration;
 
 use eyre::Context;
 use messages::{
-    ChannelGiftMessage, CheerEvent, FollowEvent, NewTwitchEventMessage, RaidEvent, SubscribeEvent,
-    TwitchEvent,
+    ChannelGiftMessage, CheerEvent, FollowEvent, HypeTrainEvent, NewTwitchEventMessage, RaidEvent,
+    StreamOfflineEvent, StreamOnlineEvent, SubscribeEvent, TwitchEvent, ANONYMOUS_CHEERER,
+    ANONYMOUS_VIEWER_ID,
 };
 use tokio::sync::{mpsc::UnboundedSender, RwLock};
 use tokio_tungstenite::tungstenite;
 use tracing::Instrument;
 use twitch_api::eventsub::channel::{
-    ChannelCheerV1Payload, ChannelRaidV1Payload, ChannelSubscribeV1Payload,
-    ChannelSubscriptionGiftV1Payload, ChannelSubscriptionMessageV1Payload,
+    ChannelCheerV1Payload, ChannelHypeTrainBeginV1Payload, ChannelHypeTrainEndV1Payload,
+    ChannelRaidV1Payload, ChannelSubscribeV1Payload, ChannelSubscriptionGiftV1Payload,
+    ChannelSubscriptionMessageV1Payload,
 };
+use twitch_api::eventsub::stream::{StreamOfflineV1Payload, StreamOnlineV1Payload};
 use twitch_api::twitch_oauth2::UserToken;
 use twitch_api::{
     eventsub::{
@@ -161,8 +164,9 @@ impl WebsocketClient {
             message_at: metadata.message_timestamp.as_str().into(),
             message_id: metadata.message_id.to_string(),
         };
-        self.sender.send(message).unwrap();
-        Ok(())
+        self.sender
+            .send(message)
+            .map_err(|_| eyre::eyre!("no one is listening for events"))
     }
 
     pub async fn process_welcome_message(
@@ -200,6 +204,17 @@ impl WebsocketClient {
             )
             .await?;
 
+        // Gifts, which the subscribe events of their recipients are folded into
+        self.client
+            .create_eventsub_subscription(
+                twitch_api::eventsub::channel::ChannelSubscriptionGiftV1::broadcaster_user_id(
+                    self.user_id.clone(),
+                ),
+                transport.clone(),
+                &*self.token.read().await,
+            )
+            .await?;
+
         // Create Channel Raid Subscription
         self.client
             .create_eventsub_subscription(
@@ -222,9 +237,69 @@ impl WebsocketClient {
             )
             .await?;
 
+        // Cheers, hype trains and the start and end of the stream are extras, the token may not
+        // have the scopes for them
+        self.optional_subscription(
+            twitch_api::eventsub::channel::ChannelCheerV1::broadcaster_user_id(
+                self.user_id.clone(),
+            ),
+            &transport,
+        )
+        .await;
+        self.optional_subscription(
+            twitch_api::eventsub::channel::ChannelHypeTrainBeginV1::broadcaster_user_id(
+                self.user_id.clone(),
+            ),
+            &transport,
+        )
+        .await;
+        self.optional_subscription(
+            twitch_api::eventsub::channel::ChannelHypeTrainEndV1::broadcaster_user_id(
+                self.user_id.clone(),
+            ),
+            &transport,
+        )
+        .await;
+        self.optional_subscription(
+            twitch_api::eventsub::stream::StreamOnlineV1::broadcaster_user_id(self.user_id.clone()),
+            &transport,
+        )
+        .await;
+        self.optional_subscription(
+            twitch_api::eventsub::stream::StreamOfflineV1::broadcaster_user_id(
+                self.user_id.clone(),
+            ),
+            &transport,
+        )
+        .await;
+
         tracing::info!("we are listening");
         Ok(())
     }
+
+    /// Subscribes to an event the alerts can do without, logging instead of failing when
+    /// Twitch says no, like when the token is missing its scope.
+    async fn optional_subscription<E: twitch_api::eventsub::EventSubscription + Send>(
+        &self,
+        subscription: E,
+        transport: &twitch_api::eventsub::Transport,
+    ) {
+        let created = self
+            .client
+            .create_eventsub_subscription(
+                subscription,
+                transport.clone(),
+                &*self.token.read().await,
+            )
+            .await;
+        if let Err(e) = created {
+            println!(
+                "Could not subscribe to {}, going on without it: {}",
+                E::EVENT_TYPE,
+                e
+            );
+        }
+    }
 }
 
 // Creates a new TwitchEvent enum from the payload and metadata
@@ -233,12 +308,17 @@ fn new_twitch_event(payload: Event) -> Result<TwitchEvent, eyre::Report> {
         Event::ChannelFollowV2(Payload {
             message:
                 Message::Notification(ChannelFollowV2Payload {
-                    user_name, user_id, ..
+                    user_name,
+                    user_id,
+                    broadcaster_user_id,
+                    ..
                 }),
             ..
         }) => Ok(TwitchEvent::ChannelFollow(FollowEvent {
             user_name: user_name.to_string(),
             user_id: user_id.to_string().parse::<i64>()?,
+            broadcaster_user_id: broadcaster_user_id.to_string().parse::<i64>()?,
+            profile: None,
         })),
         Event::ChannelSubscribeV1(Payload {
             message:
@@ -265,6 +345,7 @@ fn new_twitch_event(payload: Event) -> Result<TwitchEvent, eyre::Report> {
                 cumulative_months: 1,
                 duration_months: 1,
                 message: "".to_string(),
+                profile: None,
             }))
         }
         Event::ChannelRaidV1(Payload {
@@ -317,6 +398,7 @@ fn new_twitch_event(payload: Event) -> Result<TwitchEvent, eyre::Report> {
             user_id: braid_optional_to_string_optional(user_id),
             user_login: braid_optional_to_string_optional(user_login),
             user_name: braid_optional_to_string_optional(user_name),
+            recipients: vec![],
         })),
 
         Event::ChannelSubscriptionMessageV1(Payload {
@@ -348,6 +430,7 @@ fn new_twitch_event(payload: Event) -> Result<TwitchEvent, eyre::Report> {
                 //TODO: deal with emotes
                 message: message.text,
                 streak_months,
+                profile: None,
             }))
         }
 
@@ -362,10 +445,16 @@ fn new_twitch_event(payload: Event) -> Result<TwitchEvent, eyre::Report> {
                 }),
             ..
         }) => Ok(TwitchEvent::ChannelCheer(CheerEvent {
-            user_name: user_name.unwrap().to_string(),
-            user_id: user_id.unwrap().to_string().parse::<i64>()?,
+            // Anonymous cheers come without the viewer
+            user_name: user_name
+                .map_or_else(|| ANONYMOUS_CHEERER.to_string(), |name| name.to_string()),
+            user_id: match user_id {
+                Some(user_id) => user_id.to_string().parse::<i64>()?,
+                None => ANONYMOUS_VIEWER_ID,
+            },
             bits,
             message: message.to_string(),
+            profile: None,
         })),
 
         Event::ChannelPointsCustomRewardAddV1(Payload {
@@ -399,17 +488,28 @@ fn new_twitch_event(payload: Event) -> Result<TwitchEvent, eyre::Report> {
             "ChannelPointsCustomRewardRedemptionUpdateV1 is not supported"
         )),
         Event::ChannelHypeTrainBeginV1(Payload {
-            message: Message::Notification(..),
+            message:
+                Message::Notification(ChannelHypeTrainBeginV1Payload {
+                    level, total, goal, ..
+                }),
             ..
-        }) => Err(eyre::eyre!("ChannelHypeTrainBeginV1 is not supported")),
+        }) => Ok(TwitchEvent::ChannelHypeTrainBegin(HypeTrainEvent {
+            level,
+            total,
+            goal: Some(goal),
+        })),
         Event::ChannelHypeTrainProgressV1(Payload {
             message: Message::Notification(..),
             ..
         }) => Err(eyre::eyre!("ChannelHypeTrainProgressV1 is not supported")),
         Event::ChannelHypeTrainEndV1(Payload {
-            message: Message::Notification(..),
+            message: Message::Notification(ChannelHypeTrainEndV1Payload { level, total, .. }),
             ..
-        }) => Err(eyre::eyre!("ChannelHypeTrainEndV1 is not supported")),
+        }) => Ok(TwitchEvent::ChannelHypeTrainEnd(HypeTrainEvent {
+            level,
+            total,
+            goal: None,
+        })),
         Event::ChannelPollBeginV1(Payload {
             message: Message::Notification(..),
             ..
@@ -451,6 +551,26 @@ fn new_twitch_event(payload: Event) -> Result<TwitchEvent, eyre::Report> {
             ..
         }) => Err(eyre::eyre!("ChannelUpdateV1 is not supported")),
 
+        Event::StreamOnlineV1(Payload {
+            message:
+                Message::Notification(StreamOnlineV1Payload {
+                    broadcaster_user_name,
+                    ..
+                }),
+            ..
+        }) => Ok(TwitchEvent::StreamOnline(StreamOnlineEvent {
+            broadcaster_user_name: broadcaster_user_name.to_string(),
+        })),
+        Event::StreamOfflineV1(Payload {
+            message:
+                Message::Notification(StreamOfflineV1Payload {
+                    broadcaster_user_name,
+                    ..
+                }),
+            ..
+        }) => Ok(TwitchEvent::StreamOffline(StreamOfflineEvent {
+            broadcaster_user_name: broadcaster_user_name.to_string(),
+        })),
         _ => todo!(),
     }
 }
@@ -471,3 +591,93 @@ fn braid_optional_to_string_optional<T: ToString>(input: Option<T>) -> Option<St
         Some(thing) => Some(thing.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use twitch_api::eventsub::event::websocket::EventsubWebsocketData;
+
+    use super::*;
+
+    fn notification(subscription_type: &str, event: &str) -> Event {
+        let json = format!(
+            r#"{{
+                "metadata": {{
+                    "message_id": "befa7b53-d79d-478f-86b9-120f112b044e",
+                    "message_type": "notification",
+                    "message_timestamp": "2022-11-16T10:11:12.464757833Z",
+                    "subscription_type": "{0}",
+                    "subscription_version": "1"
+                }},
+                "payload": {{
+                    "subscription": {{
+                        "id": "f1c2a387-161a-49f9-a165-0f21d7a4e1c4",
+                        "status": "enabled",
+                        "type": "{0}",
+                        "version": "1",
+                        "cost": 0,
+                        "condition": {{ "broadcaster_user_id": "1337" }},
+                        "transport": {{
+                            "method": "websocket",
+                            "session_id": "AQoQexAWVYKSTIu4ec_2VAxyuhAB"
+                        }},
+                        "created_at": "2022-11-16T10:11:12.464757833Z"
+                    }},
+                    "event": {1}
+                }}
+            }}"#,
+            subscription_type, event
+        );
+        match Event::parse_websocket(&json).unwrap() {
+            EventsubWebsocketData::Notification { payload, .. } => payload,
+            other => panic!("not a notification: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn anonymous_cheers_come_from_the_anonymous_viewer() {
+        let event = notification(
+            "channel.cheer",
+            r#"{
+                "is_anonymous": true,
+                "user_id": null,
+                "user_login": null,
+                "user_name": null,
+                "broadcaster_user_id": "1337",
+                "broadcaster_user_login": "null",
+                "broadcaster_user_name": "Null",
+                "message": "pogchamp",
+                "bits": 1000
+            }"#,
+        );
+
+        let TwitchEvent::ChannelCheer(cheer) = new_twitch_event(event).unwrap() else {
+            panic!("not a cheer");
+        };
+        assert!(cheer.is_anonymous());
+        assert_eq!(cheer.user_name, ANONYMOUS_CHEERER);
+        assert_eq!(cheer.bits, 1000);
+    }
+
+    #[test]
+    fn cheers_keep_the_viewer() {
+        let event = notification(
+            "channel.cheer",
+            r#"{
+                "is_anonymous": false,
+                "user_id": "1234",
+                "user_login": "sam",
+                "user_name": "Sam",
+                "broadcaster_user_id": "1337",
+                "broadcaster_user_login": "null",
+                "broadcaster_user_name": "Null",
+                "message": "pogchamp",
+                "bits": 100
+            }"#,
+        );
+
+        let TwitchEvent::ChannelCheer(cheer) = new_twitch_event(event).unwrap() else {
+            panic!("not a cheer");
+        };
+        assert_eq!((cheer.user_id, cheer.user_name.as_str()), (1234, "Sam"));
+    }
+}